@@ -0,0 +1,194 @@
+// Copyright (c) 2022 Espresso Systems (espressosys.com)
+// This file is part of the Configurable Asset Privacy for Ethereum (CAPE) library.
+
+// This program is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+// You should have received a copy of the GNU General Public License along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+use crate::DEFAULT_PORT;
+use dirs::data_local_dir;
+use serde::Deserialize;
+use std::io::{Error, ErrorKind};
+use std::{
+    env,
+    path::{Path, PathBuf},
+};
+use structopt::StructOpt;
+use surf::Url;
+use tide::log::LevelFilter;
+
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "CAPE Address Book",
+    about = "Maintains a persistent mapping from user addresses to user public keys"
+)]
+pub struct AddressBookOptions {
+    /// Path to a TOML configuration file.
+    ///
+    /// The file may set `bind_address`, `port`, `tls_cert`, `tls_key` and `log_level`. Options
+    /// given on the command line or in the environment take precedence over the file.
+    #[structopt(long = "config", short = "c", env = "ADDRESS_BOOK_CONFIG")]
+    pub config: Option<PathBuf>,
+
+    /// Path to persistence files.
+    ///
+    /// Persistence files will be nested under the specified directory
+    #[structopt(long = "store_path", short = "s", default_value = "")]
+    pub store_path: String,
+
+    /// Flag to reset persisted state.
+    #[structopt(long = "reset_store_state")]
+    pub reset_store_state: bool,
+
+    /// Allow anyone to list the registered addresses.
+    ///
+    /// Off by default, so that the address directory cannot be enumerated.
+    #[structopt(long = "enable_listing")]
+    pub enable_listing: bool,
+
+    /// Address of the interface to listen on. Defaults to 0.0.0.0, every IPv4 interface.
+    #[structopt(long = "bind_address", env = "ADDRESS_BOOK_BIND_ADDRESS")]
+    pub bind_address: Option<String>,
+
+    /// Port to listen on. Defaults to 50078.
+    #[structopt(long = "port", env = "PORT")]
+    pub port: Option<u16>,
+
+    /// Path to a PEM file with the TLS certificate chain.
+    ///
+    /// If given, along with --tls_key, the address book serves HTTPS instead of HTTP.
+    #[structopt(long = "tls_cert", env = "ADDRESS_BOOK_TLS_CERT")]
+    pub tls_cert: Option<PathBuf>,
+
+    /// Path to a PEM file with the TLS private key.
+    #[structopt(long = "tls_key", env = "ADDRESS_BOOK_TLS_KEY")]
+    pub tls_key: Option<PathBuf>,
+
+    /// One of off, error, warn, info, debug and trace. Defaults to info.
+    #[structopt(long = "log_level", env = "ADDRESS_BOOK_LOG_LEVEL")]
+    pub log_level: Option<LevelFilter>,
+}
+
+/// The options which can be set in the configuration file.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ConfigFile {
+    bind_address: Option<String>,
+    port: Option<u16>,
+    tls_cert: Option<PathBuf>,
+    tls_key: Option<PathBuf>,
+    log_level: Option<String>,
+}
+
+fn default_data_path() -> PathBuf {
+    let mut data_dir = data_local_dir()
+        .unwrap_or_else(|| env::current_dir().unwrap_or_else(|_| PathBuf::from("./")));
+    data_dir.push("espresso");
+    data_dir.push("cape_address_book");
+    data_dir
+}
+
+impl AddressBookOptions {
+    /// Fill in the options which were not given on the command line or in the environment from
+    /// the configuration file, if there is one.
+    pub fn with_config_file(mut self) -> std::io::Result<Self> {
+        let path = match &self.config {
+            Some(path) => path,
+            None => return Ok(self),
+        };
+        let contents = std::fs::read_to_string(path)?;
+        let config: ConfigFile = toml::from_str(&contents).map_err(|err| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("Unable to parse {:?}: {}", path, err),
+            )
+        })?;
+        let log_level = match config.log_level {
+            Some(level) => Some(level.parse::<LevelFilter>().map_err(|err| {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    format!("Invalid log_level {:?} in {:?}: {}", level, path, err),
+                )
+            })?),
+            None => None,
+        };
+        self.bind_address = self.bind_address.or(config.bind_address);
+        self.port = self.port.or(config.port);
+        self.tls_cert = self.tls_cert.or(config.tls_cert);
+        self.tls_key = self.tls_key.or(config.tls_key);
+        self.log_level = self.log_level.or(log_level);
+        Ok(self)
+    }
+
+    /// Returns the path to stored persistence files.
+    pub fn store_path(&self) -> PathBuf {
+        if self.store_path.is_empty() {
+            let mut default_store_path = default_data_path();
+            default_store_path.push("store");
+            default_store_path
+        } else {
+            PathBuf::from(&self.store_path)
+        }
+    }
+
+    pub fn reset_store_state(&self) -> bool {
+        self.reset_store_state
+    }
+
+    pub fn enable_listing(&self) -> bool {
+        self.enable_listing
+    }
+
+    pub fn bind_address(&self) -> &str {
+        self.bind_address.as_deref().unwrap_or("0.0.0.0")
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port.unwrap_or(DEFAULT_PORT)
+    }
+
+    /// The address to listen on, with IPv6 addresses in brackets.
+    pub fn listen_address(&self) -> String {
+        format!("{}:{}", bracketed(self.bind_address()), self.port())
+    }
+
+    /// The TLS certificate chain and private key, if the address book serves HTTPS.
+    ///
+    /// Fails if only one of them is given.
+    pub fn tls(&self) -> std::io::Result<Option<(&Path, &Path)>> {
+        match (&self.tls_cert, &self.tls_key) {
+            (Some(cert), Some(key)) => Ok(Some((cert, key))),
+            (None, None) => Ok(None),
+            _ => Err(Error::new(
+                ErrorKind::InvalidInput,
+                "tls_cert and tls_key must be given together",
+            )),
+        }
+    }
+
+    pub fn log_level(&self) -> LevelFilter {
+        self.log_level.unwrap_or(LevelFilter::Info)
+    }
+
+    /// The base URL at which clients on the same host can reach the address book.
+    pub fn base_url(&self) -> Url {
+        let scheme = if self.tls_cert.is_some() {
+            "https"
+        } else {
+            "http"
+        };
+        let host = match self.bind_address() {
+            "0.0.0.0" | "::" => "localhost",
+            address => address,
+        };
+        Url::parse(&format!("{}://{}:{}", scheme, bracketed(host), self.port())).unwrap()
+    }
+}
+
+fn bracketed(host: &str) -> String {
+    if host.contains(':') {
+        format!("[{}]", host)
+    } else {
+        String::from(host)
+    }
+}
@@ -7,71 +7,404 @@
 
 #[warn(unused_imports)]
 use async_std::{
-    sync::{Arc, RwLock},
+    sync::{Arc, Mutex, RwLock},
     task::{sleep, spawn, JoinHandle},
 };
-use jf_cap::keys::{UserAddress, UserPubKey};
+use atomic_store::PersistenceError;
+use configuration::AddressBookOptions;
+use jf_cap::keys::{AuditorPubKey, FreezerPubKey, UserAddress, UserKeyPair, UserPubKey};
 use jf_cap::Signature;
-use once_cell::sync::Lazy;
+use persistence::{AddressBookStore, LogEntry};
 use std::collections::HashMap;
-use std::time::Duration;
-use tide::{log::LevelFilter, prelude::*, StatusCode};
+use std::sync::Once;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use surf::Url;
+use tide::{prelude::*, StatusCode};
+use tide_rustls::TlsListener;
 
+pub mod configuration;
+pub mod persistence;
 pub mod signal;
 
 pub const DEFAULT_PORT: u16 = 50078u16;
 const ADDRESS_BOOK_STARTUP_RETRIES: usize = 8;
+/// Maximum number of addresses in a batch lookup, or in a page of the listing.
+pub const MAX_BATCH_SIZE: usize = 1000;
+/// Maximum length of an alias, in bytes.
+pub const MAX_ALIAS_LEN: usize = 64;
 
-pub static mut LOG_LEVEL: LevelFilter = LevelFilter::Info;
+/// Ensures that only one logger is started, with the log level of the first server.
+static LOGGING: Once = Once::new();
 
-/// Runs one and only one logger.
+/// A request to publish a public key, signed by its owner.
 ///
-/// Accessing `LOG_LEVEL` is considered unsafe since it is a static mutable
-/// variable, but we need this to ensure that only one logger is running.
-static LOGGING: Lazy<()> = Lazy::new(|| unsafe {
-    tide::log::with_level(LOG_LEVEL);
-});
-
+/// The signature covers the key and `nonce` ([InsertPubKey::message]). The nonce of each request
+/// for an address must be greater than that of the last one accepted, so that an old request
+/// cannot be replayed to roll the key back. A timestamp ([timestamp_nonce]) makes a good nonce.
 #[derive(Debug, Deserialize, Serialize)]
 pub struct InsertPubKey {
     pub pub_key_bytes: Vec<u8>,
+    pub nonce: u64,
     pub sig: Signature,
 }
 
-#[derive(Clone, Default)]
-struct ServerState {
-    map: Arc<RwLock<HashMap<UserAddress, UserPubKey>>>,
+impl InsertPubKey {
+    pub fn new(user_key: &UserKeyPair, nonce: u64) -> Self {
+        let pub_key_bytes = bincode::serialize(&user_key.pub_key()).unwrap();
+        let sig = user_key.sign(&Self::message(&pub_key_bytes, nonce));
+        Self {
+            pub_key_bytes,
+            nonce,
+            sig,
+        }
+    }
+
+    /// The signed message: a domain separator, followed by the serialized key and the nonce as a
+    /// little-endian u64, so that it cannot be confused with the message of another request.
+    pub fn message(pub_key_bytes: &[u8], nonce: u64) -> Vec<u8> {
+        [
+            b"CAPE pub key".as_ref(),
+            pub_key_bytes,
+            &nonce.to_le_bytes(),
+        ]
+        .concat()
+    }
+}
+
+/// Milliseconds since the Unix epoch, for use as a nonce.
+pub fn timestamp_nonce() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+}
+
+/// A public key published for an address.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct KeyVersion {
+    pub pub_key: UserPubKey,
+    /// Nonce of the request which published the key.
+    pub nonce: u64,
+    /// When the address book accepted the key, in seconds since the Unix epoch.
+    pub timestamp: u64,
+}
+
+/// A viewing or freezing public key, for use in asset policies.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq, Hash)]
+pub enum PolicyKey {
+    Viewing(AuditorPubKey),
+    Freezing(FreezerPubKey),
+}
+
+/// A request to publish a viewing or freezing key for an address, signed by the key of the address.
+///
+/// Viewing and freezing keys cannot sign, so the registration is signed by a user key instead,
+/// and does not prove possession of the policy key. Any address can therefore claim any policy
+/// key, and every address which has claimed a key is listed as a claimant of it, so that an early
+/// claim cannot lock out the real holder of the key. As with [InsertPubKey], the nonce of each
+/// request for an address must be greater than that of the last policy key registered for it.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct InsertPolicyKey {
+    pub policy_key: PolicyKey,
+    pub pub_key_bytes: Vec<u8>,
+    pub nonce: u64,
+    pub sig: Signature,
+}
+
+impl InsertPolicyKey {
+    pub fn new(user_key: &UserKeyPair, policy_key: PolicyKey, nonce: u64) -> Self {
+        let pub_key_bytes = bincode::serialize(&user_key.pub_key()).unwrap();
+        let sig = user_key.sign(&Self::message(&policy_key, &pub_key_bytes, nonce));
+        Self {
+            policy_key,
+            pub_key_bytes,
+            nonce,
+            sig,
+        }
+    }
+
+    /// The signed message: a domain separator, followed by the serialized policy key, user key and
+    /// nonce.
+    pub fn message(policy_key: &PolicyKey, pub_key_bytes: &[u8], nonce: u64) -> Vec<u8> {
+        [
+            b"CAPE policy key".as_ref(),
+            &bincode::serialize(&(policy_key, pub_key_bytes, nonce)).unwrap(),
+        ]
+        .concat()
+    }
+}
+
+/// A viewing or freezing key published for an address.
+///
+/// This is only a claim by `owner`: nothing shows that `owner` holds the key, and other addresses
+/// may claim the same key.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct PolicyKeyVersion {
+    pub policy_key: PolicyKey,
+    /// The address which published the key.
+    pub owner: UserAddress,
+    /// Nonce of the request which published the key.
+    pub nonce: u64,
+    /// When the address book accepted the key, in seconds since the Unix epoch.
+    pub timestamp: u64,
+}
+
+/// Check that `alias` is a valid alias.
+///
+/// An alias is 1 to [MAX_ALIAS_LEN] lowercase ASCII letters, digits and the characters `.`, `_`,
+/// `-`, `+` and `@`, starting with a letter or a digit, such as `alice@example.com`. Since it
+/// cannot contain `~`, an alias is never mistaken for an `ADDR~...` address.
+pub fn check_alias(alias: &str) -> Result<(), String> {
+    if alias.is_empty() || alias.len() > MAX_ALIAS_LEN {
+        return Err(format!(
+            "an alias must have between 1 and {} characters",
+            MAX_ALIAS_LEN
+        ));
+    }
+    if !alias.starts_with(|c: char| c.is_ascii_lowercase() || c.is_ascii_digit()) {
+        return Err(String::from(
+            "an alias must start with a lowercase letter or a digit",
+        ));
+    }
+    if let Some(c) = alias
+        .chars()
+        .find(|c| !(c.is_ascii_lowercase() || c.is_ascii_digit() || ".-_+@".contains(*c)))
+    {
+        return Err(format!("an alias cannot contain {:?}", c));
+    }
+    Ok(())
+}
+
+/// What an [AliasRequest] does to its alias.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub enum AliasAction {
+    /// Bind an alias which has no owner to the address of the signer, which must have a published
+    /// key.
+    Register,
+    /// Bind an alias owned by the signer to another address, which must have a published key.
+    Transfer(UserAddress),
+    /// Unbind an alias owned by the signer, so that anyone can register it.
+    Release,
+}
+
+/// A request to register, transfer or release an alias, signed by the key of its owner.
+///
+/// As with [InsertPubKey], the nonce of each request for an alias must be greater than that of
+/// the last one accepted for it, even after the alias is released, so that old requests cannot
+/// be replayed.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct AliasRequest {
+    pub alias: String,
+    pub action: AliasAction,
+    pub pub_key_bytes: Vec<u8>,
+    pub nonce: u64,
+    pub sig: Signature,
+}
+
+impl AliasRequest {
+    pub fn new(user_key: &UserKeyPair, alias: &str, action: AliasAction, nonce: u64) -> Self {
+        let pub_key_bytes = bincode::serialize(&user_key.pub_key()).unwrap();
+        let sig = user_key.sign(&Self::message(alias, &action, &pub_key_bytes, nonce));
+        Self {
+            alias: String::from(alias),
+            action,
+            pub_key_bytes,
+            nonce,
+            sig,
+        }
+    }
+
+    /// The signed message: a domain separator, followed by the serialized alias, action, key and
+    /// nonce, so that it cannot be confused with the message of an [InsertPubKey].
+    pub fn message(alias: &str, action: &AliasAction, pub_key_bytes: &[u8], nonce: u64) -> Vec<u8> {
+        [
+            b"CAPE alias".as_ref(),
+            &bincode::serialize(&(alias, action, pub_key_bytes, nonce)).unwrap(),
+        ]
+        .concat()
+    }
+}
+
+/// The current state of an alias.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct AliasVersion {
+    pub alias: String,
+    /// The address the alias is bound to, or `None` if it has been released.
+    pub owner: Option<UserAddress>,
+    /// Nonce of the request which last changed the alias.
+    pub nonce: u64,
+}
+
+/// A request for a page of the registered addresses.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ListAddresses {
+    /// Index of the first address, in the order in which they were registered.
+    pub first: usize,
+    /// At most this many addresses are returned, and never more than [MAX_BATCH_SIZE].
+    pub max_count: usize,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct AddressPage {
+    pub addresses: Vec<UserAddress>,
+    /// Index from which to continue for the next page.
+    pub next: usize,
+    /// Number of addresses registered so far.
+    pub total: usize,
+}
+
+/// The contents of the address book, as rebuilt from its log.
+#[derive(Default)]
+pub struct AddressBook {
+    // every key published for each address, oldest first
+    history: HashMap<UserAddress, Vec<KeyVersion>>,
+    // addresses in the order in which they were first registered, for listing
+    addresses: Vec<UserAddress>,
+    // every alias which has ever been registered, including released ones, whose nonces are still
+    // needed to reject replays
+    aliases: HashMap<String, AliasVersion>,
+    // the viewing and freezing keys published for each address, oldest first
+    policy_keys: HashMap<UserAddress, Vec<PolicyKeyVersion>>,
+    // the addresses which have published each viewing and freezing key, in the order they did
+    policy_key_claimants: HashMap<PolicyKey, Vec<UserAddress>>,
+}
+
+impl AddressBook {
+    /// Replay the log in `store`.
+    pub fn load(store: &AddressBookStore) -> Result<Self, PersistenceError> {
+        let mut book = Self::default();
+        for entry in store.entries() {
+            book.apply(entry?);
+        }
+        Ok(book)
+    }
+
+    pub fn apply(&mut self, entry: LogEntry) {
+        match entry {
+            LogEntry::InsertPubKey(version) => {
+                let address = version.pub_key.address();
+                if !self.history.contains_key(&address) {
+                    self.addresses.push(address.clone());
+                }
+                self.history.entry(address).or_default().push(version);
+            }
+            LogEntry::SetAlias(version) => {
+                self.aliases.insert(version.alias.clone(), version);
+            }
+            LogEntry::InsertPolicyKey(version) => {
+                self.policy_key_claimants
+                    .entry(version.policy_key.clone())
+                    .or_default()
+                    .push(version.owner.clone());
+                self.policy_keys
+                    .entry(version.owner.clone())
+                    .or_default()
+                    .push(version);
+            }
+        }
+    }
+
+    /// Every key published for `address`, oldest first.
+    pub fn history(&self, address: &UserAddress) -> &[KeyVersion] {
+        self.history
+            .get(address)
+            .map_or(&[], |history| history.as_slice())
+    }
+
+    /// The current key of `address`.
+    pub fn pub_key(&self, address: &UserAddress) -> Option<&UserPubKey> {
+        self.history(address).last().map(|version| &version.pub_key)
+    }
+
+    pub fn addresses(&self) -> &[UserAddress] {
+        &self.addresses
+    }
+
+    /// The current state of `alias`, if it has ever been registered.
+    pub fn alias(&self, alias: &str) -> Option<&AliasVersion> {
+        self.aliases.get(alias)
+    }
+
+    /// The viewing and freezing keys published for `address`, oldest first.
+    ///
+    /// These are the keys `address` signed for, but it need not hold them.
+    pub fn policy_keys(&self, address: &UserAddress) -> &[PolicyKeyVersion] {
+        self.policy_keys
+            .get(address)
+            .map_or(&[], |versions| versions.as_slice())
+    }
+
+    /// The addresses which have published `policy_key`, in the order they did.
+    ///
+    /// Publishing a policy key does not prove possession of it, so this list is unauthenticated:
+    /// it must not be taken to say which address holds the key. It is kept apart from the user key
+    /// lookups, which are authenticated.
+    pub fn policy_key_claimants(&self, policy_key: &PolicyKey) -> &[UserAddress] {
+        self.policy_key_claimants
+            .get(policy_key)
+            .map_or(&[], |claimants| claimants.as_slice())
+    }
+
+    /// The address `alias` is bound to.
+    pub fn resolve_alias(&self, alias: &str) -> Option<&UserAddress> {
+        self.alias(alias)?.owner.as_ref()
+    }
 }
 
-pub fn address_book_port() -> String {
-    std::env::var("PORT").unwrap_or_else(|_| DEFAULT_PORT.to_string())
+#[derive(Clone)]
+struct ServerState {
+    book: Arc<RwLock<AddressBook>>,
+    // Changes are serialized by this lock, so that they are logged in the order in which they are
+    // applied to `book`.
+    store: Arc<Mutex<AddressBookStore>>,
+    // whether the registered addresses can be listed
+    enable_listing: bool,
 }
 
+/// Start the web server, with the contents of `store`. Changes are persisted to `store`.
+///
+/// The server listens on the address and port in `opt`, over TLS if `opt` has a certificate and
+/// key. Unless listing is enabled in `opt`, requests to list the registered addresses fail with
+/// StatusCode::Forbidden.
 pub async fn init_web_server(
-    log_level: LevelFilter,
+    opt: &AddressBookOptions,
+    store: AddressBookStore,
 ) -> std::io::Result<JoinHandle<std::io::Result<()>>> {
-    // Accessing `LOG_LEVEL` is considered unsafe since it is a static mutable
-    // variable, but we need this to ensure that only one logger is running.
-    unsafe {
-        LOG_LEVEL = log_level;
-    }
-    Lazy::force(&LOGGING);
-    let mut app = tide::with_state(ServerState::default());
+    let tls = opt.tls()?;
+    LOGGING.call_once(|| tide::log::with_level(opt.log_level()));
+    let book = AddressBook::load(&store)
+        .map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err.to_string()))?;
+    let mut app = tide::with_state(ServerState {
+        book: Arc::new(RwLock::new(book)),
+        store: Arc::new(Mutex::new(store)),
+        enable_listing: opt.enable_listing(),
+    });
     app.at("/insert_pubkey").post(insert_pubkey);
     app.at("/request_pubkey").post(request_pubkey);
-    let address = format!("0.0.0.0:{}", address_book_port());
-    Ok(spawn(app.listen(address)))
+    app.at("/request_pubkeys").post(request_pubkeys);
+    app.at("/request_pubkey_history")
+        .post(request_pubkey_history);
+    app.at("/list_addresses").post(list_addresses);
+    app.at("/update_alias").post(update_alias);
+    app.at("/resolve_alias").post(resolve_alias);
+    app.at("/insert_policy_key").post(insert_policy_key);
+    app.at("/request_policy_keys").post(request_policy_keys);
+    app.at("/request_policy_key_claimants")
+        .post(request_policy_key_claimants);
+    let address = opt.listen_address();
+    match tls {
+        Some((cert, key)) => Ok(spawn(
+            app.listen(TlsListener::build().addrs(address).cert(cert).key(key)),
+        )),
+        None => Ok(spawn(app.listen(address))),
+    }
 }
 
-pub async fn wait_for_server() {
-    // Wait for the server to come up and start serving.
+/// Wait for the server at `base_url` to come up and start serving.
+pub async fn wait_for_server(base_url: &Url) {
     let mut backoff = Duration::from_millis(100);
     for _ in 0..ADDRESS_BOOK_STARTUP_RETRIES {
-        if surf::connect(format!("http://localhost:{}", address_book_port()))
-            .send()
-            .await
-            .is_ok()
-        {
+        if surf::connect(base_url.as_str()).send().await.is_ok() {
             return;
         }
         sleep(backoff).await;
@@ -83,40 +416,303 @@ pub async fn wait_for_server() {
 /// Lookup a user public key from a signed public key address. Fail with
 /// tide::StatusCode::BadRequest if key deserialization or the signature check
 /// fail.
-fn verify_sig_and_get_pub_key(insert_request: InsertPubKey) -> Result<UserPubKey, tide::Error> {
-    let pub_key: UserPubKey = bincode::deserialize(&insert_request.pub_key_bytes)
+fn verify_sig_and_get_pub_key(insert_request: &InsertPubKey) -> Result<UserPubKey, tide::Error> {
+    let message = InsertPubKey::message(&insert_request.pub_key_bytes, insert_request.nonce);
+    verify_sig(&insert_request.pub_key_bytes, &message, &insert_request.sig)
+}
+
+/// Deserialize a user public key and check its signature of `message`. Fail with
+/// tide::StatusCode::BadRequest if either fails.
+fn verify_sig(
+    pub_key_bytes: &[u8],
+    message: &[u8],
+    sig: &Signature,
+) -> Result<UserPubKey, tide::Error> {
+    let pub_key: UserPubKey = bincode::deserialize(pub_key_bytes)
         .map_err(|e| tide::Error::new(tide::StatusCode::BadRequest, e))?;
     pub_key
-        .verify_sig(&insert_request.pub_key_bytes, &insert_request.sig)
+        .verify_sig(message, sig)
         .map_err(|e| tide::Error::new(tide::StatusCode::BadRequest, e))?;
     Ok(pub_key)
 }
 
 /// Insert or update the public key at the given address.
+///
+/// Fail with StatusCode::Conflict if the nonce is not greater than that of the current key for
+/// the address.
 async fn insert_pubkey(mut req: tide::Request<ServerState>) -> Result<tide::Response, tide::Error> {
     let insert_request: InsertPubKey = net::server::request_body(&mut req).await?;
-    let pub_key = verify_sig_and_get_pub_key(insert_request)?;
-    let mut hash_map = req.state().map.write().await;
-    hash_map.insert(pub_key.address(), pub_key.clone());
+    let pub_key = verify_sig_and_get_pub_key(&insert_request)?;
+    let mut store = req.state().store.lock().await;
+    let mut book = req.state().book.write().await;
+    if let Some(current) = book.history(&pub_key.address()).last() {
+        if insert_request.nonce <= current.nonce {
+            return Err(tide::Error::from_str(
+                StatusCode::Conflict,
+                format!(
+                    "nonce {} is not greater than {}, the nonce of the current key",
+                    insert_request.nonce, current.nonce
+                ),
+            ));
+        }
+    }
+    let entry = LogEntry::InsertPubKey(KeyVersion {
+        pub_key,
+        nonce: insert_request.nonce,
+        timestamp: timestamp_nonce() / 1000,
+    });
+    store
+        .append(&entry)
+        .map_err(|e| tide::Error::from_str(StatusCode::InternalServerError, e.to_string()))?;
+    book.apply(entry);
     Ok(tide::Response::new(StatusCode::Ok))
 }
 
+fn bincode_response<T: Serialize>(value: &T) -> tide::Response {
+    tide::Response::builder(StatusCode::Ok)
+        .body(bincode::serialize(value).unwrap())
+        .content_type(tide::http::mime::BYTE_STREAM)
+        .build()
+}
+
 /// Fetch the public key for the given address. If not found, return
 /// StatusCode::NotFound.
 async fn request_pubkey(
     mut req: tide::Request<ServerState>,
 ) -> Result<tide::Response, tide::Error> {
     let address: UserAddress = net::server::request_body(&mut req).await?;
-    let hash_map = req.state().map.read().await;
-    match hash_map.get(&address) {
-        Some(value) => {
-            let bytes = bincode::serialize(value).unwrap();
-            let response = tide::Response::builder(StatusCode::Ok)
-                .body(bytes)
-                .content_type(tide::http::mime::BYTE_STREAM)
-                .build();
-            Ok(response)
-        }
+    let book = req.state().book.read().await;
+    match book.pub_key(&address) {
+        Some(value) => Ok(bincode_response(value)),
         _ => Ok(tide::Response::new(StatusCode::NotFound)),
     }
 }
+
+/// Fetch the public keys for a list of at most [MAX_BATCH_SIZE] addresses, as a bincode
+/// `Vec<Option<UserPubKey>>` in the same order, with `None` for addresses which are not found.
+async fn request_pubkeys(
+    mut req: tide::Request<ServerState>,
+) -> Result<tide::Response, tide::Error> {
+    let addresses: Vec<UserAddress> = net::server::request_body(&mut req).await?;
+    if addresses.len() > MAX_BATCH_SIZE {
+        return Err(tide::Error::from_str(
+            StatusCode::BadRequest,
+            format!(
+                "at most {} addresses can be requested at once",
+                MAX_BATCH_SIZE
+            ),
+        ));
+    }
+    let book = req.state().book.read().await;
+    let pub_keys: Vec<Option<&UserPubKey>> = addresses
+        .iter()
+        .map(|address| book.pub_key(address))
+        .collect();
+    Ok(bincode_response(&pub_keys))
+}
+
+/// Fetch every public key published for the given address, oldest first, as a bincode
+/// `Vec<KeyVersion>`. If none was, return StatusCode::NotFound.
+async fn request_pubkey_history(
+    mut req: tide::Request<ServerState>,
+) -> Result<tide::Response, tide::Error> {
+    let address: UserAddress = net::server::request_body(&mut req).await?;
+    let book = req.state().book.read().await;
+    match book.history(&address) {
+        [] => Ok(tide::Response::new(StatusCode::NotFound)),
+        history => Ok(bincode_response(&history)),
+    }
+}
+
+/// Fetch a page of the registered addresses, as a bincode [AddressPage]. Fail with
+/// StatusCode::Forbidden unless listing is enabled.
+async fn list_addresses(
+    mut req: tide::Request<ServerState>,
+) -> Result<tide::Response, tide::Error> {
+    if !req.state().enable_listing {
+        return Err(tide::Error::from_str(
+            StatusCode::Forbidden,
+            "listing addresses is disabled",
+        ));
+    }
+    let request: ListAddresses = net::server::request_body(&mut req).await?;
+    let book = req.state().book.read().await;
+    let total = book.addresses().len();
+    let first = std::cmp::min(request.first, total);
+    let next = std::cmp::min(
+        total,
+        first + std::cmp::min(request.max_count, MAX_BATCH_SIZE),
+    );
+    Ok(bincode_response(&AddressPage {
+        addresses: book.addresses()[first..next].to_vec(),
+        next,
+        total,
+    }))
+}
+
+/// Register, transfer or release an alias.
+///
+/// Fail with StatusCode::BadRequest if the alias is invalid, or if the signature check fails, or
+/// if an alias is registered by or transferred to an address with no published key. Fail with
+/// StatusCode::Conflict if an alias being registered is already bound to an address, or if the
+/// nonce is not greater than that of the last request for the alias. Fail with
+/// StatusCode::Forbidden if an alias being transferred or released is not bound to the signer.
+async fn update_alias(mut req: tide::Request<ServerState>) -> Result<tide::Response, tide::Error> {
+    let request: AliasRequest = net::server::request_body(&mut req).await?;
+    check_alias(&request.alias)
+        .map_err(|msg| tide::Error::from_str(StatusCode::BadRequest, msg))?;
+    let message = AliasRequest::message(
+        &request.alias,
+        &request.action,
+        &request.pub_key_bytes,
+        request.nonce,
+    );
+    let signer = verify_sig(&request.pub_key_bytes, &message, &request.sig)?.address();
+
+    let mut store = req.state().store.lock().await;
+    let mut book = req.state().book.write().await;
+    let current = book.alias(&request.alias);
+    let current_owner = current.and_then(|version| version.owner.as_ref());
+    let owner = match request.action {
+        AliasAction::Register => {
+            if current_owner.is_some() {
+                return Err(tide::Error::from_str(
+                    StatusCode::Conflict,
+                    format!("alias {} is already registered", request.alias),
+                ));
+            }
+            if book.pub_key(&signer).is_none() {
+                return Err(tide::Error::from_str(
+                    StatusCode::BadRequest,
+                    "an alias can only be registered by an address with a published key",
+                ));
+            }
+            Some(signer)
+        }
+        AliasAction::Transfer(_) | AliasAction::Release if current_owner != Some(&signer) => {
+            return Err(tide::Error::from_str(
+                StatusCode::Forbidden,
+                format!("alias {} is not registered to the signer", request.alias),
+            ));
+        }
+        AliasAction::Transfer(address) => {
+            if book.pub_key(&address).is_none() {
+                return Err(tide::Error::from_str(
+                    StatusCode::BadRequest,
+                    "an alias can only be transferred to an address with a published key",
+                ));
+            }
+            Some(address)
+        }
+        AliasAction::Release => None,
+    };
+    if let Some(current) = current {
+        if request.nonce <= current.nonce {
+            return Err(tide::Error::from_str(
+                StatusCode::Conflict,
+                format!(
+                    "nonce {} is not greater than {}, the nonce of the last change to the alias",
+                    request.nonce, current.nonce
+                ),
+            ));
+        }
+    }
+    let entry = LogEntry::SetAlias(AliasVersion {
+        alias: request.alias,
+        owner,
+        nonce: request.nonce,
+    });
+    store
+        .append(&entry)
+        .map_err(|e| tide::Error::from_str(StatusCode::InternalServerError, e.to_string()))?;
+    book.apply(entry);
+    Ok(tide::Response::new(StatusCode::Ok))
+}
+
+/// Fetch the address an alias is bound to. If it is not bound to any, return
+/// StatusCode::NotFound.
+async fn resolve_alias(mut req: tide::Request<ServerState>) -> Result<tide::Response, tide::Error> {
+    let alias: String = net::server::request_body(&mut req).await?;
+    let book = req.state().book.read().await;
+    match book.resolve_alias(&alias) {
+        Some(address) => Ok(bincode_response(address)),
+        None => Ok(tide::Response::new(StatusCode::NotFound)),
+    }
+}
+
+/// Publish a viewing or freezing key for the address which signed the request.
+///
+/// Fail with StatusCode::BadRequest if key deserialization or the signature check fail. Fail with
+/// StatusCode::Conflict if the address has already published the key, or if the nonce is not
+/// greater than that of the last policy key published for the address. Other addresses may have
+/// published the same key.
+async fn insert_policy_key(
+    mut req: tide::Request<ServerState>,
+) -> Result<tide::Response, tide::Error> {
+    let request: InsertPolicyKey = net::server::request_body(&mut req).await?;
+    let message =
+        InsertPolicyKey::message(&request.policy_key, &request.pub_key_bytes, request.nonce);
+    let owner = verify_sig(&request.pub_key_bytes, &message, &request.sig)?.address();
+
+    let mut store = req.state().store.lock().await;
+    let mut book = req.state().book.write().await;
+    if book
+        .policy_key_claimants(&request.policy_key)
+        .contains(&owner)
+    {
+        return Err(tide::Error::from_str(
+            StatusCode::Conflict,
+            "the key has already been published for this address",
+        ));
+    }
+    if let Some(last) = book.policy_keys(&owner).last() {
+        if request.nonce <= last.nonce {
+            return Err(tide::Error::from_str(
+                StatusCode::Conflict,
+                format!(
+                    "nonce {} is not greater than {}, the nonce of the last policy key",
+                    request.nonce, last.nonce
+                ),
+            ));
+        }
+    }
+    let entry = LogEntry::InsertPolicyKey(PolicyKeyVersion {
+        policy_key: request.policy_key,
+        owner,
+        nonce: request.nonce,
+        timestamp: timestamp_nonce() / 1000,
+    });
+    store
+        .append(&entry)
+        .map_err(|e| tide::Error::from_str(StatusCode::InternalServerError, e.to_string()))?;
+    book.apply(entry);
+    Ok(tide::Response::new(StatusCode::Ok))
+}
+
+/// Fetch the viewing and freezing keys published for the given address, oldest first, as a
+/// bincode `Vec<PolicyKeyVersion>`. If none were, return StatusCode::NotFound.
+async fn request_policy_keys(
+    mut req: tide::Request<ServerState>,
+) -> Result<tide::Response, tide::Error> {
+    let address: UserAddress = net::server::request_body(&mut req).await?;
+    let book = req.state().book.read().await;
+    match book.policy_keys(&address) {
+        [] => Ok(tide::Response::new(StatusCode::NotFound)),
+        versions => Ok(bincode_response(&versions)),
+    }
+}
+
+/// Fetch the addresses which have published the given viewing or freezing key, in the order they
+/// did, as a bincode `Vec<UserAddress>`. If none have, return StatusCode::NotFound.
+///
+/// The claims are unauthenticated; see [AddressBook::policy_key_claimants].
+async fn request_policy_key_claimants(
+    mut req: tide::Request<ServerState>,
+) -> Result<tide::Response, tide::Error> {
+    let policy_key: PolicyKey = net::server::request_body(&mut req).await?;
+    let book = req.state().book.read().await;
+    match book.policy_key_claimants(&policy_key) {
+        [] => Ok(tide::Response::new(StatusCode::NotFound)),
+        claimants => Ok(bincode_response(&claimants)),
+    }
+}
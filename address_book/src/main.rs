@@ -5,12 +5,14 @@
 // This program is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
 // You should have received a copy of the GNU General Public License along with this program. If not, see <https://www.gnu.org/licenses/>.
 
+use address_book::configuration::AddressBookOptions;
 use address_book::init_web_server;
+use address_book::persistence::AddressBookStore;
 use address_book::signal::handle_signals;
 use signal_hook::consts::SIGINT;
 use signal_hook::consts::SIGTERM;
 use signal_hook_async_std::Signals;
-use tide::log::LevelFilter;
+use structopt::StructOpt;
 
 /// Run a web server that provides a key/value store mapping user
 /// addresses to public keys.
@@ -20,7 +22,20 @@ async fn main() -> Result<(), std::io::Error> {
     let handle = signals.handle();
     let signals_task = async_std::task::spawn(handle_signals(signals));
 
-    init_web_server(LevelFilter::Info)
+    let opt = AddressBookOptions::from_args()
+        .with_config_file()
+        .unwrap_or_else(|err| panic!("Unable to read the configuration file: {}", err));
+    let store =
+        AddressBookStore::open(&opt.store_path(), opt.reset_store_state()).unwrap_or_else(|err| {
+            panic!(
+                "Unable to open the store in {:?}: {}. Run again with --reset_store_state to \
+                 start from an empty address book.",
+                opt.store_path(),
+                err
+            )
+        });
+
+    init_web_server(&opt, store)
         .await
         .unwrap_or_else(|err| {
             panic!("Web server exited with an error: {}", err);
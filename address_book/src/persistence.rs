@@ -0,0 +1,77 @@
+// Copyright (c) 2022 Espresso Systems (espressosys.com)
+// This file is part of the Configurable Asset Privacy for Ethereum (CAPE) library.
+
+// This program is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+// You should have received a copy of the GNU General Public License along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+use crate::{AliasVersion, KeyVersion, PolicyKeyVersion};
+use atomic_store::{
+    load_store::BincodeLoadStore, AppendLog, AtomicStore, AtomicStoreLoader, PersistenceError,
+};
+use jf_cap::keys::UserAddress;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+// hook up with atomic_store
+
+/// A change to the address book, as recorded in its log.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum LogEntry {
+    InsertPubKey(KeyVersion),
+    SetAlias(AliasVersion),
+    InsertPolicyKey(PolicyKeyVersion),
+}
+
+/// The log of every change to the address book.
+///
+/// Each change is committed before it is applied in memory, and on startup the address book is
+/// rebuilt by replaying the log. An entry which was being written when the server stopped is
+/// not part of any committed version, so it is discarded when the store is loaded.
+pub struct AddressBookStore {
+    atomic_store: AtomicStore,
+    log: AppendLog<BincodeLoadStore<LogEntry>>,
+}
+
+impl AddressBookStore {
+    pub fn new(store_path: &Path) -> Result<AddressBookStore, PersistenceError> {
+        let mut loader = AtomicStoreLoader::create(&Self::dir(store_path), "address_book")?;
+        let log = AppendLog::create(&mut loader, Default::default(), "address_book_log", 1024)?;
+        let atomic_store = AtomicStore::open(loader)?;
+        Ok(AddressBookStore { atomic_store, log })
+    }
+
+    pub fn load(store_path: &Path) -> Result<AddressBookStore, PersistenceError> {
+        let mut loader = AtomicStoreLoader::load(&Self::dir(store_path), "address_book")?;
+        let log = AppendLog::load(&mut loader, Default::default(), "address_book_log", 1024)?;
+        let atomic_store = AtomicStore::open(loader)?;
+        Ok(AddressBookStore { atomic_store, log })
+    }
+
+    /// Load the store in `store_path` if there is one, or else create it. With `reset`, any
+    /// existing store is replaced by an empty one.
+    pub fn open(store_path: &Path, reset: bool) -> Result<AddressBookStore, PersistenceError> {
+        if reset || !Self::dir(store_path).exists() {
+            Self::new(store_path)
+        } else {
+            Self::load(store_path)
+        }
+    }
+
+    fn dir(store_path: &Path) -> PathBuf {
+        let mut dir = PathBuf::from(store_path);
+        dir.push("address_book");
+        dir
+    }
+
+    pub fn append(&mut self, entry: &LogEntry) -> Result<(), PersistenceError> {
+        self.log.store_resource(entry)?;
+        self.log.commit_version()?;
+        self.atomic_store.commit_version()
+    }
+
+    /// Every committed entry, oldest first.
+    pub fn entries(&self) -> impl Iterator<Item = Result<LogEntry, PersistenceError>> + '_ {
+        self.log.iter()
+    }
+}
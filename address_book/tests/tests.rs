@@ -5,14 +5,75 @@
 // This program is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
 // You should have received a copy of the GNU General Public License along with this program. If not, see <https://www.gnu.org/licenses/>.
 
-use address_book::{address_book_port, init_web_server, wait_for_server, InsertPubKey};
-use jf_cap::keys::{UserKeyPair, UserPubKey};
+use address_book::persistence::{AddressBookStore, LogEntry};
+use address_book::{
+    configuration::AddressBookOptions, init_web_server, wait_for_server, AddressBook, AddressPage,
+    AliasAction, AliasRequest, AliasVersion, InsertPolicyKey, InsertPubKey, KeyVersion,
+    ListAddresses, PolicyKey, PolicyKeyVersion, DEFAULT_PORT, MAX_BATCH_SIZE,
+};
+use jf_cap::keys::{AuditorKeyPair, FreezerKeyPair, UserAddress, UserKeyPair, UserPubKey};
 use rand_chacha::rand_core::SeedableRng;
-use tide::log::LevelFilter;
+use serde::Serialize;
+use structopt::StructOpt;
+use surf::Url;
+use tempdir::TempDir;
+use tide::StatusCode;
 
 const ROUND_TRIP_COUNT: u64 = 100;
 const NOT_FOUND_COUNT: u64 = 100;
 
+/// Start an address book on `port`, with the options in `args` and an empty store, which lasts as
+/// long as the returned directory. Each test uses its own port, so that tests can run in parallel.
+async fn start_server(port: u16, args: &[&str]) -> (AddressBookOptions, TempDir) {
+    let port = port.to_string();
+    let opt = AddressBookOptions::from_iter(
+        ["address_book", "--port", &port, "--log_level", "error"]
+            .iter()
+            .chain(args),
+    );
+    let store_dir = TempDir::new("address_book").unwrap();
+    init_web_server(&opt, AddressBookStore::new(store_dir.path()).unwrap())
+        .await
+        .expect("Failed to run server.");
+    wait_for_server(&opt.base_url()).await;
+    (opt, store_dir)
+}
+
+fn url(opt: &AddressBookOptions, route: &str) -> Url {
+    opt.base_url().join(route).unwrap()
+}
+
+async fn post_json<T: Serialize>(opt: &AddressBookOptions, route: &str, body: &T) -> StatusCode {
+    surf::post(url(opt, route))
+        .content_type(surf::http::mime::JSON)
+        .body_json(body)
+        .unwrap()
+        .await
+        .unwrap()
+        .status()
+}
+
+async fn post_bincode<T: Serialize>(
+    opt: &AddressBookOptions,
+    route: &str,
+    body: &T,
+) -> surf::Response {
+    surf::post(url(opt, route))
+        .content_type(surf::http::mime::BYTE_STREAM)
+        .body_bytes(&bincode::serialize(body).unwrap())
+        .await
+        .unwrap()
+}
+
+async fn resolve_alias(opt: &AddressBookOptions, alias: &str) -> Option<UserAddress> {
+    let bytes = post_bincode(opt, "resolve_alias", &alias)
+        .await
+        .body_bytes()
+        .await
+        .unwrap();
+    bincode::deserialize(&bytes).ok()
+}
+
 // Test
 //    lookup(insert(x)) = x
 // and
@@ -20,11 +81,7 @@ const NOT_FOUND_COUNT: u64 = 100;
 //
 #[async_std::test]
 async fn round_trip() {
-    // TODO !corbett find an unused port rather than assuming 50078 is free.
-    init_web_server(LevelFilter::Error)
-        .await
-        .expect("Failed to run server.");
-    wait_for_server().await;
+    let (opt, _store_dir) = start_server(DEFAULT_PORT, &[]).await;
 
     let mut rng = rand_chacha::ChaChaRng::from_seed([0u8; 32]);
     let mut rng2 = rand_chacha::ChaChaRng::from_seed([0u8; 32]);
@@ -33,27 +90,12 @@ async fn round_trip() {
     for _ in 0..ROUND_TRIP_COUNT {
         let user_key = UserKeyPair::generate(&mut rng);
         let pub_key = user_key.pub_key();
-        let pub_key_bytes = bincode::serialize(&pub_key).unwrap();
-        let sig = user_key.sign(&pub_key_bytes);
-        let json_request = InsertPubKey { pub_key_bytes, sig };
-        let _response = surf::post(format!(
-            "http://127.0.0.1:{}/insert_pubkey",
-            address_book_port()
-        ))
-        .content_type(surf::http::mime::JSON)
-        .body_json(&json_request)
-        .unwrap()
-        .await
-        .unwrap();
-        let address_bytes = bincode::serialize(&pub_key.address()).unwrap();
-        let mut response = surf::post(format!(
-            "http://127.0.0.1:{}/request_pubkey",
-            address_book_port()
-        ))
-        .content_type(surf::http::mime::BYTE_STREAM)
-        .body_bytes(&address_bytes)
-        .await
-        .unwrap();
+        let json_request = InsertPubKey::new(&user_key, 1);
+        assert_eq!(
+            post_json(&opt, "insert_pubkey", &json_request).await,
+            StatusCode::Ok
+        );
+        let mut response = post_bincode(&opt, "request_pubkey", &pub_key.address()).await;
         let bytes = response.body_bytes().await.unwrap();
         let gotten_pub_key: UserPubKey = bincode::deserialize(&bytes).unwrap();
         assert_eq!(gotten_pub_key, pub_key);
@@ -63,15 +105,7 @@ async fn round_trip() {
     for _ in 0..ROUND_TRIP_COUNT {
         let user_key = UserKeyPair::generate(&mut rng2);
         let pub_key = user_key.pub_key();
-        let address_bytes = bincode::serialize(&pub_key.address()).unwrap();
-        let mut response = surf::post(format!(
-            "http://127.0.0.1:{}/request_pubkey",
-            address_book_port()
-        ))
-        .content_type(surf::http::mime::BYTE_STREAM)
-        .body_bytes(&address_bytes)
-        .await
-        .unwrap();
+        let mut response = post_bincode(&opt, "request_pubkey", &pub_key.address()).await;
         let bytes = response.body_bytes().await.unwrap();
         let gotten_pub_key: UserPubKey = bincode::deserialize(&bytes).unwrap();
         assert_eq!(gotten_pub_key, pub_key);
@@ -81,16 +115,408 @@ async fn round_trip() {
     for _ in 0..NOT_FOUND_COUNT {
         let user_key = UserKeyPair::generate(&mut rng2);
         let pub_key = user_key.pub_key();
-        let address_bytes = bincode::serialize(&pub_key.address()).unwrap();
-        let mut response = surf::post(format!(
-            "http://127.0.0.1:{}/request_pubkey",
-            address_book_port()
-        ))
-        .content_type(surf::http::mime::BYTE_STREAM)
-        .body_bytes(&address_bytes)
-        .await
-        .unwrap();
+        let mut response = post_bincode(&opt, "request_pubkey", &pub_key.address()).await;
+        assert_eq!(response.status(), StatusCode::NotFound);
         let bytes = response.body_bytes().await.unwrap();
         assert!(bincode::deserialize::<UserPubKey>(&bytes).is_err());
     }
 }
+
+// Test registering, transferring and releasing an alias, and that invalid, unauthorized and
+// replayed requests are rejected.
+#[async_std::test]
+async fn aliases() {
+    let (opt, _store_dir) = start_server(DEFAULT_PORT + 4, &[]).await;
+    let mut rng = rand_chacha::ChaChaRng::from_seed([6u8; 32]);
+    let owner = UserKeyPair::generate(&mut rng);
+    let user_key = UserKeyPair::generate(&mut rng);
+    for key in [&owner, &user_key] {
+        let request = InsertPubKey::new(key, 1);
+        assert_eq!(
+            post_json(&opt, "insert_pubkey", &request).await,
+            StatusCode::Ok
+        );
+    }
+    // An address with no published key.
+    let unknown = UserKeyPair::generate(&mut rng);
+
+    // Register an alias, then transfer it to another address and release it.
+    let alias = "alice@example.com";
+    let register = AliasRequest::new(&owner, alias, AliasAction::Register, 1);
+    for (request, status) in [
+        (&register, StatusCode::Ok),
+        // a replayed request
+        (&register, StatusCode::Conflict),
+        // an alias which is taken
+        (
+            &AliasRequest::new(&user_key, alias, AliasAction::Register, 2),
+            StatusCode::Conflict,
+        ),
+        // an alias which the signer does not own
+        (
+            &AliasRequest::new(&user_key, alias, AliasAction::Release, 2),
+            StatusCode::Forbidden,
+        ),
+        // an address with no published key
+        (
+            &AliasRequest::new(&owner, alias, AliasAction::Transfer(unknown.address()), 2),
+            StatusCode::BadRequest,
+        ),
+        (
+            &AliasRequest::new(&unknown, "bob@example.com", AliasAction::Register, 1),
+            StatusCode::BadRequest,
+        ),
+        // an invalid alias
+        (
+            &AliasRequest::new(&owner, "Alice", AliasAction::Register, 1),
+            StatusCode::BadRequest,
+        ),
+    ] {
+        assert_eq!(post_json(&opt, "update_alias", request).await, status);
+    }
+    assert_eq!(resolve_alias(&opt, alias).await, Some(owner.address()));
+
+    let transfer = AliasRequest::new(&owner, alias, AliasAction::Transfer(user_key.address()), 2);
+    assert_eq!(
+        post_json(&opt, "update_alias", &transfer).await,
+        StatusCode::Ok
+    );
+    assert_eq!(resolve_alias(&opt, alias).await, Some(user_key.address()));
+
+    let release = AliasRequest::new(&user_key, alias, AliasAction::Release, 3);
+    assert_eq!(
+        post_json(&opt, "update_alias", &release).await,
+        StatusCode::Ok
+    );
+    assert_eq!(resolve_alias(&opt, alias).await, None);
+    // Releasing the alias does not make the old requests valid again.
+    assert_eq!(
+        post_json(&opt, "update_alias", &register).await,
+        StatusCode::Conflict
+    );
+}
+
+// Test that a key can be replaced by one with a greater nonce, that every key published for an
+// address is kept, and that old or forged requests are rejected.
+#[async_std::test]
+async fn key_history() {
+    let (opt, _store_dir) = start_server(DEFAULT_PORT + 1, &[]).await;
+    let mut rng = rand_chacha::ChaChaRng::from_seed([4u8; 32]);
+    let user_key = UserKeyPair::generate(&mut rng);
+
+    // Publish a key again with a greater nonce, then replay both requests.
+    let first = InsertPubKey::new(&user_key, 10);
+    let second = InsertPubKey::new(&user_key, 11);
+    for (request, status) in [
+        (&first, StatusCode::Ok),
+        (&second, StatusCode::Ok),
+        (&first, StatusCode::Conflict),
+        (&second, StatusCode::Conflict),
+    ] {
+        assert_eq!(post_json(&opt, "insert_pubkey", request).await, status);
+    }
+    // A request signed for another nonce is rejected.
+    let forged = InsertPubKey {
+        nonce: 12,
+        ..InsertPubKey::new(&user_key, 1)
+    };
+    assert_eq!(
+        post_json(&opt, "insert_pubkey", &forged).await,
+        StatusCode::BadRequest
+    );
+    // So is a signature of the undecorated key and nonce, which another kind of request could
+    // have produced.
+    let undecorated = InsertPubKey {
+        nonce: 12,
+        sig: user_key.sign(&[first.pub_key_bytes.as_slice(), &12u64.to_le_bytes()].concat()),
+        ..InsertPubKey::new(&user_key, 12)
+    };
+    assert_eq!(
+        post_json(&opt, "insert_pubkey", &undecorated).await,
+        StatusCode::BadRequest
+    );
+
+    let mut response = post_bincode(&opt, "request_pubkey_history", &user_key.address()).await;
+    let bytes = response.body_bytes().await.unwrap();
+    let history: Vec<KeyVersion> = bincode::deserialize(&bytes).unwrap();
+    assert_eq!(
+        history
+            .iter()
+            .map(|version| version.nonce)
+            .collect::<Vec<_>>(),
+        vec![10, 11]
+    );
+    assert!(history
+        .iter()
+        .all(|version| version.pub_key == user_key.pub_key()));
+
+    // An address which never published a key has no history.
+    let unknown = UserKeyPair::generate(&mut rng);
+    let response = post_bincode(&opt, "request_pubkey_history", &unknown.address()).await;
+    assert_eq!(response.status(), StatusCode::NotFound);
+}
+
+async fn list_addresses(opt: &AddressBookOptions, first: usize, max_count: usize) -> AddressPage {
+    let request = ListAddresses { first, max_count };
+    let mut response = post_bincode(opt, "list_addresses", &request).await;
+    assert_eq!(response.status(), StatusCode::Ok);
+    bincode::deserialize(&response.body_bytes().await.unwrap()).unwrap()
+}
+
+// Test looking up several addresses at once, and listing the registered addresses.
+#[async_std::test]
+async fn batch_lookup_and_listing() {
+    let (opt, _store_dir) = start_server(DEFAULT_PORT + 2, &["--enable_listing"]).await;
+    let mut rng = rand_chacha::ChaChaRng::from_seed([5u8; 32]);
+    let user_keys = (0..5)
+        .map(|_| UserKeyPair::generate(&mut rng))
+        .collect::<Vec<_>>();
+    for user_key in &user_keys {
+        let request = InsertPubKey::new(user_key, 1);
+        assert_eq!(
+            post_json(&opt, "insert_pubkey", &request).await,
+            StatusCode::Ok
+        );
+    }
+
+    // Look up two registered addresses and an unknown one.
+    let unknown = UserKeyPair::generate(&mut rng);
+    let addresses = vec![
+        user_keys[0].address(),
+        unknown.address(),
+        user_keys[1].address(),
+    ];
+    let mut response = post_bincode(&opt, "request_pubkeys", &addresses).await;
+    let bytes = response.body_bytes().await.unwrap();
+    let pub_keys: Vec<Option<UserPubKey>> = bincode::deserialize(&bytes).unwrap();
+    assert_eq!(
+        pub_keys,
+        vec![
+            Some(user_keys[0].pub_key()),
+            None,
+            Some(user_keys[1].pub_key())
+        ]
+    );
+
+    // A batch may hold at most MAX_BATCH_SIZE addresses.
+    let addresses = vec![unknown.address(); MAX_BATCH_SIZE + 1];
+    let response = post_bincode(&opt, "request_pubkeys", &addresses).await;
+    assert_eq!(response.status(), StatusCode::BadRequest);
+
+    // List the addresses, in the order they were registered.
+    let addresses = user_keys
+        .iter()
+        .map(|user_key| user_key.address())
+        .collect::<Vec<_>>();
+    let page = list_addresses(&opt, 1, 3).await;
+    assert_eq!(page.addresses, addresses[1..4].to_vec());
+    assert_eq!(page.next, 4);
+    assert_eq!(page.total, 5);
+    let page = list_addresses(&opt, page.next, 10).await;
+    assert_eq!(page.addresses, addresses[4..].to_vec());
+    assert_eq!(page.next, 5);
+    // Past the end, pages are empty.
+    let page = list_addresses(&opt, 7, 10).await;
+    assert!(page.addresses.is_empty());
+    assert_eq!(page.next, 5);
+}
+
+// Test that the registered addresses cannot be listed unless listing is enabled.
+#[async_std::test]
+async fn listing_disabled() {
+    let (opt, _store_dir) = start_server(DEFAULT_PORT + 3, &[]).await;
+    let request = ListAddresses {
+        first: 0,
+        max_count: 10,
+    };
+    let response = post_bincode(&opt, "list_addresses", &request).await;
+    assert_eq!(response.status(), StatusCode::Forbidden);
+}
+
+async fn policy_key_claimants(
+    opt: &AddressBookOptions,
+    policy_key: &PolicyKey,
+) -> Vec<UserAddress> {
+    let mut response = post_bincode(opt, "request_policy_key_claimants", policy_key).await;
+    assert_eq!(response.status(), StatusCode::Ok);
+    bincode::deserialize(&response.body_bytes().await.unwrap()).unwrap()
+}
+
+// Test publishing viewing and freezing keys, and looking them up by address and by key. Since a
+// claim to a policy key is not proven, a key claimed by one address can still be claimed by
+// others.
+#[async_std::test]
+async fn policy_keys() {
+    let (opt, _store_dir) = start_server(DEFAULT_PORT + 5, &[]).await;
+    let mut rng = rand_chacha::ChaChaRng::from_seed([7u8; 32]);
+    let owner = UserKeyPair::generate(&mut rng);
+    let other = UserKeyPair::generate(&mut rng);
+    let viewing_key = PolicyKey::Viewing(AuditorKeyPair::generate(&mut rng).pub_key());
+    let freezing_key = PolicyKey::Freezing(FreezerKeyPair::generate(&mut rng).pub_key());
+
+    let insert_viewing_key = InsertPolicyKey::new(&owner, viewing_key.clone(), 1);
+    for (request, status) in [
+        (&insert_viewing_key, StatusCode::Ok),
+        // a replayed request
+        (&insert_viewing_key, StatusCode::Conflict),
+        // a key the address has already published
+        (
+            &InsertPolicyKey::new(&owner, viewing_key.clone(), 2),
+            StatusCode::Conflict,
+        ),
+        // a key another address has published
+        (
+            &InsertPolicyKey::new(&other, viewing_key.clone(), 1),
+            StatusCode::Ok,
+        ),
+        // a request signed for another nonce
+        (
+            &InsertPolicyKey {
+                nonce: 3,
+                ..InsertPolicyKey::new(&owner, freezing_key.clone(), 2)
+            },
+            StatusCode::BadRequest,
+        ),
+        (
+            &InsertPolicyKey::new(&owner, freezing_key.clone(), 2),
+            StatusCode::Ok,
+        ),
+    ] {
+        assert_eq!(post_json(&opt, "insert_policy_key", request).await, status);
+    }
+
+    for (address, expected) in [
+        (
+            owner.address(),
+            vec![viewing_key.clone(), freezing_key.clone()],
+        ),
+        (other.address(), vec![viewing_key.clone()]),
+    ] {
+        let mut response = post_bincode(&opt, "request_policy_keys", &address).await;
+        let bytes = response.body_bytes().await.unwrap();
+        let versions: Vec<PolicyKeyVersion> = bincode::deserialize(&bytes).unwrap();
+        assert_eq!(
+            versions
+                .into_iter()
+                .map(|version| version.policy_key)
+                .collect::<Vec<_>>(),
+            expected
+        );
+    }
+
+    assert_eq!(
+        policy_key_claimants(&opt, &viewing_key).await,
+        vec![owner.address(), other.address()]
+    );
+    assert_eq!(
+        policy_key_claimants(&opt, &freezing_key).await,
+        vec![owner.address()]
+    );
+    let unclaimed = PolicyKey::Viewing(AuditorKeyPair::generate(&mut rng).pub_key());
+    let response = post_bincode(&opt, "request_policy_key_claimants", &unclaimed).await;
+    assert_eq!(response.status(), StatusCode::NotFound);
+}
+
+// Test that the keys inserted before a restart are there after it, in the order they were
+// inserted.
+#[test]
+fn persistence() {
+    let mut rng = rand_chacha::ChaChaRng::from_seed([1u8; 32]);
+    let store_dir = TempDir::new("address_book").unwrap();
+    let user_keys = (0..3)
+        .map(|_| UserKeyPair::generate(&mut rng))
+        .collect::<Vec<_>>();
+    let viewing_key = PolicyKey::Viewing(AuditorKeyPair::generate(&mut rng).pub_key());
+    {
+        let mut store = AddressBookStore::open(store_dir.path(), false).unwrap();
+        for nonce in 0..2 {
+            for user_key in &user_keys {
+                store
+                    .append(&LogEntry::InsertPubKey(KeyVersion {
+                        pub_key: user_key.pub_key(),
+                        nonce,
+                        timestamp: 0,
+                    }))
+                    .unwrap();
+            }
+        }
+        store
+            .append(&LogEntry::SetAlias(AliasVersion {
+                alias: String::from("alice"),
+                owner: Some(user_keys[0].address()),
+                nonce: 0,
+            }))
+            .unwrap();
+        store
+            .append(&LogEntry::InsertPolicyKey(PolicyKeyVersion {
+                policy_key: viewing_key.clone(),
+                owner: user_keys[1].address(),
+                nonce: 0,
+                timestamp: 0,
+            }))
+            .unwrap();
+    }
+
+    let store = AddressBookStore::open(store_dir.path(), false).unwrap();
+    let book = AddressBook::load(&store).unwrap();
+    assert_eq!(
+        book.addresses(),
+        user_keys
+            .iter()
+            .map(|user_key| user_key.address())
+            .collect::<Vec<_>>()
+    );
+    for user_key in &user_keys {
+        let versions = book.history(&user_key.address());
+        assert_eq!(
+            versions
+                .iter()
+                .map(|version| version.nonce)
+                .collect::<Vec<_>>(),
+            vec![0, 1]
+        );
+        assert_eq!(versions[1].pub_key, user_key.pub_key());
+    }
+    assert_eq!(book.resolve_alias("alice"), Some(&user_keys[0].address()));
+    assert_eq!(
+        book.policy_key_claimants(&viewing_key),
+        [user_keys[1].address()]
+    );
+    assert_eq!(book.policy_keys(&user_keys[1].address()).len(), 1);
+
+    // Resetting the store forgets every key.
+    let store = AddressBookStore::open(store_dir.path(), true).unwrap();
+    assert!(AddressBook::load(&store).unwrap().addresses().is_empty());
+}
+
+// Test that options given on the command line take precedence over the configuration file.
+#[test]
+fn config_file() {
+    let dir = TempDir::new("address_book_config").unwrap();
+    let path = dir.path().join("address_book.toml");
+    std::fs::write(
+        &path,
+        "bind_address = \"::1\"\nport = 8080\ntls_cert = \"cert.pem\"\ntls_key = \"key.pem\"\n\
+         log_level = \"warn\"\n",
+    )
+    .unwrap();
+    let opt = AddressBookOptions::from_iter([
+        "address_book",
+        "--config",
+        path.to_str().unwrap(),
+        "--port",
+        "8443",
+    ])
+    .with_config_file()
+    .unwrap();
+    assert_eq!(opt.bind_address(), "::1");
+    assert_eq!(opt.port(), 8443);
+    assert_eq!(opt.listen_address(), "[::1]:8443");
+    assert_eq!(opt.log_level(), tide::log::LevelFilter::Warn);
+    assert!(opt.tls().unwrap().is_some());
+    assert_eq!(opt.base_url().as_str(), "https://[::1]:8443/");
+
+    // A certificate without a key is an error.
+    let opt = AddressBookOptions::from_iter(["address_book", "--tls_cert", "cert.pem"]);
+    assert!(opt.tls().is_err());
+}
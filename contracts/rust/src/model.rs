@@ -314,7 +314,7 @@ fn is_cap_asset_def_valid(def: &AssetDefinition) -> bool {
 /// None => invalid field, should always be rejected
 /// Some(None) => Valid field, not a burn
 /// Some(Some(addr)) => Valid field, a burn sending to `addr`
-fn extract_burn_dst(xfr: &TransferNote) -> Option<Option<EthereumAddr>> {
+pub fn extract_burn_dst(xfr: &TransferNote) -> Option<Option<EthereumAddr>> {
     let magic_bytes = CAPE_BURN_MAGIC_BYTES.as_bytes().to_vec();
     assert_eq!(magic_bytes.len(), 12);
     assert_eq!(EthereumAddr::default().0.len(), 20);
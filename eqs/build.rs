@@ -0,0 +1,163 @@
+// Copyright (c) 2022 Espresso Systems (espressosys.com)
+// This file is part of the Configurable Asset Privacy for Ethereum (CAPE) library.
+
+// This program is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+// You should have received a copy of the GNU General Public License along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Generates the `ApiRouteKey` enum from `api/api.toml`.
+//!
+//! Every route in api.toml becomes a variant carrying its path patterns, HTTP method and parameter
+//! types, so a route without a handler (or a handler without a route) fails to compile, and
+//! malformed route definitions fail the build instead of the server at startup.
+
+use std::collections::HashMap;
+use std::{env, fs, path::Path};
+
+const API_PATH: &str = "api/api.toml";
+
+// Must match the variants of `route_parsing::UrlSegmentType`.
+const SEGMENT_TYPES: &[&str] = &[
+    "Boolean",
+    "Hexadecimal",
+    "Integer",
+    "TaggedBase64",
+    "Literal",
+];
+
+fn main() {
+    println!("cargo:rerun-if-changed={}", API_PATH);
+    let messages = fs::read_to_string(API_PATH)
+        .unwrap_or_else(|err| panic!("Unable to read {}: {}", API_PATH, err));
+    let api: toml::Value = toml::from_str(&messages)
+        .unwrap_or_else(|err| panic!("Unable to parse {}: {}", API_PATH, err));
+    let routes = api["route"]
+        .as_table()
+        .unwrap_or_else(|| panic!("{} must define a [route] table", API_PATH));
+
+    let mut variants = String::new();
+    let mut paths_arms = String::new();
+    let mut param_types_arms = String::new();
+    let mut method_arms = String::new();
+    // Method and path shape (literals and parameter positions) -> route, to catch routes which
+    // shadow each other.
+    let mut shapes: HashMap<String, String> = HashMap::new();
+
+    for (key, route) in routes {
+        let route = route
+            .as_table()
+            .unwrap_or_else(|| panic!("[route.{}] must be a table", key));
+        if !route.get("DOC").map(|doc| doc.is_str()).unwrap_or(false) {
+            panic!("[route.{}] is missing a DOC string", key);
+        }
+        if !route
+            .get("RESPONSE")
+            .map(|response| response.is_str())
+            .unwrap_or(false)
+        {
+            panic!("[route.{}] is missing a RESPONSE type", key);
+        }
+        let method = match route.get("METHOD").map(|method| method.as_str()) {
+            None | Some(Some("GET")) => "Get",
+            Some(Some("POST")) => "Post",
+            _ => panic!("[route.{}] METHOD must be GET or POST", key),
+        };
+        let paths: Vec<&str> = match route.get("PATH") {
+            Some(toml::Value::String(path)) => vec![path.as_str()],
+            Some(toml::Value::Array(paths)) => paths
+                .iter()
+                .map(|path| {
+                    path.as_str()
+                        .unwrap_or_else(|| panic!("[route.{}] PATH must contain strings", key))
+                })
+                .collect(),
+            _ => panic!(
+                "[route.{}] PATH must be a string or an array of strings",
+                key
+            ),
+        };
+
+        let mut params: Vec<(String, String)> = Vec::new();
+        for path in &paths {
+            let mut shape = Vec::new();
+            for segment in path.split('/') {
+                if !segment.starts_with(':') {
+                    shape.push(segment.to_string());
+                    continue;
+                }
+                let ptype = route
+                    .get(segment)
+                    .and_then(|ptype| ptype.as_str())
+                    .unwrap_or_else(|| {
+                        panic!("[route.{}] has no type for parameter {}", key, segment)
+                    });
+                if !SEGMENT_TYPES.contains(&ptype) {
+                    panic!(
+                        "[route.{}] parameter {} has unknown type {}; expected one of {:?}",
+                        key, segment, ptype, SEGMENT_TYPES
+                    );
+                }
+                shape.push(String::from(":"));
+                if !params.iter().any(|(param, _)| param == segment) {
+                    params.push((segment.to_string(), ptype.to_string()));
+                }
+            }
+            let shape = format!("{} {}", method, shape.join("/"));
+            if let Some(other) = shapes.insert(shape, format!("{} ({})", key, path)) {
+                panic!(
+                    "[route.{}] path {} cannot be told apart from {}",
+                    key, path, other
+                );
+            }
+        }
+
+        variants += &format!("    {},\n", key);
+        method_arms += &format!(
+            "            ApiRouteKey::{} => tide::http::Method::{},\n",
+            key, method
+        );
+        paths_arms += &format!("            ApiRouteKey::{} => &{:?},\n", key, paths);
+        param_types_arms += &format!(
+            "            ApiRouteKey::{} => &[{}],\n",
+            key,
+            params
+                .iter()
+                .map(|(param, ptype)| format!("({:?}, UrlSegmentType::{})", param, ptype))
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+
+    let code = format!(
+        "/// Routes defined in api.toml
+#[allow(non_camel_case_types)]
+#[derive(AsRefStr, Copy, Clone, Debug, PartialEq, Eq, Hash, EnumIter, EnumString)]
+pub enum ApiRouteKey {{
+{}}}
+
+impl ApiRouteKey {{
+    /// Path patterns of this route, relative to the server root.
+    pub fn paths(&self) -> &'static [&'static str] {{
+        match self {{
+{}        }}
+    }}
+
+    /// HTTP method of this route.
+    pub fn method(&self) -> tide::http::Method {{
+        match self {{
+{}        }}
+    }}
+
+    /// Parameters of this route, with the type to parse each one as.
+    pub fn param_types(&self) -> &'static [(&'static str, UrlSegmentType)] {{
+        match self {{
+{}        }}
+    }}
+}}
+",
+        variants, paths_arms, method_arms, param_types_arms
+    );
+    let out_path = Path::new(&env::var("OUT_DIR").unwrap()).join("api_routes.rs");
+    fs::write(&out_path, code)
+        .unwrap_or_else(|err| panic!("Unable to write {}: {}", out_path.display(), err));
+}
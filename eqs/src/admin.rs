@@ -0,0 +1,228 @@
+// Copyright (c) 2022 Espresso Systems (espressosys.com)
+// This file is part of the Configurable Asset Privacy for Ethereum (CAPE) library.
+
+// This program is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+// You should have received a copy of the GNU General Public License along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Offline inspection and repair of the persisted state of an EQS, for the `eqs-admin` binary.
+//!
+//! The EQS must not be running on the same store while these are used. The store keeps a
+//! snapshot of the whole state after every event and every synced range of blocks, which is
+//! what makes [truncate] possible without talking to the chain.
+
+use crate::query_result_state::QueryResultState;
+use crate::state_persistence::StatePersistence;
+use cap_rust_sandbox::model::CAPE_MERKLE_HEIGHT;
+use jf_cap::MerkleTree;
+use reef::traits::{Block, Transaction};
+use seahorse::events::LedgerEvent;
+use serde_json::json;
+use std::io::Write;
+
+/// Summary statistics of the latest state, one `name: value` per line.
+pub fn summary(persistence: &StatePersistence, state: &QueryResultState) -> String {
+    let lines = vec![
+        ("contract", format!("{:?}", state.contract_address)),
+        (
+            "last updated block",
+            state.last_updated_block_height.to_string(),
+        ),
+        ("head block", state.head_block_height.to_string()),
+        (
+            "last processed event",
+            format!("{:?}", state.last_processed_event),
+        ),
+        (
+            "events",
+            format!(
+                "{} (indexes {}..{})",
+                state.events.len(),
+                state.first_event,
+                state.num_events()
+            ),
+        ),
+        ("ledger snapshots", state.ledger_history.len().to_string()),
+        ("transactions", state.transaction_by_id.len().to_string()),
+        ("nullifiers", state.nullifier_list.len().to_string()),
+        (
+            "record Merkle leaves",
+            state
+                .ledger_state
+                .record_merkle_commitment
+                .num_leaves
+                .to_string(),
+        ),
+        ("sponsored assets", state.erc20_registrar.len().to_string()),
+        (
+            "pending wraps",
+            state.pending_commit_event.len().to_string(),
+        ),
+        (
+            "last checkpoint block",
+            format!(
+                "{:?}",
+                state
+                    .last_checkpoint
+                    .as_ref()
+                    .map(|checkpoint| checkpoint.summary.block_height)
+            ),
+        ),
+        (
+            "integrity failure",
+            format!("{:?}", state.integrity_failure),
+        ),
+        ("snapshots", persistence.snapshots().count().to_string()),
+    ];
+    lines
+        .into_iter()
+        .map(|(name, value)| format!("{}: {}\n", name, value))
+        .collect()
+}
+
+/// Write each event, with its index in the full event log, as a line of JSON.
+pub fn export_events(state: &QueryResultState, out: &mut impl Write) -> std::io::Result<()> {
+    for (i, event) in state.events.iter().enumerate() {
+        let line = json!({ "index": state.first_event + i as u64, "event": event });
+        writeln!(out, "{}", line)?;
+    }
+    Ok(())
+}
+
+/// Write each committed transaction, in the order they were committed, as a line of JSON.
+pub fn export_transactions(state: &QueryResultState, out: &mut impl Write) -> std::io::Result<()> {
+    let mut ids: Vec<_> = state.transaction_by_id.keys().collect();
+    ids.sort();
+    for id in ids {
+        let line = serde_json::to_string(&state.transaction_by_id[id]).unwrap();
+        writeln!(out, "{}", line)?;
+    }
+    Ok(())
+}
+
+/// Check that the stored record Merkle frontier, and the record Merkle root after each committed
+/// block, match the outputs of the stored events.
+///
+/// The events are replayed from the start of the event log, or, for a state bootstrapped from a
+/// checkpoint, from the earliest committed block in the state.
+pub fn verify(state: &QueryResultState) -> Result<(), String> {
+    let (mut merkle_tree, first) = if state.first_event == 0 {
+        (MerkleTree::new(CAPE_MERKLE_HEIGHT).unwrap(), 0)
+    } else {
+        let (block_id, snapshot) = state
+            .ledger_history
+            .iter()
+            .next()
+            .ok_or("the state was bootstrapped and has no committed blocks to start from")?;
+        let merkle_tree = MerkleTree::restore_from_frontier(
+            snapshot.ledger_state.record_merkle_commitment,
+            &snapshot.ledger_state.record_merkle_frontier,
+        )
+        .ok_or_else(|| format!("malformed frontier after block {}", block_id))?;
+        (
+            merkle_tree,
+            (snapshot.num_events - state.first_event) as usize,
+        )
+    };
+
+    for event in &state.events[first..] {
+        match event {
+            LedgerEvent::Commit {
+                block, block_id, ..
+            } => {
+                for txn in block.txns() {
+                    for commitment in txn.output_commitments() {
+                        merkle_tree.push(commitment.to_field_element());
+                    }
+                }
+                if let Some(snapshot) = state.ledger_history.get(block_id) {
+                    if snapshot.ledger_state.record_merkle_commitment != merkle_tree.commitment() {
+                        return Err(format!(
+                            "the record Merkle root after block {} does not match its events",
+                            block_id
+                        ));
+                    }
+                }
+            }
+            // Faucet records are not part of any block.
+            LedgerEvent::Memos {
+                outputs,
+                transaction: None,
+            } => {
+                for (_, commitment, _, _) in outputs {
+                    merkle_tree.push(commitment.to_field_element());
+                }
+            }
+            _ => {}
+        }
+    }
+
+    if state.ledger_state.record_merkle_commitment != merkle_tree.commitment()
+        || bincode::serialize(&state.ledger_state.record_merkle_frontier).unwrap()
+            != bincode::serialize(&merkle_tree.frontier()).unwrap()
+    {
+        return Err(format!(
+            "the stored record Merkle frontier ({} leaves) does not match the events ({} leaves)",
+            state.ledger_state.record_merkle_commitment.num_leaves,
+            merkle_tree.num_leaves()
+        ));
+    }
+    Ok(())
+}
+
+/// Roll the state back to the end of Ethereum block `block_height`, so that the EQS syncs again
+/// from the next block when it restarts.
+///
+/// The latest stored snapshot which includes all the events up to `block_height` and none after
+/// it becomes the latest state again. Returns it, or an error if there is no such snapshot.
+pub fn truncate(
+    persistence: &mut StatePersistence,
+    block_height: u64,
+) -> Result<QueryResultState, String> {
+    // Snapshots taken in the middle of a range of blocks also have some of the events after
+    // `last_updated_block_height`; only the ones at the end of a range are complete.
+    let state = persistence
+        .snapshots()
+        .filter(|state| {
+            state.last_updated_block_height <= block_height
+                && state
+                    .last_processed_event
+                    .map_or(true, |(block, _)| block <= state.last_updated_block_height)
+        })
+        .max_by_key(|state| state.last_updated_block_height)
+        .ok_or_else(|| format!("no complete snapshot at or before block {}", block_height))?;
+    persistence.store_latest_state(&state);
+    Ok(state)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::configuration::EQSOptions;
+    use structopt::StructOpt;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_truncate() {
+        let dir = TempDir::new("eqs_admin").unwrap();
+        let mut persistence = StatePersistence::new(dir.path(), "eqs").unwrap();
+        let mut state = QueryResultState::new(EQSOptions::from_iter(["eqs"]).verifier_keys());
+        verify(&state).unwrap();
+
+        for block_height in 1..=5 {
+            // a snapshot in the middle of the block, with only some of its events
+            state.last_processed_event = Some((block_height, 0));
+            persistence.store_latest_state(&state);
+            // and one at the end of it
+            state.last_updated_block_height = block_height;
+            persistence.store_latest_state(&state);
+        }
+
+        let truncated = truncate(&mut persistence, 3).unwrap();
+        assert_eq!(truncated.last_updated_block_height, 3);
+        assert_eq!(truncated.last_processed_event, Some((3, 0)));
+        let latest = persistence.load_latest_state().unwrap();
+        assert_eq!(latest.last_updated_block_height, 3);
+        assert_eq!(latest.last_processed_event, Some((3, 0)));
+    }
+}
@@ -6,22 +6,27 @@
 // You should have received a copy of the GNU General Public License along with this program. If not, see <https://www.gnu.org/licenses/>.
 
 use crate::configuration::EQSOptions;
-use crate::errors::EQSNetError;
+use crate::errors::{server_error, EQSNetError};
+use crate::metrics::Metrics;
 use crate::query_result_state::QueryResultState;
-use crate::route_parsing::{RouteBinding, UrlSegmentType, UrlSegmentValue};
-use crate::routes::dispatch_url;
+use crate::route_parsing::{RouteBinding, UrlSegmentValue};
+use crate::routes::{dispatch_url, ApiRouteKey};
 
 use async_std::{
     sync::{Arc, RwLock},
     task,
 };
+use ethers::prelude::Address;
 use net::server;
 use serde::{Deserialize, Serialize};
 use snafu::Snafu;
 use std::collections::hash_map::HashMap;
 use std::path::PathBuf;
-use std::str::FromStr;
+use strum::IntoEnumIterator;
+use tide::http::headers::HeaderValue;
+use tide::security::{CorsMiddleware, Origin};
 use tide::StatusCode;
+use tide_compress::CompressMiddleware;
 
 #[derive(Clone, Debug, Snafu, Serialize, Deserialize)]
 pub enum Error {
@@ -48,201 +53,527 @@ impl net::Error for Error {
 #[allow(dead_code)]
 #[derive(Clone)]
 pub struct WebState {
-    pub(crate) query_result_state: Arc<RwLock<QueryResultState>>,
+    /// State of each indexed contract.
+    pub(crate) contracts: Arc<HashMap<Address, Arc<RwLock<QueryResultState>>>>,
+    /// Contract whose routes are also served without a `/:contract` prefix.
+    pub(crate) default_contract: Address,
     pub(crate) web_path: PathBuf,
     pub(crate) api: toml::Value,
+    pub(crate) metrics: Arc<Metrics>,
+    /// Token required to read viewed transactions, which are disabled without one.
+    pub(crate) viewing_token: Option<String>,
 }
 
-// Get the route pattern that matches the URL of a request, and the bindings for parameters in the
-// pattern. If no route matches, the error is a documentation string explaining what went wrong.
-fn parse_route(
+/// The state of the contract a request is for: the one named by its `/:contract` prefix, if it
+/// has one, or else the default contract.
+pub(crate) fn contract_state(
     req: &tide::Request<WebState>,
-) -> Result<(String, HashMap<String, RouteBinding>), String> {
-    let first_segment = &req
-        .url()
-        .path_segments()
-        .ok_or_else(|| String::from("No path segments"))?
-        .next()
-        .ok_or_else(|| String::from("Empty path"))?;
-    let api = &req.state().api["route"][first_segment];
-    let route_patterns = api["PATH"]
-        .as_array()
-        .expect("Invalid PATH type. Expecting array.");
-    let mut arg_doc: String = api["DOC"].as_str().expect("Missing DOC").to_string();
-    let mut matching_route_count = 0u64;
-    let mut matching_route = String::new();
-    let mut bindings: HashMap<String, HashMap<String, RouteBinding>> = HashMap::new();
-    for route_pattern in route_patterns.iter() {
-        let mut found_literal_mismatch = false;
-        let mut argument_parse_failed = false;
-        arg_doc.push_str(&format!(
-            "\n\nRoute: {}\n--------------------\n",
-            &route_pattern.as_str().unwrap()
-        ));
-        // The `path_segments()` succeeded above, so `unwrap()` is safe.
-        let mut req_segments = req.url().path_segments().unwrap();
-        for pat_segment in route_pattern
-            .as_str()
-            .expect("PATH must be an array of strings")
-            .split('/')
-        {
-            // Each route parameter has an associated type. The lookup
-            // will only succeed if the current segment is a parameter
-            // placeholder, such as :id. Otherwise, it is assumed to
-            // be a literal.
-            if let Some(segment_type_value) = &api.get(pat_segment) {
-                let segment_type = segment_type_value
-                    .as_str()
-                    .expect("The path pattern must be a string.");
-                let req_segment = req_segments.next().unwrap_or("");
-                arg_doc.push_str(&format!(
-                    "  Argument: {} as type {} and value: {} ",
-                    pat_segment, segment_type, req_segment
-                ));
-                let ptype =
-                    UrlSegmentType::from_str(segment_type).map_err(|err| err.to_string())?;
-                if let Some(value) = UrlSegmentValue::parse(ptype, req_segment) {
-                    let rb = RouteBinding {
-                        parameter: pat_segment.to_string(),
-                        ptype,
+) -> Result<Arc<RwLock<QueryResultState>>, tide::Error> {
+    let state = req.state();
+    let address = match req.param("contract") {
+        Ok(contract) => contract.parse::<Address>().map_err(|_| {
+            server_error(EQSNetError::UnknownContract {
+                contract: contract.to_string(),
+            })
+        })?,
+        Err(_) => state.default_contract,
+    };
+    state.contracts.get(&address).cloned().ok_or_else(|| {
+        server_error(EQSNetError::UnknownContract {
+            contract: format!("{:?}", address),
+        })
+    })
+}
+
+/// Bind the parameters of `pattern` in the URL of a request, parsed as the types declared for
+/// `key` in api.toml.
+///
+/// If a parameter fails to parse, the error carries the `ERROR_` message for that parameter from
+/// api.toml and a BadRequest status.
+fn bind_params(
+    req: &tide::Request<WebState>,
+    key: ApiRouteKey,
+    pattern: &str,
+) -> Result<HashMap<String, RouteBinding>, tide::Error> {
+    let mut bindings = HashMap::new();
+    for (parameter, ptype) in key.param_types() {
+        if !pattern.split('/').any(|segment| segment == *parameter) {
+            continue;
+        }
+        let name = parameter.trim_start_matches(':');
+        // Tide only routes the request here if the path matches `pattern`, so the parameter is
+        // always present.
+        let req_segment = req.param(name)?;
+        match UrlSegmentValue::parse(*ptype, req_segment) {
+            Some(value) => {
+                bindings.insert(
+                    parameter.to_string(),
+                    RouteBinding {
+                        parameter: parameter.to_string(),
+                        ptype: *ptype,
                         value,
-                    };
-                    bindings
-                        .entry(String::from(route_pattern.as_str().unwrap()))
-                        .or_default()
-                        .insert(pat_segment.to_string(), rb);
-                    arg_doc.push_str("(Parse succeeded)\n");
-                } else {
-                    arg_doc.push_str("(Parse failed)\n");
-                    argument_parse_failed = true;
-                    // TODO !corbett capture parse failures documentation
-                    // UrlSegmentValue::ParseFailed(segment_type, req_segment)
-                }
-            } else {
-                // No type information. Assume pat_segment is a literal.
-                let req_segment = req_segments.next().unwrap_or("");
-                if req_segment != pat_segment {
-                    found_literal_mismatch = true;
-                    arg_doc.push_str(&format!(
-                        "Request segment {} does not match route segment {}.\n",
-                        req_segment, pat_segment
-                    ));
-                }
-                // TODO !corbett else capture the matching literal in bindings
-                // TODO !corebtt if the edit distance is small, capture spelling suggestion
+                    },
+                );
+            }
+            None => {
+                let route_name: &str = key.as_ref();
+                let msg = req
+                    .state()
+                    .api
+                    .get("route")
+                    .and_then(|routes| routes.get(route_name))
+                    .and_then(|route| route.get(&format!("ERROR_{}", name)))
+                    .and_then(|msg| msg.as_str())
+                    .map(String::from)
+                    .unwrap_or_else(|| format!("expected {:?}", ptype));
+                return Err(server_error(EQSNetError::RouteParam {
+                    param: name.to_string(),
+                    value: req_segment.to_string(),
+                    msg,
+                }));
             }
-        }
-        if !found_literal_mismatch {
-            arg_doc.push_str(&format!(
-                "Literals match for {}\n",
-                &route_pattern.as_str().unwrap(),
-            ));
-        }
-        let mut length_matches = false;
-        if req_segments.next().is_none() {
-            arg_doc.push_str(&format!(
-                "Length match for {}\n",
-                &route_pattern.as_str().unwrap(),
-            ));
-            length_matches = true;
-        }
-        if argument_parse_failed {
-            arg_doc.push_str("Argument parsing failed.\n");
-        } else {
-            arg_doc.push_str("No argument parsing errors!\n");
-        }
-        if !argument_parse_failed && length_matches && !found_literal_mismatch {
-            let route_pattern_str = route_pattern.as_str().unwrap();
-            arg_doc.push_str(&format!("Route matches request: {}\n", &route_pattern_str));
-            matching_route_count += 1;
-            matching_route = String::from(route_pattern_str);
-        } else {
-            arg_doc.push_str("Route does not match request.\n");
-        }
-    }
-    match matching_route_count {
-        0 => {
-            arg_doc.push_str("\nNeed documentation");
-            Err(arg_doc)
-        }
-        1 => {
-            let route_bindings = bindings.remove(&matching_route).unwrap_or_default();
-            Ok((matching_route, route_bindings))
-        }
-        _ => {
-            arg_doc.push_str("\nAmbiguity in api.toml");
-            Err(arg_doc)
         }
     }
+    Ok(bindings)
 }
 
-/// Handle API requests defined in api.toml.
-///
-/// This function duplicates the logic for deciding which route was requested. This
-/// implies defining the routes in an external file.
-// todo !corbett Convert the error feedback into HTML
-async fn entry_page(req: tide::Request<WebState>) -> Result<tide::Response, tide::Error> {
-    match parse_route(&req) {
-        Ok((pattern, bindings)) => dispatch_url(req, pattern.as_str(), &bindings).await,
-        Err(arg_doc) => Ok(tide::Response::builder(200).body(arg_doc).build()),
-    }
+/// Handle a request for one of the routes defined in api.toml.
+async fn entry_page(
+    req: tide::Request<WebState>,
+    key: ApiRouteKey,
+    pattern: &'static str,
+) -> Result<tide::Response, tide::Error> {
+    req.state().metrics.count_request(key);
+    let bindings = bind_params(&req, key, pattern)?;
+    dispatch_url(req, key, &bindings).await
 }
 
 /// Initialize the web server.
 ///
-/// `opt_web_path` is the path to the web assets directory. If the path
-/// is empty, the default is constructed assuming Cargo is used to
-/// build the executable in the customary location.
-///
-/// `own_id` is the identifier of this instance of the executable. The
-/// port the web server listens on is `50087`, unless the
+/// The port the web server listens on is `50087`, unless the
 /// --eqs_port parameter is passed to the command line.
 /// The wallet uses the PORT env variable, making that unsuitable for the EQS
-
+///
+/// `contracts` holds the state of each indexed contract, and `default_contract` is the one
+/// served without a `/:contract` prefix. `metrics` are served at `/metrics`, along with the
+/// `/healthz` and `/readyz` checks.
+///
+/// Fails if the api.toml at `opt.api_path()` does not define the routes this EQS was built with.
 pub(crate) fn init_web_server(
     opt: &EQSOptions,
-    query_result_state: Arc<RwLock<QueryResultState>>,
+    contracts: Arc<HashMap<Address, Arc<RwLock<QueryResultState>>>>,
+    default_contract: Address,
+    metrics: Arc<Metrics>,
 ) -> Result<task::JoinHandle<Result<(), std::io::Error>>, tide::Error> {
+    let web_server = web_server(opt, contracts, default_contract, metrics);
+    check_api(&web_server.state().api).map_err(|msg| {
+        tide::Error::from_str(
+            StatusCode::InternalServerError,
+            format!(
+                "{} does not match the routes this EQS was built with: {}",
+                opt.api_path().display(),
+                msg
+            ),
+        )
+    })?;
+    let port = opt.eqs_port().to_string();
+    let addr = format!("0.0.0.0:{}", port);
+    let join_handle = async_std::task::spawn(web_server.listen(addr));
+    Ok(join_handle)
+}
+
+/// Check that the routes of `api`, the api.toml loaded at runtime, are the ones compiled into
+/// `ApiRouteKey`, with the same paths and methods.
+///
+/// The help page, OpenAPI document and parameter error messages come from the runtime api.toml,
+/// so a stale or edited copy would describe routes the server does not have.
+fn check_api(api: &toml::Value) -> Result<(), String> {
+    let routes = api
+        .get("route")
+        .and_then(|routes| routes.as_table())
+        .ok_or_else(|| String::from("missing [route] table"))?;
+    for key in ApiRouteKey::iter() {
+        let name: &str = key.as_ref();
+        let route = routes
+            .get(name)
+            .ok_or_else(|| format!("route {} is missing", name))?;
+        let paths: Vec<&str> = match route.get("PATH") {
+            Some(toml::Value::String(path)) => vec![path.as_str()],
+            Some(toml::Value::Array(paths)) => {
+                paths.iter().filter_map(|path| path.as_str()).collect()
+            }
+            _ => vec![],
+        };
+        if paths != key.paths() {
+            return Err(format!(
+                "route {} has paths {:?}, expected {:?}",
+                name,
+                paths,
+                key.paths()
+            ));
+        }
+        let method = route
+            .get("METHOD")
+            .and_then(|method| method.as_str())
+            .unwrap_or("GET");
+        if method != key.method().to_string() {
+            return Err(format!(
+                "route {} has method {}, expected {}",
+                name,
+                method,
+                key.method()
+            ));
+        }
+    }
+    if let Some(name) = routes
+        .keys()
+        .find(|name| !ApiRouteKey::iter().any(|key| key.as_ref() == name.as_str()))
+    {
+        return Err(format!("route {} is not served", name));
+    }
+    Ok(())
+}
+
+/// CORS headers for the origins in `--cors_origin`, if any.
+fn cors(opt: &EQSOptions) -> Option<CorsMiddleware> {
+    if opt.cors_origins().is_empty() {
+        return None;
+    }
+    let origin = if opt.cors_origins().iter().any(|origin| origin == "*") {
+        Origin::Any
+    } else {
+        Origin::List(opt.cors_origins().to_vec())
+    };
+    Some(
+        CorsMiddleware::new()
+            .allow_methods("GET, POST, OPTIONS".parse::<HeaderValue>().unwrap())
+            .allow_origin(origin)
+            .allow_credentials(false),
+    )
+}
+
+/// The routes and middleware of the web server.
+fn web_server(
+    opt: &EQSOptions,
+    contracts: Arc<HashMap<Address, Arc<RwLock<QueryResultState>>>>,
+    default_contract: Address,
+    metrics: Arc<Metrics>,
+) -> tide::Server<WebState> {
     let api = crate::disco::load_messages(&opt.api_path());
     let mut web_server = tide::with_state(WebState {
-        query_result_state,
+        contracts,
+        default_contract,
         web_path: opt.web_path(),
-        api: api.clone(),
+        api,
+        metrics,
+        viewing_token: opt.viewing_token().map(String::from),
     });
     web_server
         .with(server::trace)
         .with(server::add_error_body::<_, EQSNetError>);
+    if let Some(cors) = cors(opt) {
+        web_server.with(cors);
+    }
+    // Responses are compressed with gzip, brotli or deflate, according to the Accept-Encoding
+    // header of the request.
+    web_server.with(CompressMiddleware::new());
     web_server.at("/").get(crate::disco::compose_help);
+    web_server
+        .at("/openapi.json")
+        .get(crate::disco::compose_openapi);
+    web_server.at("/healthz").get(crate::metrics::healthz);
+    web_server.at("/readyz").get(crate::metrics::readyz);
+    web_server.at("/metrics").get(crate::metrics::metrics);
+    for prefix in ["", ":contract/"] {
+        web_server
+            .at(&format!("{}explorer", prefix))
+            .get(crate::explorer::explorer_home);
+        web_server
+            .at(&format!("{}explorer/block/:block_id", prefix))
+            .get(crate::explorer::explorer_block);
+        web_server
+            .at(&format!("{}explorer/transaction/:block_id/:txn_id", prefix))
+            .get(crate::explorer::explorer_transaction);
+        web_server
+            .at(&format!("{}explorer/nullifier/:nullifier", prefix))
+            .get(crate::explorer::explorer_nullifier);
+    }
 
-    // Add routes from a configuration file.
-    if let Some(api_map) = api["route"].as_table() {
-        api_map.values().for_each(|v| {
-            let routes = match &v["PATH"] {
-                toml::Value::String(s) => {
-                    vec![s.clone()]
-                }
-                toml::Value::Array(a) => a
-                    .iter()
-                    .filter_map(|v| {
-                        if let Some(s) = v.as_str() {
-                            Some(String::from(s))
-                        } else {
-                            println!("Array element: {:?}", v);
-                            None
-                        }
-                    })
-                    .collect(),
-                _ => panic!("Expecting a toml::String or toml::Array, but got: {:?}", &v),
-            };
-            for path in routes {
-                let mut route = web_server.at(&path);
-                route.get(entry_page);
+    // Add the routes defined in api.toml, for the default contract and under a `/:contract`
+    // prefix for every contract. Requests which match none of them get a NotFound response from
+    // tide.
+    for key in ApiRouteKey::iter() {
+        for &pattern in key.paths() {
+            for path in [pattern.to_string(), format!(":contract/{}", pattern)] {
+                web_server
+                    .at(&path)
+                    .method(key.method(), move |req: tide::Request<WebState>| {
+                        entry_page(req, key, pattern)
+                    });
             }
-        });
+        }
     }
 
-    let port = opt.eqs_port().to_string();
-    let addr = format!("0.0.0.0:{}", port);
-    let join_handle = async_std::task::spawn(web_server.listen(addr));
-    Ok(join_handle)
+    web_server
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::metrics::Metrics;
+    use crate::routes::{MAX_NULLIFIER_BATCH, MAX_NULLIFIER_SIZE};
+    use jf_cap::keys::AuditorKeyPair;
+    use rand_chacha::rand_core::SeedableRng;
+    use rand_chacha::ChaChaRng;
+    use structopt::StructOpt;
+    use tide::http::{Method, Request, Response, Url};
+
+    fn test_server(args: &[&str]) -> tide::Server<WebState> {
+        let opt = EQSOptions::from_iter(std::iter::once("eqs").chain(args.iter().copied()));
+        multi_contract_server(&opt, &[Address::zero()])
+    }
+
+    /// A server for `contracts`, the first of which is the default. The state of each contract has
+    /// its position in `contracts` as its `last_updated_block_height`.
+    fn multi_contract_server(opt: &EQSOptions, contracts: &[Address]) -> tide::Server<WebState> {
+        let verifier_keys = opt.verifier_keys();
+        let states = contracts
+            .iter()
+            .enumerate()
+            .map(|(i, contract)| {
+                let mut state = QueryResultState::new(verifier_keys.clone());
+                state.last_updated_block_height = i as u64;
+                (*contract, Arc::new(RwLock::new(state)))
+            })
+            .collect();
+        let metrics = Metrics::new(Default::default(), opt.max_ready_lag());
+        web_server(opt, Arc::new(states), contracts[0], Arc::new(metrics))
+    }
+
+    async fn get(
+        server: &tide::Server<WebState>,
+        path: &str,
+        headers: &[(&str, &str)],
+    ) -> Response {
+        let mut req = Request::new(
+            Method::Get,
+            Url::parse(&format!("http://localhost/{}", path)).unwrap(),
+        );
+        for (name, value) in headers {
+            req.insert_header(*name, *value);
+        }
+        server.respond(req).await.unwrap()
+    }
+
+    fn header(res: &Response, name: &str) -> Option<String> {
+        res.header(name).map(|value| value.as_str().to_string())
+    }
+
+    #[async_std::test]
+    async fn test_content_negotiation() {
+        let server = test_server(&[]);
+
+        let mut res = get(
+            &server,
+            "get_sponsored_assets",
+            &[("Accept", "application/json")],
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::Ok);
+        assert!(header(&res, "Content-Type")
+            .unwrap()
+            .starts_with("application/json"));
+        let assets: Vec<serde_json::Value> =
+            serde_json::from_str(&res.body_string().await.unwrap()).unwrap();
+        assert!(assets.is_empty());
+
+        let mut res = get(
+            &server,
+            "get_sponsored_assets",
+            &[("Accept", "application/octet-stream")],
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::Ok);
+        assert!(header(&res, "Content-Type")
+            .unwrap()
+            .starts_with("application/octet-stream"));
+        assert_eq!(res.body_bytes().await.unwrap(), vec![0u8; 8]);
+
+        // The nullifier filter is negotiated like every other route.
+        let mut res = get(
+            &server,
+            "get_nullifier_filter",
+            &[("Accept", "application/json")],
+        )
+        .await;
+        let filter: Vec<u8> = serde_json::from_str(&res.body_string().await.unwrap()).unwrap();
+        let mut res = get(
+            &server,
+            "get_nullifier_filter",
+            &[("Accept", "application/octet-stream")],
+        )
+        .await;
+        let bytes: Vec<u8> = bincode::deserialize(&res.body_bytes().await.unwrap()).unwrap();
+        assert_eq!(filter, bytes);
+    }
+
+    #[test]
+    fn test_check_api() {
+        let opt = EQSOptions::from_iter(["eqs"]);
+        let api = crate::disco::load_messages(&opt.api_path());
+        check_api(&api).unwrap();
+
+        // A route the server does not have.
+        let mut extra = api.clone();
+        extra["route"].as_table_mut().unwrap().insert(
+            String::from("get_nothing"),
+            toml::from_str("PATH = [\"get_nothing\"]\nDOC = \"Nothing.\"").unwrap(),
+        );
+        assert!(check_api(&extra).unwrap_err().contains("get_nothing"));
+
+        // A route the server has, but api.toml lacks.
+        let mut missing = api.clone();
+        missing["route"]
+            .as_table_mut()
+            .unwrap()
+            .remove("get_cap_state")
+            .unwrap();
+        assert!(check_api(&missing).unwrap_err().contains("get_cap_state"));
+
+        // A route whose path has changed.
+        let mut moved = api.clone();
+        moved["route"]["get_cap_state"]["PATH"] = toml::Value::Array(vec!["cap_state".into()]);
+        assert!(check_api(&moved).unwrap_err().contains("get_cap_state"));
+
+        // A route whose method has changed.
+        let mut method = api;
+        method["route"]["get_cap_state"]
+            .as_table_mut()
+            .unwrap()
+            .insert(String::from("METHOD"), "POST".into());
+        assert!(check_api(&method).unwrap_err().contains("get_cap_state"));
+    }
+
+    #[async_std::test]
+    async fn test_compression() {
+        let server = test_server(&[]);
+        for encoding in ["gzip", "br"] {
+            let res = get(&server, "openapi.json", &[("Accept-Encoding", encoding)]).await;
+            assert_eq!(res.status(), StatusCode::Ok);
+            assert_eq!(header(&res, "Content-Encoding").as_deref(), Some(encoding));
+        }
+        let res = get(&server, "openapi.json", &[]).await;
+        assert_eq!(header(&res, "Content-Encoding"), None);
+    }
+
+    #[async_std::test]
+    async fn test_cors() {
+        let origin = "https://explorer.example.com";
+
+        // No CORS headers unless origins are configured.
+        let server = test_server(&[]);
+        let res = get(&server, "get_sponsored_assets", &[("Origin", origin)]).await;
+        assert_eq!(header(&res, "Access-Control-Allow-Origin"), None);
+
+        let server = test_server(&["--cors_origin", origin]);
+        let res = get(&server, "get_sponsored_assets", &[("Origin", origin)]).await;
+        assert_eq!(res.status(), StatusCode::Ok);
+        assert_eq!(
+            header(&res, "Access-Control-Allow-Origin").as_deref(),
+            Some(origin)
+        );
+    }
+
+    #[async_std::test]
+    async fn test_help_pages() {
+        // Both pages render every heading they use from api.toml, and panic on a missing one.
+        let server = test_server(&[]);
+        for path in ["", "explorer"] {
+            let mut res = get(&server, path, &[]).await;
+            assert_eq!(res.status(), StatusCode::Ok);
+            let body = res.body_string().await.unwrap();
+            assert!(body.contains("</html>"));
+        }
+        let mut res = get(&server, "", &[]).await;
+        let body = res.body_string().await.unwrap();
+        assert!(body.contains("get_cap_state"));
+        assert!(body.contains("Routes"));
+    }
+
+    #[async_std::test]
+    async fn test_viewing_token() {
+        let key = AuditorKeyPair::generate(&mut ChaChaRng::from_seed([0; 32])).pub_key();
+        let path = format!("get_viewed_transactions/{}/0/10", key);
+
+        // Viewed transactions are not served at all without a token.
+        let server = test_server(&[]);
+        let res = get(&server, &path, &[("Authorization", "Bearer secret")]).await;
+        assert_eq!(res.status(), StatusCode::Forbidden);
+
+        let server = test_server(&["--viewing_token", "secret"]);
+        let unauthorized: [&[(&str, &str)]; 3] = [
+            &[],
+            &[("Authorization", "Bearer wrong")],
+            &[("Authorization", "secret")],
+        ];
+        for headers in unauthorized {
+            let res = get(&server, &path, headers).await;
+            assert_eq!(res.status(), StatusCode::Unauthorized);
+        }
+        // With the token, the request gets as far as looking up the key, which is not scanned for.
+        let res = get(&server, &path, &[("Authorization", "Bearer secret")]).await;
+        assert_eq!(res.status(), StatusCode::NotFound);
+    }
+
+    #[async_std::test]
+    async fn test_check_nullifiers_body_limit() {
+        let server = test_server(&[]);
+        let request = |body: Vec<u8>| {
+            let mut req = Request::new(
+                Method::Post,
+                Url::parse("http://localhost/check_nullifiers").unwrap(),
+            );
+            req.insert_header("Content-Type", "application/json");
+            req.set_body(body);
+            req
+        };
+
+        let mut res: Response = server.respond(request(b"[]".to_vec())).await.unwrap();
+        assert_eq!(res.status(), StatusCode::Ok);
+        let spent: Vec<bool> = res.body_json().await.unwrap();
+        assert!(spent.is_empty());
+
+        // An oversized body is rejected without being parsed.
+        let max = MAX_NULLIFIER_BATCH * MAX_NULLIFIER_SIZE;
+        let res: Response = server.respond(request(vec![b' '; max + 1])).await.unwrap();
+        assert_eq!(res.status(), StatusCode::PayloadTooLarge);
+    }
+
+    #[async_std::test]
+    async fn test_contract_routing() {
+        let opt = EQSOptions::from_iter(["eqs"]);
+        let contracts = [Address::from([1; 20]), Address::from([2; 20])];
+        let server = multi_contract_server(&opt, &contracts);
+
+        let sync_height = |mut res: Response| async move {
+            assert_eq!(res.status(), StatusCode::Ok);
+            let status: serde_json::Value =
+                serde_json::from_str(&res.body_string().await.unwrap()).unwrap();
+            status["last_updated_block_height"].as_u64().unwrap()
+        };
+
+        // Routes without a prefix go to the first contract.
+        let res = get(&server, "sync_status", &[]).await;
+        assert_eq!(sync_height(res).await, 0);
+        for (i, contract) in contracts.iter().enumerate() {
+            let res = get(&server, &format!("{:?}/sync_status", contract), &[]).await;
+            assert_eq!(sync_height(res).await, i as u64);
+        }
+
+        let unknown = Address::from([3; 20]);
+        let res = get(&server, &format!("{:?}/sync_status", unknown), &[]).await;
+        assert_eq!(res.status(), StatusCode::NotFound);
+        let res = get(&server, "not_a_contract/sync_status", &[]).await;
+        assert_eq!(res.status(), StatusCode::NotFound);
+    }
 }
@@ -0,0 +1,417 @@
+// Copyright (c) 2022 Espresso Systems (espressosys.com)
+// This file is part of the Configurable Asset Privacy for Ethereum (CAPE) library.
+
+// This program is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+// You should have received a copy of the GNU General Public License along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Optional scanning of committed transactions for asset viewers (auditors).
+//!
+//! When the EQS is given viewing key pairs (`--viewing_key_file`), it opens the audit memos of
+//! the transfers and mints it commits with each key, and keeps a ledger of what each key can
+//! view. What is revealed is up to the `AssetPolicy` of the asset: the asset code always, and the
+//! address and amount of each record only if the policy reveals them. Freeze notes have no audit
+//! memos, so they are never viewable. Wraps are viewable in full, since their record openings
+//! are public anyway.
+//!
+//! To open a transfer memo, the viewer needs the definition of the asset, so the definitions of
+//! all assets seen in sponsors, wraps and mints are kept as well.
+
+use cap_rust_sandbox::ledger::{CapeTransactionKind, CapeTransition, CommittedCapeTransition};
+use cap_rust_sandbox::model::CapeModelTxn;
+use jf_cap::keys::{AuditorKeyPair, AuditorPubKey, UserAddress};
+use jf_cap::structs::{AssetCode, AssetDefinition, AuditData};
+use jf_cap::transfer::TransferNote;
+use jf_cap::TransactionNote;
+use reef::traits::Transaction;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::read;
+use std::path::PathBuf;
+
+/// The part of a record revealed to an asset viewer.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ViewedRecord {
+    pub asset_code: AssetCode,
+    pub address: Option<UserAddress>,
+    pub amount: Option<u64>,
+}
+
+impl From<AuditData> for ViewedRecord {
+    fn from(data: AuditData) -> Self {
+        Self {
+            asset_code: data.asset_code,
+            address: data.user_address,
+            amount: data.amount,
+        }
+    }
+}
+
+/// A committed transaction, as seen by an asset viewer.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ViewedTransaction {
+    pub block_id: u64,
+    pub txn_id: u64,
+    pub kind: CapeTransactionKind,
+    pub inputs: Vec<ViewedRecord>,
+    pub outputs: Vec<ViewedRecord>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct AuditState {
+    // every asset definition seen so far
+    pub assets: HashMap<AssetCode, AssetDefinition>,
+    // transactions viewable with each registered viewing key, in the order they were committed
+    pub ledgers: HashMap<AuditorPubKey, Vec<ViewedTransaction>>,
+}
+
+impl AuditState {
+    /// Start keeping a ledger for `key`. Only transactions committed from now on are scanned.
+    pub fn register(&mut self, key: AuditorPubKey) {
+        self.ledgers.entry(key).or_default();
+    }
+
+    pub fn learn_asset(&mut self, asset_def: &AssetDefinition) {
+        self.assets
+            .entry(asset_def.code)
+            .or_insert_with(|| asset_def.clone());
+    }
+
+    /// Add `txn` to the ledger of each key in `keys` that can view it.
+    pub fn scan(&mut self, keys: &[AuditorKeyPair], txn: &CommittedCapeTransition) {
+        match &txn.transition {
+            CapeTransition::Transaction(CapeModelTxn::CAP(TransactionNote::Mint(mint))) => {
+                self.learn_asset(&mint.mint_asset_def)
+            }
+            CapeTransition::Transaction(CapeModelTxn::Burn { ro, .. }) => {
+                self.learn_asset(&ro.asset_def)
+            }
+            CapeTransition::Wrap { ro, .. } => self.learn_asset(&ro.asset_def),
+            _ => {}
+        }
+        for key in keys {
+            if let Some((inputs, outputs)) = self.view(key, &txn.transition) {
+                self.ledgers
+                    .entry(key.pub_key())
+                    .or_default()
+                    .push(ViewedTransaction {
+                        block_id: txn.block_id,
+                        txn_id: txn.txn_id,
+                        kind: txn.transition.kind(),
+                        inputs,
+                        outputs,
+                    });
+            }
+        }
+    }
+
+    /// The inputs and outputs of `transition` which `key` can view, if any.
+    fn view(
+        &self,
+        key: &AuditorKeyPair,
+        transition: &CapeTransition,
+    ) -> Option<(Vec<ViewedRecord>, Vec<ViewedRecord>)> {
+        match transition {
+            CapeTransition::Transaction(CapeModelTxn::CAP(TransactionNote::Transfer(xfr)))
+            | CapeTransition::Transaction(CapeModelTxn::Burn { xfr, .. }) => {
+                self.view_transfer(key, xfr)
+            }
+            CapeTransition::Transaction(CapeModelTxn::CAP(TransactionNote::Mint(mint))) => {
+                let output = key.open_mint_audit_memo(mint).ok()?;
+                Some((vec![], vec![output.into()]))
+            }
+            CapeTransition::Transaction(CapeModelTxn::CAP(TransactionNote::Freeze(_))) => None,
+            CapeTransition::Wrap { ro, .. } => {
+                if ro.asset_def.policy_ref().auditor_pub_key() != &key.pub_key() {
+                    return None;
+                }
+                Some((
+                    vec![],
+                    vec![ViewedRecord {
+                        asset_code: ro.asset_def.code,
+                        address: Some(ro.pub_key.address()),
+                        amount: Some(ro.amount),
+                    }],
+                ))
+            }
+        }
+    }
+
+    /// The records of a transfer, if its audit memo is for an asset viewed by `key`.
+    fn view_transfer(
+        &self,
+        key: &AuditorKeyPair,
+        xfr: &TransferNote,
+    ) -> Option<(Vec<ViewedRecord>, Vec<ViewedRecord>)> {
+        let pub_key = key.pub_key();
+        self.assets
+            .values()
+            .filter(|asset_def| asset_def.policy_ref().auditor_pub_key() == &pub_key)
+            .find_map(|asset_def| key.open_transfer_audit_memo(asset_def, xfr).ok())
+            .map(|(inputs, outputs)| {
+                (
+                    inputs.into_iter().map(ViewedRecord::from).collect(),
+                    outputs.into_iter().map(ViewedRecord::from).collect(),
+                )
+            })
+    }
+}
+
+/// Loads the viewing key pairs, each a bincode-serialized `AuditorKeyPair`, or dies trying.
+pub fn load_viewing_keys(paths: &[PathBuf]) -> Vec<AuditorKeyPair> {
+    paths
+        .iter()
+        .map(|path| {
+            let bytes = read(path).unwrap_or_else(|_| panic!("Unable to read {:?}.", path));
+            bincode::deserialize(&bytes)
+                .unwrap_or_else(|_| panic!("Unable to parse a viewing key pair from {:?}.", path))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use cap_rust_sandbox::ledger::CapeLedger;
+    use cap_rust_sandbox::model::{Erc20Code, EthereumAddr};
+    use jf_cap::freeze::{FreezeNote, FreezeNoteInput};
+    use jf_cap::keys::{FreezerKeyPair, UserKeyPair};
+    use jf_cap::mint::MintNote;
+    use jf_cap::structs::{
+        AssetCodeSeed, AssetPolicy, FeeInput, FreezeFlag, RecordCommitment, RecordOpening,
+        TxnFeeInfo,
+    };
+    use jf_cap::testing_apis::universal_setup_for_test;
+    use jf_cap::transfer::TransferNoteInput;
+    use jf_cap::{AccMemberWitness, MerkleTree};
+    use rand_chacha::rand_core::SeedableRng;
+    use rand_chacha::ChaChaRng;
+    use reef::Ledger;
+
+    fn committed(txn_id: u64, transition: CapeTransition) -> CommittedCapeTransition {
+        CommittedCapeTransition {
+            block_id: 0,
+            txn_id,
+            output_start: 0,
+            output_size: 0,
+            transition,
+        }
+    }
+
+    fn cap(note: TransactionNote) -> CapeTransition {
+        CapeTransition::Transaction(CapeModelTxn::CAP(note))
+    }
+
+    fn assert_viewed(record: &ViewedRecord, expected: &ViewedRecord) {
+        assert_eq!(record.asset_code, expected.asset_code);
+        assert_eq!(record.address, expected.address);
+        assert_eq!(record.amount, expected.amount);
+    }
+
+    #[test]
+    fn test_wrap_viewing() {
+        let mut rng = ChaChaRng::from_seed([0; 32]);
+        let keys = [
+            AuditorKeyPair::generate(&mut rng),
+            AuditorKeyPair::generate(&mut rng),
+        ];
+        let policy = AssetPolicy::default().set_auditor_pub_key(keys[0].pub_key());
+        let asset_def =
+            AssetDefinition::new(AssetCode::new_foreign(b"wrapped token"), policy).unwrap();
+        let owner = UserKeyPair::generate(&mut rng);
+        let ro = RecordOpening::new(
+            &mut rng,
+            10,
+            asset_def.clone(),
+            owner.pub_key(),
+            FreezeFlag::Unfrozen,
+        );
+
+        let mut audit = AuditState::default();
+        for key in &keys {
+            audit.register(key.pub_key());
+        }
+        audit.scan(
+            &keys,
+            &committed(
+                0,
+                CapeTransition::Wrap {
+                    erc20_code: Erc20Code(EthereumAddr([1; 20])),
+                    src_addr: EthereumAddr([2; 20]),
+                    ro: Box::new(ro),
+                },
+            ),
+        );
+
+        // Wrapped records are public, so the viewer of the asset sees them in full, even though
+        // the policy reveals nothing.
+        assert!(audit.assets.contains_key(&asset_def.code));
+        let ledger = &audit.ledgers[&keys[0].pub_key()];
+        assert_eq!(ledger.len(), 1);
+        assert!(ledger[0].inputs.is_empty());
+        assert_eq!(ledger[0].outputs.len(), 1);
+        assert_viewed(
+            &ledger[0].outputs[0],
+            &ViewedRecord {
+                asset_code: asset_def.code,
+                address: Some(owner.address()),
+                amount: Some(10),
+            },
+        );
+        assert!(audit.ledgers[&keys[1].pub_key()].is_empty());
+    }
+
+    #[test]
+    fn test_mint_transfer_freeze_viewing() {
+        let mut rng = ChaChaRng::from_seed([0; 32]);
+        let srs = universal_setup_for_test(2usize.pow(16), &mut rng).unwrap();
+        let height = CapeLedger::merkle_height();
+        let mint_key = jf_cap::proof::mint::preprocess(&srs, height).unwrap().0;
+        let xfr_key = jf_cap::proof::transfer::preprocess(&srs, 2, 2, height)
+            .unwrap()
+            .0;
+        let freeze_key = jf_cap::proof::freeze::preprocess(&srs, 2, height)
+            .unwrap()
+            .0;
+
+        let keys = [
+            AuditorKeyPair::generate(&mut rng),
+            AuditorKeyPair::generate(&mut rng),
+        ];
+        let freezer = FreezerKeyPair::generate(&mut rng);
+        let minter = UserKeyPair::generate(&mut rng);
+        let receiver = UserKeyPair::generate(&mut rng);
+
+        // The policy reveals addresses but not amounts.
+        let seed = AssetCodeSeed::generate(&mut rng);
+        let description = b"viewed asset";
+        let policy = AssetPolicy::default()
+            .set_auditor_pub_key(keys[0].pub_key())
+            .set_freezer_pub_key(freezer.pub_key())
+            .reveal_user_address()
+            .unwrap();
+        let asset_def =
+            AssetDefinition::new(AssetCode::new_domestic(seed, description), policy).unwrap();
+
+        let fee_ro = RecordOpening::new(
+            &mut rng,
+            100,
+            AssetDefinition::native(),
+            minter.pub_key(),
+            FreezeFlag::Unfrozen,
+        );
+        let minted_ro = RecordOpening::new(
+            &mut rng,
+            50,
+            asset_def.clone(),
+            minter.pub_key(),
+            FreezeFlag::Unfrozen,
+        );
+        let mut mt = MerkleTree::new(height).unwrap();
+        mt.push(RecordCommitment::from(&fee_ro).to_field_element());
+        mt.push(RecordCommitment::from(&minted_ro).to_field_element());
+        let witness = |pos| {
+            AccMemberWitness::lookup_from_tree(&mt, pos)
+                .expect_ok()
+                .unwrap()
+                .1
+        };
+        let fee_info = |rng: &mut ChaChaRng| {
+            let fee_input = FeeInput {
+                ro: fee_ro.clone(),
+                acc_member_witness: witness(0),
+                owner_keypair: &minter,
+            };
+            TxnFeeInfo::new(rng, fee_input, 1).unwrap().0
+        };
+
+        let mint = MintNote::generate(
+            &mut rng,
+            minted_ro.clone(),
+            seed,
+            description,
+            fee_info(&mut rng),
+            &mint_key,
+        )
+        .unwrap()
+        .0;
+
+        let output_ro = RecordOpening::new(
+            &mut rng,
+            50,
+            asset_def.clone(),
+            receiver.pub_key(),
+            FreezeFlag::Unfrozen,
+        );
+        let valid_until = 2u64.pow(jf_cap::constants::MAX_TIMESTAMP_LEN as u32) - 1;
+        let transfer = TransferNote::generate_non_native(
+            &mut rng,
+            vec![TransferNoteInput {
+                ro: minted_ro.clone(),
+                acc_member_witness: witness(1),
+                owner_keypair: &minter,
+                cred: None,
+            }],
+            &[output_ro],
+            fee_info(&mut rng),
+            valid_until,
+            &xfr_key,
+            vec![],
+        )
+        .unwrap()
+        .0;
+
+        let freeze = FreezeNote::generate(
+            &mut rng,
+            vec![FreezeNoteInput {
+                ro: minted_ro,
+                acc_member_witness: witness(1),
+                keypair: &freezer,
+            }],
+            fee_info(&mut rng),
+            &freeze_key,
+        )
+        .unwrap()
+        .0;
+
+        let mut audit = AuditState::default();
+        for key in &keys {
+            audit.register(key.pub_key());
+        }
+        audit.scan(
+            &keys,
+            &committed(0, cap(TransactionNote::Mint(Box::new(mint)))),
+        );
+        assert!(audit.assets.contains_key(&asset_def.code));
+        audit.scan(
+            &keys,
+            &committed(1, cap(TransactionNote::Transfer(Box::new(transfer)))),
+        );
+        audit.scan(
+            &keys,
+            &committed(2, cap(TransactionNote::Freeze(Box::new(freeze)))),
+        );
+
+        // Freeze notes have no audit memos, so only the mint and the transfer are viewable.
+        let ledger = &audit.ledgers[&keys[0].pub_key()];
+        let txn_ids: Vec<_> = ledger.iter().map(|txn| txn.txn_id).collect();
+        assert_eq!(txn_ids, vec![0, 1]);
+
+        let revealed = |owner: &UserKeyPair| ViewedRecord {
+            asset_code: asset_def.code,
+            address: Some(owner.address()),
+            amount: None,
+        };
+        let (mint, transfer) = (&ledger[0], &ledger[1]);
+        assert!(mint.inputs.is_empty());
+        assert_eq!(mint.outputs.len(), 1);
+        assert_viewed(&mint.outputs[0], &revealed(&minter));
+        assert_eq!(transfer.inputs.len(), 1);
+        assert_viewed(&transfer.inputs[0], &revealed(&minter));
+        assert_eq!(transfer.outputs.len(), 1);
+        assert_viewed(&transfer.outputs[0], &revealed(&receiver));
+
+        // Another viewing key sees none of it.
+        assert!(audit.ledgers[&keys[1].pub_key()].is_empty());
+    }
+}
@@ -0,0 +1,136 @@
+// Copyright (c) 2022 Espresso Systems (espressosys.com)
+// This file is part of the Configurable Asset Privacy for Ethereum (CAPE) library.
+
+// This program is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+// You should have received a copy of the GNU General Public License along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! # EQS database administration
+//!
+//! Inspects and repairs the persisted state of an EQS without starting it. Stop the EQS first.
+//!
+//! ## Usage
+//! ```
+//! cargo run --release -p eqs --bin eqs-admin -- [--store_path PATH] [--cape_address ADDRESS] COMMAND
+//! ```
+//!
+//! where `COMMAND` is one of
+//! * `summary`: print statistics about the latest state
+//! * `export-events [FILE]`: write the events as JSON lines, to FILE or stdout
+//! * `export-transactions [FILE]`: write the committed transactions as JSON lines
+//! * `verify`: check the stored record Merkle frontier against the stored events
+//! * `truncate BLOCK`: roll the state back to the end of Ethereum block BLOCK, so that the EQS
+//!   syncs again from the next block
+//!
+//! `--cape_address` may be left out if the store holds a single contract.
+
+use eqs::admin::{export_events, export_transactions, summary, truncate, verify};
+use eqs::configuration::default_store_path;
+use eqs::state_persistence::StatePersistence;
+use ethers::prelude::Address;
+use std::fs::{read_dir, File};
+use std::io::{stdout, Write};
+use std::path::PathBuf;
+use std::process::exit;
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "eqs-admin",
+    about = "Inspects and repairs the persisted state of a stopped EQS"
+)]
+struct Options {
+    /// Path to persistence files, as given to the EQS.
+    #[structopt(long = "store_path", short = "s")]
+    store_path: Option<PathBuf>,
+
+    /// Address of the contract whose state to open.
+    #[structopt(long = "cape_address", env = "CAPE_ADDRESS")]
+    cape_address: Option<Address>,
+
+    #[structopt(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, StructOpt)]
+enum Command {
+    /// Print statistics about the latest state.
+    Summary,
+    /// Write the events as JSON lines.
+    ExportEvents { out: Option<PathBuf> },
+    /// Write the committed transactions as JSON lines.
+    ExportTransactions { out: Option<PathBuf> },
+    /// Check the stored record Merkle frontier against the stored events.
+    Verify,
+    /// Roll the state back to the end of an Ethereum block.
+    Truncate { block_height: u64 },
+}
+
+fn fail(msg: impl std::fmt::Display) -> ! {
+    eprintln!("{}", msg);
+    exit(1)
+}
+
+/// The directory of the contract to open: the given one, or else the only one in the store.
+fn contract_dir(store_path: PathBuf, cape_address: Option<Address>) -> PathBuf {
+    if let Some(address) = cape_address {
+        return store_path.join(format!("{:?}", address));
+    }
+    let dirs: Vec<_> = read_dir(&store_path)
+        .unwrap_or_else(|err| fail(format!("Unable to read {:?}: {}", store_path, err)))
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.join("eqs").is_dir())
+        .collect();
+    match dirs.as_slice() {
+        [dir] => dir.clone(),
+        [] => fail(format!("No EQS state in {:?}.", store_path)),
+        _ => fail(format!(
+            "{:?} holds the state of several contracts; choose one with --cape_address.",
+            store_path
+        )),
+    }
+}
+
+fn output(out: Option<PathBuf>) -> Box<dyn Write> {
+    match out {
+        Some(path) => Box::new(
+            File::create(&path)
+                .unwrap_or_else(|err| fail(format!("Unable to create {:?}: {}", path, err))),
+        ),
+        None => Box::new(stdout()),
+    }
+}
+
+fn main() -> std::io::Result<()> {
+    let opt = Options::from_args();
+    let dir = contract_dir(
+        opt.store_path.unwrap_or_else(default_store_path),
+        opt.cape_address,
+    );
+    let mut persistence = StatePersistence::load(&dir, "eqs")
+        .unwrap_or_else(|err| fail(format!("Unable to open the store in {:?}: {}", dir, err)));
+    let state = persistence
+        .load_latest_state()
+        .unwrap_or_else(|err| fail(format!("Unable to load the latest state: {}", err)));
+
+    match opt.command {
+        Command::Summary => print!("{}", summary(&persistence, &state)),
+        Command::ExportEvents { out } => export_events(&state, &mut output(out))?,
+        Command::ExportTransactions { out } => export_transactions(&state, &mut output(out))?,
+        Command::Verify => match verify(&state) {
+            Ok(()) => println!("The record Merkle frontier matches the events."),
+            Err(err) => fail(err),
+        },
+        Command::Truncate { block_height } => match truncate(&mut persistence, block_height) {
+            Ok(state) => println!(
+                "Truncated to block {} ({} events); the EQS will sync from block {}.",
+                state.last_updated_block_height,
+                state.num_events(),
+                state.last_updated_block_height + 1
+            ),
+            Err(err) => fail(err),
+        },
+    }
+    Ok(())
+}
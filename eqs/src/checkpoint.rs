@@ -0,0 +1,255 @@
+// Copyright (c) 2022 Espresso Systems (espressosys.com)
+// This file is part of the Configurable Asset Privacy for Ethereum (CAPE) library.
+
+// This program is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+// You should have received a copy of the GNU General Public License along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Signed checkpoints of the state of a contract, for bootstrapping new EQS replicas and wallets.
+//!
+//! An EQS given an operator key (`--checkpoint_mnemonic`) writes a checkpoint of each contract
+//! every `--checkpoint_interval` Ethereum blocks, to `<store_path>/<contract>/checkpoints/`, and
+//! serves the signed summary of the latest one at `get_checkpoint`. Checkpoints are only taken
+//! when the record Merkle root of the state matches the contract, and when no wraps are waiting
+//! to be committed, so that the ledger state, nullifiers and sponsored assets are all there is to
+//! the state of the contract.
+//!
+//! A fresh EQS started with `--bootstrap_checkpoint` checks the checkpoint against its signer
+//! (`--checkpoint_signer`), its contents and the record Merkle root of the contract, and then
+//! syncs only the blocks after it. It has none of the events or history from before the
+//! checkpoint; the events it has keep their indexes in the full event log.
+
+use crate::query_result_state::QueryResultState;
+use cap_rust_sandbox::ethereum::EthConnection;
+use cap_rust_sandbox::helpers::compare_merkle_root_from_contract_and_jf_tree;
+use cap_rust_sandbox::model::{CapeLedgerState, Erc20Code, EthereumAddr};
+use ethers::prelude::{Address, BlockNumber, LocalWallet, Signature, Signer};
+use ethers::utils::keccak256;
+use jf_cap::structs::{AssetDefinition, Nullifier};
+use key_set::VerifierKeySet;
+use net::TaggedBlob;
+use serde::{Deserialize, Serialize};
+use std::fs::{create_dir_all, read, write};
+use std::path::{Path, PathBuf};
+
+/// What a checkpoint vouches for.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CheckpointSummary {
+    pub contract: Address,
+    /// Every event up to and including this Ethereum block is included.
+    pub block_height: u64,
+    pub ledger_state: CapeLedgerState,
+    /// Length of the full event log.
+    pub num_events: u64,
+    /// See [nullifier_digest].
+    pub nullifier_digest: [u8; 32],
+    /// See [sponsored_assets_digest].
+    pub sponsored_assets_digest: [u8; 32],
+}
+
+impl CheckpointSummary {
+    /// The message signed by the operator: the keccak256 hash of the bincode serialization of the
+    /// summary. It is signed as an Ethereum message (EIP-191).
+    pub fn message(&self) -> Vec<u8> {
+        keccak256(bincode::serialize(self).unwrap()).to_vec()
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SignedCheckpoint {
+    pub summary: CheckpointSummary,
+    pub signature: Signature,
+}
+
+impl SignedCheckpoint {
+    pub fn verify(&self, signer: Address) -> Result<(), String> {
+        self.signature
+            .verify(self.summary.message(), signer)
+            .map_err(|err| format!("checkpoint not signed by {:?}: {}", signer, err))
+    }
+}
+
+/// A sponsored asset, with the ERC-20 token it wraps and its sponsor.
+pub type SponsoredAsset = (AssetDefinition, Erc20Code, EthereumAddr);
+
+/// The contents of a checkpoint file.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub signed: SignedCheckpoint,
+    /// Spent nullifiers, in the order they were committed.
+    pub nullifiers: Vec<Nullifier>,
+    pub sponsored_assets: Vec<SponsoredAsset>,
+}
+
+/// The keccak256 hash of the concatenated bytes of the nullifiers, in the order they were
+/// committed (the order of `get_nullifiers`). The bytes of a nullifier are the ones encoded in its
+/// TaggedBase64 form.
+pub fn nullifier_digest(nullifiers: &[Nullifier]) -> [u8; 32] {
+    let mut bytes = Vec::new();
+    for nullifier in nullifiers {
+        bytes.extend(nullifier.tagged_blob().value());
+    }
+    keccak256(bytes)
+}
+
+/// The keccak256 hash of the bincode serialization of the sponsored assets, sorted by asset code.
+pub fn sponsored_assets_digest(sponsored_assets: &[SponsoredAsset]) -> [u8; 32] {
+    keccak256(bincode::serialize(sponsored_assets).unwrap())
+}
+
+fn sponsored_assets(query_result_state: &QueryResultState) -> Vec<SponsoredAsset> {
+    let mut assets: Vec<_> = query_result_state
+        .erc20_registrar
+        .iter()
+        .map(|(asset_def, (erc20_code, sponsor))| {
+            (asset_def.clone(), erc20_code.clone(), sponsor.clone())
+        })
+        .collect();
+    assets.sort_by_key(|(asset_def, _, _)| asset_def.code.to_string());
+    assets
+}
+
+impl Checkpoint {
+    /// A checkpoint of `query_result_state`, which is synced up to `block_height`.
+    pub async fn sign(
+        query_result_state: &QueryResultState,
+        contract: Address,
+        block_height: u64,
+        operator: &LocalWallet,
+    ) -> Result<Self, String> {
+        let nullifiers = query_result_state.nullifier_list.clone();
+        let sponsored_assets = sponsored_assets(query_result_state);
+        let summary = CheckpointSummary {
+            contract,
+            block_height,
+            ledger_state: query_result_state.ledger_state.clone(),
+            num_events: query_result_state.num_events(),
+            nullifier_digest: nullifier_digest(&nullifiers),
+            sponsored_assets_digest: sponsored_assets_digest(&sponsored_assets),
+        };
+        let signature = operator
+            .sign_message(summary.message())
+            .await
+            .map_err(|err| format!("failed to sign checkpoint: {}", err))?;
+        Ok(Self {
+            signed: SignedCheckpoint { summary, signature },
+            nullifiers,
+            sponsored_assets,
+        })
+    }
+
+    /// Check that the checkpoint was signed by `signer`, and that its contents match the digests.
+    pub fn verify(&self, signer: Address) -> Result<(), String> {
+        self.signed.verify(signer)?;
+        let summary = &self.signed.summary;
+        if nullifier_digest(&self.nullifiers) != summary.nullifier_digest {
+            return Err(String::from(
+                "checkpoint nullifiers do not match their digest",
+            ));
+        }
+        if sponsored_assets_digest(&self.sponsored_assets) != summary.sponsored_assets_digest {
+            return Err(String::from(
+                "checkpoint sponsored assets do not match their digest",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Path of the checkpoint of the state at `block_height` in `dir`.
+    pub fn path(dir: &Path, block_height: u64) -> PathBuf {
+        dir.join(format!("{}.bin", block_height))
+    }
+
+    /// Write the checkpoint, bincode-serialized, to `dir`.
+    pub fn store(&self, dir: &Path) -> std::io::Result<PathBuf> {
+        create_dir_all(dir)?;
+        let path = Self::path(dir, self.signed.summary.block_height);
+        write(&path, bincode::serialize(self).unwrap())?;
+        Ok(path)
+    }
+
+    /// Loads a checkpoint file or dies trying.
+    pub fn load(path: &Path) -> Self {
+        let bytes = read(path).unwrap_or_else(|_| panic!("Unable to read {:?}.", path));
+        bincode::deserialize(&bytes)
+            .unwrap_or_else(|_| panic!("Unable to parse a checkpoint from {:?}.", path))
+    }
+
+    /// The state of the contract at the checkpoint.
+    pub fn into_state(self, verifier_keys: VerifierKeySet) -> QueryResultState {
+        let summary = self.signed.summary.clone();
+        let mut state = QueryResultState::new(verifier_keys);
+        state.ledger_state = summary.ledger_state;
+        for nullifier in self.nullifiers {
+            state.insert_nullifier(nullifier);
+        }
+        for (asset_def, erc20_code, sponsor) in self.sponsored_assets {
+            state.audit.learn_asset(&asset_def);
+            // Withdrawals of assets deposited before the checkpoint are attributed through the
+            // registry.
+            state
+                .wrapped_erc20
+                .insert(asset_def.code, erc20_code.clone());
+            state
+                .erc20_registrar
+                .insert(asset_def, (erc20_code, sponsor));
+        }
+        state.first_event = summary.num_events;
+        state.contract_address = Some(summary.contract);
+        state.last_updated_block_height = summary.block_height;
+        state.head_block_height = summary.block_height;
+        state.last_checkpoint = Some(self.signed);
+        state
+    }
+}
+
+/// The state of the contract of `connection` at `checkpoint`, loaded from `path`, which must be
+/// signed by `signer`. Dies if the checkpoint is not valid for the contract.
+pub async fn bootstrap(
+    connection: &EthConnection,
+    path: &Path,
+    checkpoint: Checkpoint,
+    signer: Address,
+    verifier_keys: VerifierKeySet,
+) -> QueryResultState {
+    if let Err(err) = checkpoint.verify(signer) {
+        panic!("Invalid checkpoint {:?}: {}", path, err);
+    }
+    let summary = &checkpoint.signed.summary;
+    if summary.contract != connection.contract.address() {
+        panic!(
+            "Checkpoint {:?} is for contract {:?}, not {:?}.",
+            path,
+            summary.contract,
+            connection.contract.address()
+        );
+    }
+    let contract_root = connection
+        .contract
+        .get_root_value()
+        .block(BlockNumber::Number(summary.block_height.into()))
+        .call()
+        .await
+        .unwrap_or_else(|err| {
+            panic!(
+                "Unable to get the record Merkle root at block {} to check checkpoint {:?}; \
+                 use an archive node or a more recent checkpoint: {}",
+                summary.block_height, path, err
+            )
+        });
+    if !compare_merkle_root_from_contract_and_jf_tree(
+        contract_root,
+        summary.ledger_state.record_merkle_commitment.root_value,
+    ) {
+        panic!(
+            "The record Merkle root of checkpoint {:?} does not match the contract.",
+            path
+        );
+    }
+    tracing::info!(
+        "Bootstrapping contract {:?} from the checkpoint at block {}",
+        summary.contract,
+        summary.block_height
+    );
+    checkpoint.into_state(verifier_keys)
+}
@@ -7,7 +7,7 @@
 
 use cap_rust_sandbox::{model::CAPE_MERKLE_HEIGHT, universal_param::UNIVERSAL_PARAM};
 use dirs::data_local_dir;
-use ethers::prelude::Address;
+use ethers::prelude::{coins_bip39::English, Address, LocalWallet, MnemonicBuilder};
 use jf_cap::TransactionVerifyingKey;
 use key_set::{KeySet, VerifierKeySet};
 use std::{env, path::PathBuf, time::Duration};
@@ -30,7 +30,10 @@ pub struct EQSOptions {
 
     /// Path to persistence files.
     ///
-    /// Persistence files will be nested under the specified directory
+    /// Persistence files will be nested under the specified directory, in a subdirectory named
+    /// after the address of each contract. A state persisted directly under this directory by an
+    /// older EQS is not migrated: the contract is synced again from its deployment block, as with
+    /// --reset_store_state, and the old `eqs` subdirectory can be deleted.
     #[structopt(long = "store_path", short = "s", default_value = "")]
     pub store_path: String,
 
@@ -42,9 +45,49 @@ pub struct EQSOptions {
     )]
     pub rpc_url: String,
 
-    /// Address for CAPE contract
-    #[structopt(long = "cape_address", env = "CAPE_ADDRESS")]
-    pub cape_address: Option<Address>,
+    /// URL for Ethers WebSocket Provider
+    ///
+    /// If specified, contract events are followed with an `eth_subscribe` subscription;
+    /// otherwise the EQS polls the HTTP provider every `query_frequency` milliseconds.
+    #[structopt(long = "ws_url", env = "WS_URL")]
+    pub ws_url: Option<String>,
+
+    /// Block in which the CAPE contract was deployed.
+    ///
+    /// A fresh EQS starts syncing from this block instead of from genesis. When indexing several
+    /// contracts, use the block of the earliest deployment.
+    #[structopt(
+        long = "deployment_block",
+        env = "CAPE_DEPLOYMENT_BLOCK",
+        default_value = "0"
+    )]
+    pub deployment_block: u64,
+
+    /// Maximum number of blocks covered by a single query for contract events.
+    ///
+    /// Syncing proceeds in chunks of this size, and progress is persisted after each chunk.
+    #[structopt(long = "max_block_range", default_value = "5000")]
+    pub max_block_range: u64,
+
+    /// Number of committed blocks whose ledger state is kept for `get_cap_state/:block_id`.
+    ///
+    /// The ledger state of older blocks is discarded, so that the state does not grow by a record
+    /// Merkle frontier with every block.
+    #[structopt(long = "ledger_history", default_value = "1000")]
+    pub ledger_history: usize,
+
+    /// Addresses of the CAPE contracts to index.
+    ///
+    /// Repeat the option, or separate addresses with commas, to index several contracts. Each
+    /// contract has its own state and persistence files, and its routes are served under a
+    /// `/<address>/` prefix; the first contract is also served without a prefix.
+    #[structopt(
+        long = "cape_address",
+        env = "CAPE_ADDRESS",
+        use_delimiter = true,
+        number_of_values = 1
+    )]
+    pub cape_address: Vec<Address>,
 
     /// Invoke as a test-only instance; will create and use test contract
     /// Will also use a temp persistence path and not restore history
@@ -59,6 +102,68 @@ pub struct EQSOptions {
     #[structopt(long = "query_frequency", default_value = "500")]
     pub query_frequency: u64,
 
+    /// Files with the key pairs of asset viewers to scan transactions for.
+    ///
+    /// Each file holds a bincode-serialized `AuditorKeyPair`. The audit memos of committed
+    /// transactions are opened with each key, and what the key can view is served at
+    /// `get_viewed_transactions/:viewing_key`, but only to clients with the --viewing_token. A key
+    /// added to an existing state only sees transactions committed from then on; use
+    /// --reset_store_state to scan the whole history.
+    #[structopt(long = "viewing_key_file", use_delimiter = true, number_of_values = 1)]
+    pub viewing_key_file: Vec<PathBuf>,
+
+    /// Bearer token required to read what the viewing keys can view.
+    ///
+    /// Requests to `get_viewed_transactions` must have an `Authorization: Bearer <token>` header
+    /// with this token. Without this option, `get_viewed_transactions` is disabled.
+    #[structopt(long = "viewing_token", env = "EQS_VIEWING_TOKEN")]
+    pub viewing_token: Option<String>,
+
+    /// Mnemonic of the operator key which signs checkpoints.
+    ///
+    /// If specified, a signed checkpoint of each contract is written to
+    /// `<store_path>/<contract>/checkpoints/` every `checkpoint_interval` blocks.
+    #[structopt(long = "checkpoint_mnemonic", env = "CHECKPOINT_MNEMONIC")]
+    pub checkpoint_mnemonic: Option<String>,
+
+    /// Number of Ethereum blocks between checkpoints.
+    #[structopt(long = "checkpoint_interval", default_value = "1000")]
+    pub checkpoint_interval: u64,
+
+    /// Checkpoint files to start from instead of syncing from the deployment of the contracts.
+    ///
+    /// Repeat the option, or separate paths with commas, to give a checkpoint for each of several
+    /// contracts. A contract with a checkpoint starts from it, discarding any persisted state, as
+    /// with --reset_store_state. Requires --checkpoint_signer.
+    #[structopt(
+        long = "bootstrap_checkpoint",
+        use_delimiter = true,
+        number_of_values = 1
+    )]
+    pub bootstrap_checkpoint: Vec<PathBuf>,
+
+    /// Address of the operator key which must have signed the bootstrap checkpoints.
+    #[structopt(long = "checkpoint_signer", env = "CHECKPOINT_SIGNER")]
+    pub checkpoint_signer: Option<Address>,
+
+    /// Maximum number of blocks the EQS can be behind the head of the chain and still be ready.
+    ///
+    /// `/readyz` fails while any contract is further behind than this.
+    #[structopt(long = "max_ready_lag", default_value = "5")]
+    pub max_ready_lag: u64,
+
+    /// Origins allowed to make cross-origin requests from a browser.
+    ///
+    /// Repeat the option, or separate origins with commas, to allow several; `*` allows any
+    /// origin. Without this option, no CORS headers are sent.
+    #[structopt(
+        long = "cors_origin",
+        env = "EQS_CORS_ORIGIN",
+        use_delimiter = true,
+        number_of_values = 1
+    )]
+    pub cors_origin: Vec<String>,
+
     // Ethereum connection is specified by env variable.
     /// Web service port .
     #[structopt(long = "eqs_port", default_value = "50087")]
@@ -73,6 +178,13 @@ fn default_data_path() -> PathBuf {
     data_dir
 }
 
+/// Where persistence files are stored unless `--store_path` is given.
+pub fn default_store_path() -> PathBuf {
+    let mut default_store_path = default_data_path();
+    default_store_path.push("store");
+    default_store_path
+}
+
 impl EQSOptions {
     pub fn web_path(&self) -> PathBuf {
         let web_path = &self.web_path;
@@ -101,9 +213,7 @@ impl EQSOptions {
     pub(crate) fn store_path(&self) -> PathBuf {
         let store_path = &self.store_path;
         if store_path.is_empty() {
-            let mut default_store_path = default_data_path();
-            default_store_path.push("store");
-            default_store_path
+            default_store_path()
         } else {
             PathBuf::from(store_path)
         }
@@ -113,8 +223,54 @@ impl EQSOptions {
         &self.rpc_url
     }
 
-    pub(crate) fn cape_address(&self) -> Option<Address> {
-        self.cape_address
+    pub(crate) fn ws_url(&self) -> Option<&str> {
+        self.ws_url.as_deref()
+    }
+
+    pub(crate) fn deployment_block(&self) -> u64 {
+        self.deployment_block
+    }
+
+    pub(crate) fn max_block_range(&self) -> u64 {
+        self.max_block_range
+    }
+
+    pub(crate) fn ledger_history(&self) -> usize {
+        self.ledger_history
+    }
+
+    pub(crate) fn cape_addresses(&self) -> &[Address] {
+        &self.cape_address
+    }
+
+    pub(crate) fn viewing_key_files(&self) -> &[PathBuf] {
+        &self.viewing_key_file
+    }
+
+    pub(crate) fn viewing_token(&self) -> Option<&str> {
+        self.viewing_token.as_deref()
+    }
+
+    /// The operator key which signs checkpoints, if checkpoints are enabled.
+    pub(crate) fn checkpoint_operator(&self) -> Option<LocalWallet> {
+        self.checkpoint_mnemonic.as_ref().map(|mnemonic| {
+            MnemonicBuilder::<English>::default()
+                .phrase(mnemonic.as_str())
+                .build()
+                .expect("could not open checkpoint operator wallet")
+        })
+    }
+
+    pub(crate) fn checkpoint_interval(&self) -> u64 {
+        self.checkpoint_interval
+    }
+
+    pub(crate) fn bootstrap_checkpoints(&self) -> &[PathBuf] {
+        &self.bootstrap_checkpoint
+    }
+
+    pub(crate) fn checkpoint_signer(&self) -> Option<Address> {
+        self.checkpoint_signer
     }
 
     pub(crate) fn temp_test_run(&self) -> bool {
@@ -157,6 +313,14 @@ impl EQSOptions {
         Duration::from_millis(self.query_frequency)
     }
 
+    pub(crate) fn max_ready_lag(&self) -> u64 {
+        self.max_ready_lag
+    }
+
+    pub(crate) fn cors_origins(&self) -> &[String] {
+        &self.cors_origin
+    }
+
     pub(crate) fn eqs_port(&self) -> u16 {
         self.eqs_port
     }
@@ -5,7 +5,7 @@
 // This program is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
 // You should have received a copy of the GNU General Public License along with this program. If not, see <https://www.gnu.org/licenses/>.
 
-use crate::{api_server::WebState, routes::check_api};
+use crate::api_server::WebState;
 use std::fs::read_to_string;
 use std::path::Path;
 
@@ -14,7 +14,6 @@ pub fn load_messages(path: &Path) -> toml::Value {
     let messages = read_to_string(&path).unwrap_or_else(|_| panic!("Unable to read {:?}.", &path));
     let api: toml::Value =
         toml::from_str(&messages).unwrap_or_else(|_| panic!("Unable to parse {:?}.", &path));
-    check_api(api.clone());
     api
 }
 
@@ -102,3 +101,14 @@ pub async fn compose_help(req: tide::Request<WebState>) -> Result<tide::Response
         .body(help)
         .build())
 }
+
+/// Serve the OpenAPI document describing the routes in `api.toml`.
+///
+/// Like [compose_help], this is recomputed on every request.
+pub async fn compose_openapi(req: tide::Request<WebState>) -> Result<tide::Response, tide::Error> {
+    let document = crate::openapi::openapi_document(&req.state().api);
+    Ok(tide::Response::builder(200)
+        .content_type(tide::http::mime::JSON)
+        .body(tide::Body::from_json(&document)?)
+        .build())
+}
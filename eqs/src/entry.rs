@@ -6,37 +6,246 @@
 // You should have received a copy of the GNU General Public License along with this program. If not, see <https://www.gnu.org/licenses/>.
 
 use crate::api_server::init_web_server;
+use crate::checkpoint::{bootstrap, Checkpoint};
 use crate::configuration::EQSOptions;
 use crate::eth_polling::EthPolling;
+use crate::metrics::{ContractMetrics, Metrics};
 use crate::query_result_state::QueryResultState;
 use crate::state_persistence::StatePersistence;
 
 use async_std::{
     sync::{Arc, RwLock},
-    task::sleep,
+    task::{sleep, spawn},
 };
+use cap_rust_sandbox::ethereum::EthConnection;
+use ethers::prelude::Address;
+use std::collections::{HashMap, HashSet};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
 
-pub async fn run(opt: &EQSOptions) -> std::io::Result<()> {
-    let (state_persistence, query_result_state) = if opt.reset_state() {
-        (
-            StatePersistence::new(&opt.store_path(), "eqs").unwrap(),
-            Arc::new(RwLock::new(QueryResultState::new(opt.verifier_keys()))),
-        )
-    } else {
-        let state_persistence = StatePersistence::load(&opt.store_path(), "eqs").unwrap();
-        let query_result_state =
-            Arc::new(RwLock::new(state_persistence.load_latest_state().unwrap()));
-        (state_persistence, query_result_state)
-    };
-
-    let _api_handle = init_web_server(opt, query_result_state.clone()).unwrap();
+/// Connect to the contracts to index: the configured ones, or a freshly deployed test contract.
+async fn connect(opt: &EQSOptions) -> Vec<EthConnection> {
+    if opt.temp_test_run() {
+        return vec![EthConnection::for_test().await];
+    }
+    if opt.cape_addresses().is_empty() {
+        panic!("Invocation Error! Address required unless launched for testing");
+    }
+    let mut addresses = HashSet::new();
+    for address in opt.cape_addresses() {
+        if !addresses.insert(address) {
+            panic!(
+                "Invocation Error! CAPE contract {:?} is given more than once",
+                address
+            );
+        }
+    }
+    opt.cape_addresses()
+        .iter()
+        .map(|address| {
+            EthConnection::from_config_for_query(&format!("{:?}", address), opt.rpc_url())
+        })
+        .collect()
+}
 
-    // will replace with subscription in phase 3
-    let mut eth_poll = EthPolling::new(opt, query_result_state, state_persistence).await;
+/// The directory in which the state of `contract` is persisted.
+///
+/// Each contract is persisted in its own directory under the store path. A state persisted
+/// directly under the store path, by an EQS from before there was one directory per contract, is
+/// not used.
+fn contract_store_path(store_path: &Path, contract: Address) -> PathBuf {
+    // StatePersistence keeps its files in an `eqs` subdirectory of the path it is given.
+    let legacy_path = store_path.join("eqs");
+    if legacy_path.is_dir() {
+        tracing::warn!(
+            "Ignoring the persisted state in {:?}, which predates per-contract directories; \
+             delete it once {:?} is synced",
+            legacy_path,
+            contract
+        );
+    }
+    store_path.join(format!("{:?}", contract))
+}
 
+/// Keep the state of one contract up to date, until it fails an integrity check.
+async fn follow(mut eth_poll: EthPolling, query_frequency: Duration, poll_errors: Arc<AtomicU64>) {
     loop {
-        if let Ok(_height) = eth_poll.check().await {}
+        if let Some(failure) = &eth_poll.query_result_state.read().await.integrity_failure {
+            tracing::error!(
+                "Stopped following contract {:?}: {}. Rebuild it with --reset_store_state.",
+                eth_poll.contract_address(),
+                failure
+            );
+            return;
+        }
+        // Returns once the subscription drops; the next pass backfills whatever was missed while
+        // disconnected before subscribing again.
+        let subscribed = match eth_poll.ws_url.clone() {
+            Some(ws_url) => match eth_poll.subscribe(&ws_url).await {
+                Ok(()) => true,
+                Err(err) => {
+                    poll_errors.fetch_add(1, Ordering::Relaxed);
+                    tracing::warn!(
+                        "Contract event subscription for {:?} failed: {}",
+                        eth_poll.contract_address(),
+                        err
+                    );
+                    // If the subscription could not even be opened, poll over HTTP for this pass
+                    // and try to subscribe again on the next one.
+                    err.kind() != ErrorKind::NotConnected
+                }
+            },
+            None => false,
+        };
+        if !subscribed {
+            if let Err(err) = eth_poll.check().await {
+                poll_errors.fetch_add(1, Ordering::Relaxed);
+                tracing::warn!(
+                    "Polling for events of {:?} failed: {}",
+                    eth_poll.contract_address(),
+                    err
+                );
+            }
+        }
         // sleep here
-        sleep(opt.query_frequency()).await;
+        sleep(query_frequency).await;
+    }
+}
+
+pub async fn run(opt: &EQSOptions) -> std::io::Result<()> {
+    let mut checkpoints: HashMap<_, _> = opt
+        .bootstrap_checkpoints()
+        .iter()
+        .map(|path| {
+            let checkpoint = Checkpoint::load(path);
+            (checkpoint.signed.summary.contract, (path, checkpoint))
+        })
+        .collect();
+    let mut contracts = Vec::new();
+    let mut metrics = HashMap::new();
+    let mut pollers = Vec::new();
+    let connections = connect(opt).await;
+    for connection in connections {
+        let contract_address = connection.contract.address();
+        let store_path = contract_store_path(&opt.store_path(), contract_address);
+        let (state_persistence, query_result_state) = if let Some((path, checkpoint)) =
+            checkpoints.remove(&contract_address)
+        {
+            let signer = opt
+                .checkpoint_signer()
+                .expect("Invocation Error! --checkpoint_signer is required to bootstrap");
+            let state = bootstrap(&connection, path, checkpoint, signer, opt.verifier_keys()).await;
+            (
+                StatePersistence::new(&store_path, "eqs").unwrap(),
+                Arc::new(RwLock::new(state)),
+            )
+        } else if opt.reset_state() || !store_path.join("eqs").exists() {
+            if !opt.reset_state() {
+                tracing::info!(
+                    "No persisted state for {:?}; syncing from the deployment block",
+                    contract_address
+                );
+            }
+            (
+                StatePersistence::new(&store_path, "eqs").unwrap(),
+                Arc::new(RwLock::new(QueryResultState::new(opt.verifier_keys()))),
+            )
+        } else {
+            let state_persistence = StatePersistence::load(&store_path, "eqs").unwrap();
+            let query_result_state =
+                Arc::new(RwLock::new(state_persistence.load_latest_state().unwrap()));
+            (state_persistence, query_result_state)
+        };
+        contracts.push((contract_address, query_result_state.clone()));
+        metrics.insert(
+            contract_address,
+            ContractMetrics {
+                poll_errors: Default::default(),
+                persistence: state_persistence.stats(),
+            },
+        );
+        pollers.push(EthPolling::new(opt, connection, query_result_state, state_persistence).await);
+    }
+
+    if let Some((path, _)) = checkpoints.values().next() {
+        panic!(
+            "Checkpoint {:?} is not for any of the indexed contracts.",
+            path
+        );
+    }
+
+    // The first contract is also served without a contract prefix.
+    let default_contract = contracts[0].0;
+    let contracts: HashMap<_, _> = contracts.into_iter().collect();
+    let metrics = Arc::new(Metrics::new(metrics, opt.max_ready_lag()));
+    let _api_handle =
+        init_web_server(opt, Arc::new(contracts), default_contract, metrics.clone()).unwrap();
+
+    let followers: Vec<_> = pollers
+        .into_iter()
+        .map(|eth_poll| {
+            let poll_errors = metrics.contracts[&eth_poll.contract_address()]
+                .poll_errors
+                .clone();
+            spawn(follow(eth_poll, opt.query_frequency(), poll_errors))
+        })
+        .collect();
+    for follower in followers {
+        follower.await;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use jf_cap::keys::UserKeyPair;
+    use rand_chacha::{rand_core::SeedableRng, ChaChaRng};
+    use structopt::StructOpt;
+    use tempdir::TempDir;
+
+    #[async_std::test]
+    async fn test_follow_unreachable_ws_url() {
+        let mut rng = ChaChaRng::from_seed([7; 32]);
+        let connection = EthConnection::for_test().await;
+        let faucet_manager = UserKeyPair::generate(&mut rng);
+        connection
+            .test_contract()
+            .faucet_setup_for_testnet(faucet_manager.address().into())
+            .send()
+            .await
+            .unwrap()
+            .await
+            .unwrap();
+
+        // Nothing listens on port 1, so the subscription can never be opened.
+        let opt = EQSOptions::from_iter(["eqs", "--temp_test_run", "--ws_url", "ws://127.0.0.1:1"]);
+        let dir = TempDir::new("eqs_follow").unwrap();
+        let state = Arc::new(RwLock::new(QueryResultState::new(opt.verifier_keys())));
+        let eth_poll = EthPolling::new(
+            &opt,
+            connection,
+            state.clone(),
+            StatePersistence::new(dir.path(), "eqs").unwrap(),
+        )
+        .await;
+        let poll_errors = Arc::new(AtomicU64::new(0));
+        spawn(follow(
+            eth_poll,
+            Duration::from_millis(100),
+            poll_errors.clone(),
+        ));
+
+        // The faucet memo is still indexed by polling over HTTP.
+        for _ in 0..100 {
+            if !state.read().await.events.is_empty() {
+                break;
+            }
+            sleep(Duration::from_millis(100)).await;
+        }
+        assert_eq!(state.read().await.events.len(), 1);
+        assert!(poll_errors.load(Ordering::Relaxed) > 0);
     }
 }
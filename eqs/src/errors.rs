@@ -18,6 +18,40 @@ pub enum EQSNetError {
     #[snafu(display("invalid TaggedBase64 tag: expected {}, got {}", expected, actual))]
     Tag { expected: String, actual: String },
 
+    #[snafu(display("invalid value {} for route parameter {}: {}", value, param, msg))]
+    RouteParam {
+        param: String,
+        value: String,
+        msg: String,
+    },
+
+    #[snafu(display("this EQS does not index a CAPE contract at {}", contract))]
+    UnknownContract { contract: String },
+
+    #[snafu(display("this EQS does not scan for viewing key {}", key))]
+    UnknownViewingKey { key: String },
+
+    #[snafu(display("viewed transactions are not served by this EQS"))]
+    ViewingDisabled {},
+
+    #[snafu(display("a valid viewing token is required"))]
+    Unauthorized {},
+
+    #[snafu(display("the state of this contract failed an integrity check: {}", msg))]
+    IntegrityFailure { msg: String },
+
+    #[snafu(display(
+        "events before {} are not available from this EQS; it was bootstrapped from a checkpoint",
+        first_available
+    ))]
+    EventsUnavailable { first: u64, first_available: u64 },
+
+    #[snafu(display("a request may hold at most {} items, got {}", max, size))]
+    BatchTooLarge { size: usize, max: usize },
+
+    #[snafu(display("a request body may be at most {} bytes", max))]
+    BodyTooLarge { max: usize },
+
     #[snafu(display("failed to deserialize request parameter: {}", msg))]
     Deserialize { msg: String },
 
@@ -31,9 +65,17 @@ impl net::Error for EQSNetError {
     }
     fn status(&self) -> StatusCode {
         match self {
-            Self::Param { .. } | Self::Tag { .. } | Self::Deserialize { .. } => {
-                StatusCode::BadRequest
-            }
+            Self::Param { .. }
+            | Self::RouteParam { .. }
+            | Self::Tag { .. }
+            | Self::BatchTooLarge { .. }
+            | Self::Deserialize { .. } => StatusCode::BadRequest,
+            Self::UnknownContract { .. } | Self::UnknownViewingKey { .. } => StatusCode::NotFound,
+            Self::ViewingDisabled { .. } => StatusCode::Forbidden,
+            Self::BodyTooLarge { .. } => StatusCode::PayloadTooLarge,
+            Self::Unauthorized { .. } => StatusCode::Unauthorized,
+            Self::EventsUnavailable { .. } => StatusCode::Gone,
+            Self::IntegrityFailure { .. } => StatusCode::ServiceUnavailable,
             Self::Internal { .. } => StatusCode::InternalServerError,
         }
     }
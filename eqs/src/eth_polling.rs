@@ -5,63 +5,128 @@
 // This program is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
 // You should have received a copy of the GNU General Public License along with this program. If not, see <https://www.gnu.org/licenses/>.
 
+use crate::audit::load_viewing_keys;
+use crate::checkpoint::Checkpoint;
 use crate::configuration::EQSOptions;
-use crate::query_result_state::QueryResultState;
+use crate::query_result_state::{BlockInfo, LedgerSnapshot, QueryResultState};
 use crate::state_persistence::StatePersistence;
 
+use async_std::io::{Error, ErrorKind};
+use async_std::stream::StreamExt;
 use async_std::sync::{Arc, RwLock};
 use cap_rust_sandbox::{
     cape::submit_block::fetch_cape_block,
     ethereum::EthConnection,
-    ledger::CapeTransition,
+    helpers::compare_merkle_root_from_contract_and_jf_tree,
+    ledger::{CapeTransition, CommittedCapeTransition},
     model::{CapeModelTxn, Erc20Code, EthereumAddr},
-    types::{CAPEEvents, RecordOpening as RecordOpeningSol},
+    types::{AssetDefinition as AssetDefinitionSol, CAPEEvents, RecordOpening as RecordOpeningSol},
 };
-use commit::Committable;
 use core::mem;
-use ethers::abi::AbiDecode;
+use ethers::abi::{AbiDecode, RawLog};
+use ethers::contract::{EthLogDecode, LogMeta};
+use ethers::prelude::{Address, BlockNumber, LocalWallet, Middleware, Provider, Ws, U64};
+use ethers::utils::keccak256;
+use jf_cap::keys::AuditorKeyPair;
 use jf_cap::structs::{ReceiverMemo, RecordCommitment};
-use jf_cap::{structs::RecordOpening, MerkleTree, TransactionNote};
+use jf_cap::{
+    structs::{AssetDefinition, RecordOpening},
+    MerkleTree, TransactionNote,
+};
 use rand_chacha::rand_core::SeedableRng;
 use rand_chacha::ChaChaRng;
 use reef::traits::{Block, Transaction};
 use seahorse::events::LedgerEvent;
+use std::path::PathBuf;
+
+fn provider_error(err: impl ToString) -> Error {
+    Error::new(ErrorKind::Other, err.to_string())
+}
+
+fn not_connected(err: impl ToString) -> Error {
+    Error::new(ErrorKind::NotConnected, err.to_string())
+}
+
+fn decode_error(err: impl ToString) -> Error {
+    Error::new(ErrorKind::InvalidData, err.to_string())
+}
 
 pub(crate) struct EthPolling {
     pub query_result_state: Arc<RwLock<QueryResultState>>,
     pub state_persistence: StatePersistence,
     pub last_updated_block_height: u64,
-    pub pending_commit_event: Vec<CapeTransition>,
     pub connection: EthConnection,
+    pub ws_url: Option<String>,
+    pub max_block_range: u64,
+    // number of committed blocks to keep ledger snapshots of
+    ledger_history: usize,
+    // Ethereum block of the last BlockCommitted event whose record Merkle root has not been
+    // checked against the contract yet.
+    unverified_commit: Option<u64>,
+    // Ethereum block up to which `sponsorCapeAsset` calls have been indexed
+    sponsorships_height: u64,
+    // key pairs of the asset viewers to scan committed transactions for
+    viewing_keys: Vec<AuditorKeyPair>,
+    // operator key which signs checkpoints, if checkpoints are enabled
+    checkpoint_operator: Option<LocalWallet>,
+    checkpoint_interval: u64,
+    checkpoint_dir: PathBuf,
 }
 
 impl EthPolling {
     pub async fn new(
         opt: &EQSOptions,
+        connection: EthConnection,
         query_result_state: Arc<RwLock<QueryResultState>>,
         state_persistence: StatePersistence,
     ) -> EthPolling {
+        let viewing_keys = load_viewing_keys(opt.viewing_key_files());
+        let checkpoint_dir = opt
+            .store_path()
+            .join(format!("{:?}", connection.contract.address()))
+            .join("checkpoints");
+        {
+            let mut state = query_result_state.write().await;
+            for key in viewing_keys.iter() {
+                state.audit.register(key.pub_key());
+            }
+        }
+
         if opt.temp_test_run() {
             return EthPolling {
                 query_result_state,
                 state_persistence,
                 last_updated_block_height: 0u64,
-                pending_commit_event: Vec::new(),
-                connection: EthConnection::for_test().await,
+                connection,
+                ws_url: opt.ws_url().map(String::from),
+                max_block_range: opt.max_block_range(),
+                ledger_history: opt.ledger_history(),
+                unverified_commit: None,
+                sponsorships_height: 0,
+                viewing_keys,
+                checkpoint_operator: opt.checkpoint_operator(),
+                checkpoint_interval: opt.checkpoint_interval(),
+                checkpoint_dir,
             };
         }
 
-        let (connection, last_updated_block_height) = if let Some(contract_address) =
-            opt.cape_address()
-        {
+        let contract_address = connection.contract.address();
+        let last_updated_block_height = {
             let mut state_updater = query_result_state.write().await;
-            let last_updated_block_height = state_updater.last_updated_block_height;
+            if state_updater.last_updated_block_height == 0
+                && state_updater.last_processed_event.is_none()
+            {
+                // Nothing has been synced yet, so there is no need to look at the blocks before
+                // the contract existed.
+                state_updater.last_updated_block_height = opt.deployment_block().saturating_sub(1);
+            }
 
             if state_updater.contract_address.is_none()
-                && state_updater.last_updated_block_height > 0
+                && state_updater.last_processed_event.is_some()
             {
                 panic!(
-                    "Persisted state is malformed! Run again with --reset_store_state to repair"
+                    "Persisted state is malformed! Inspect it with eqs-admin, or run again with \
+                     --reset_store_state to rebuild it"
                 );
             }
 
@@ -72,270 +137,834 @@ impl EthPolling {
             } else {
                 state_updater.contract_address = Some(contract_address);
             }
-
-            (
-                EthConnection::from_config_for_query(
-                    &format!("{:?}", contract_address),
-                    opt.rpc_url(),
-                ),
-                last_updated_block_height,
-            )
-        } else {
-            panic!("Invocation Error! Address required unless launched for testing");
+            state_updater.last_updated_block_height
         };
 
         EthPolling {
             query_result_state,
             state_persistence,
             last_updated_block_height,
-            pending_commit_event: Vec::new(),
             connection,
+            ws_url: opt.ws_url().map(String::from),
+            max_block_range: opt.max_block_range(),
+            ledger_history: opt.ledger_history(),
+            unverified_commit: None,
+            sponsorships_height: 0,
+            viewing_keys,
+            checkpoint_operator: opt.checkpoint_operator(),
+            checkpoint_interval: opt.checkpoint_interval(),
+            checkpoint_dir,
         }
     }
 
-    pub async fn check(&mut self) -> Result<u64, async_std::io::Error> {
-        // do eth poll, unpack updates
-        //select cape events, last block + 1 to avoid grabbing the same event twice
-        let new_event = self
+    /// Address of the contract whose events are followed.
+    pub fn contract_address(&self) -> Address {
+        self.connection.contract.address()
+    }
+
+    /// Bring the state up to date with the current head of the chain.
+    ///
+    /// Contract events are requested with `eth_getLogs` in ranges of at most `max_block_range`
+    /// blocks, so that a long gap (on first start, or after a dropped subscription) is backfilled
+    /// without tripping the log range limits of RPC providers. The state is persisted after every
+    /// range, so a restarted EQS resumes from the last completed one. Returns the new
+    /// `last_updated_block_height`.
+    ///
+    /// Asset sponsorships emit no event, so every block in the range is fetched as well to find
+    /// them, see [EthPolling::index_sponsorships].
+    pub async fn check(&mut self) -> Result<u64, Error> {
+        let head = self
             .connection
-            .contract
-            .events()
-            .from_block(self.last_updated_block_height + 1)
-            .query_with_meta()
+            .provider
+            .get_block_number()
             .await
-            .unwrap();
+            .map_err(provider_error)?
+            .as_u64();
+        self.query_result_state.write().await.head_block_height = head;
+
+        while self.last_updated_block_height < head {
+            //select cape events, last block + 1 to avoid grabbing the same event twice
+            let from_block = self.last_updated_block_height + 1;
+            let to_block = std::cmp::min(head, from_block + self.max_block_range.max(1) - 1);
+            // Assets are sponsored before they can be deposited, so they are registered before
+            // the events of the range are processed.
+            self.index_sponsorships(to_block).await?;
+            let new_events = self
+                .connection
+                .contract
+                .events()
+                .from_block(from_block)
+                .to_block(to_block)
+                .query_with_meta()
+                .await
+                .map_err(provider_error)?;
+
+            for (filter, meta) in new_events {
+                self.process_event(filter, meta).await?;
+            }
+            self.complete_block(to_block).await?;
+
+            let updated_state = self.query_result_state.read().await;
+            self.state_persistence.store_latest_state(&*updated_state);
+            tracing::info!(
+                "Synced contract events up to block {} of {}",
+                to_block,
+                head
+            );
+        }
+        Ok(self.last_updated_block_height)
+    }
 
-        for (filter, meta) in new_event {
-            match filter {
-                CAPEEvents::BlockCommittedFilter(_) => {
-                    let fetched_block_with_memos =
-                        fetch_cape_block(&self.connection, meta.transaction_hash)
-                            .await
-                            .unwrap()
-                            .unwrap();
-
-                    let model_txns = fetched_block_with_memos
-                        .block
-                        .clone()
-                        .into_cape_transactions()
-                        .unwrap()
-                        .0;
-
-                    // TODO Instead of panicking here we need to handle cases of missing memos gracefully
-                    let num_txn = model_txns.len();
-                    let num_txn_memo = fetched_block_with_memos.memos.len();
-                    if num_txn != num_txn_memo {
-                        panic!(
-                            "Different number of txns and txn memos: {} vs {}",
-                            num_txn, num_txn_memo
-                        );
-                    }
+    /// Follow new contract events over a WebSocket `eth_subscribe` subscription.
+    ///
+    /// The subscription is opened before backfilling with [EthPolling::check], so that no event
+    /// falls in between the two; events which are delivered by both are only processed once.
+    /// Returns when the subscription is dropped, so that the caller can backfill and reconnect.
+    /// Asset sponsorships emit no event, so they are only indexed once a later event is delivered.
+    /// Fails with [ErrorKind::NotConnected] if the subscription cannot be opened at all, in which
+    /// case nothing has been synced and the caller should fall back to [EthPolling::check].
+    pub async fn subscribe(&mut self, ws_url: &str) -> Result<(), Error> {
+        let provider = Provider::<Ws>::connect(ws_url)
+            .await
+            .map_err(not_connected)?;
+        let filter = self.connection.contract.events().filter;
+        let mut stream = provider
+            .subscribe_logs(&filter)
+            .await
+            .map_err(not_connected)?;
 
-                    let mut wraps = mem::take(&mut self.pending_commit_event);
+        self.check().await?;
 
-                    //add transactions followed by wraps to pending commit
-                    let mut transitions = Vec::new();
-                    for tx in model_txns.clone() {
-                        transitions.push(CapeTransition::Transaction(tx.clone()));
-                    }
+        while let Some(log) = stream.next().await {
+            if log.removed == Some(true) {
+                continue;
+            }
+            let meta = LogMeta::from(&log);
+            let block_number = meta.block_number.as_u64();
+            {
+                let mut updated_state = self.query_result_state.write().await;
+                updated_state.head_block_height =
+                    std::cmp::max(updated_state.head_block_height, block_number);
+            }
+            // Every event of the blocks before this one has been delivered already.
+            if block_number > self.last_updated_block_height + 1 {
+                self.complete_block(block_number - 1).await?;
+            }
+            let filter = CAPEEvents::decode_log(&RawLog {
+                topics: log.topics,
+                data: log.data.to_vec(),
+            })
+            .map_err(provider_error)?;
+            self.index_sponsorships(block_number).await?;
+            self.process_event(filter, meta).await?;
+            // Later events of this block may change the root again, so a mismatch here is only
+            // final once the block is complete.
+            if self.unverified_commit == Some(block_number) {
+                self.verify_root(block_number, false).await?;
+            }
+        }
+        Ok(())
+    }
 
-                    self.pending_commit_event.append(&mut transitions.clone());
-                    self.pending_commit_event.append(&mut wraps);
+    /// Record that every event up to and including `block_height` has been processed.
+    ///
+    /// If a block was committed since the record Merkle root was last checked, the root is checked
+    /// against the contract as of `block_height`.
+    async fn complete_block(&mut self, block_height: u64) -> Result<(), Error> {
+        if block_height <= self.last_updated_block_height {
+            return Ok(());
+        }
+        self.last_updated_block_height = block_height;
+        self.query_result_state
+            .write()
+            .await
+            .last_updated_block_height = block_height;
+        if self.unverified_commit.is_some() {
+            self.verify_root(block_height, true).await?;
+        }
+        self.checkpoint(block_height).await;
+        Ok(())
+    }
 
-                    let output_record_commitments = fetched_block_with_memos
-                        .block
-                        .get_list_of_output_record_commitments();
+    /// Write a signed checkpoint of the state at `block_height`, if checkpoints are enabled, one is
+    /// due, and the state can be vouched for: its record Merkle root matches the contract and no
+    /// wraps are waiting to be committed.
+    ///
+    /// Failures are only logged, since checkpoints are not needed to serve the state.
+    async fn checkpoint(&self, block_height: u64) {
+        let operator = match &self.checkpoint_operator {
+            Some(operator) => operator,
+            None => return,
+        };
+        {
+            let state = self.query_result_state.read().await;
+            let last_checkpoint = state
+                .last_checkpoint
+                .as_ref()
+                .map_or(0, |checkpoint| checkpoint.summary.block_height);
+            if block_height < last_checkpoint + self.checkpoint_interval
+                || !state.pending_commit_event.is_empty()
+                || state.integrity_failure.is_some()
+            {
+                return;
+            }
+        }
 
-                    let state_lock = self.query_result_state.read().await;
-                    let merkle_tree = MerkleTree::restore_from_frontier(
-                        state_lock.ledger_state.record_merkle_commitment,
-                        &state_lock.ledger_state.record_merkle_frontier,
+        let contract_root = match self
+            .connection
+            .contract
+            .get_root_value()
+            .block(BlockNumber::Number(block_height.into()))
+            .call()
+            .await
+        {
+            Ok(root) => root,
+            Err(err) => {
+                tracing::warn!(
+                    "Unable to get the record Merkle root at block {} for a checkpoint: {}",
+                    block_height,
+                    err
+                );
+                return;
+            }
+        };
+        let mut state = self.query_result_state.write().await;
+        if !compare_merkle_root_from_contract_and_jf_tree(
+            contract_root,
+            state.ledger_state.record_merkle_commitment.root_value,
+        ) {
+            // A mismatch is reported by verify_root once the block is complete.
+            return;
+        }
+        let checkpoint =
+            match Checkpoint::sign(&state, self.contract_address(), block_height, operator).await {
+                Ok(checkpoint) => checkpoint,
+                Err(err) => {
+                    tracing::warn!("{}", err);
+                    return;
+                }
+            };
+        match checkpoint.store(&self.checkpoint_dir) {
+            Ok(path) => tracing::info!("Wrote checkpoint {:?}", path),
+            Err(err) => {
+                tracing::warn!(
+                    "Unable to write checkpoint at block {}: {}",
+                    block_height,
+                    err
+                );
+                return;
+            }
+        }
+        state.last_checkpoint = Some(checkpoint.signed);
+    }
+
+    /// Compare the record Merkle root of the state with `getRootValue()` on the contract at the
+    /// end of Ethereum block `block_height`.
+    ///
+    /// Several CAPE blocks may be committed in the same Ethereum block, so the roots only have to
+    /// match once every event of `block_height` has been processed, which the caller indicates
+    /// with `complete`. A mismatch then means the state was built incorrectly: the state is marked
+    /// as failed, which stops it from being served or updated, and an error is returned.
+    async fn verify_root(&mut self, block_height: u64, complete: bool) -> Result<(), Error> {
+        let contract_root = match self
+            .connection
+            .contract
+            .get_root_value()
+            .block(BlockNumber::Number(block_height.into()))
+            .call()
+            .await
+        {
+            Ok(root) => root,
+            Err(err) => {
+                // Nodes other than archive nodes only keep the state of recent blocks, so the
+                // root of a block synced from far behind the head may not be available.
+                if complete {
+                    tracing::warn!(
+                        "Unable to check the record Merkle root at block {}: {}",
+                        block_height,
+                        err
                     );
+                    self.unverified_commit = None;
+                }
+                return Ok(());
+            }
+        };
 
-                    //add commitments to merkle tree
-                    let mut uids = Vec::new();
-                    let mut merkle_paths = Vec::new();
-                    if let Some(mut merkle_tree) = merkle_tree.clone() {
-                        for (_record_id, record_commitment) in
-                            output_record_commitments.iter().enumerate()
-                        {
-                            uids.push(merkle_tree.num_leaves());
-                            merkle_tree.push(record_commitment.to_field_element());
-                        }
-                        merkle_paths = uids
-                            .iter()
-                            .map(|uid| merkle_tree.get_leaf(*uid).expect_ok().unwrap().1.path)
-                            .collect::<Vec<_>>();
-                    }
+        let mut updated_state = self.query_result_state.write().await;
+        let root = updated_state
+            .ledger_state
+            .record_merkle_commitment
+            .root_value;
+        if compare_merkle_root_from_contract_and_jf_tree(contract_root, root) {
+            self.unverified_commit = None;
+            return Ok(());
+        }
+        if !complete {
+            return Ok(());
+        }
+        let msg = format!(
+            "record Merkle root {:?} does not match root {} of contract {:?} at block {}",
+            root,
+            contract_root,
+            self.contract_address(),
+            block_height
+        );
+        tracing::error!("EQS state integrity failure: {}", msg);
+        updated_state.integrity_failure = Some(msg.clone());
+        self.state_persistence.store_latest_state(&*updated_state);
+        Err(Error::new(ErrorKind::InvalidData, msg))
+    }
 
-                    let memos_sig_valid: Vec<_> = model_txns
-                        .iter()
-                        .zip(fetched_block_with_memos.memos.iter())
-                        .map(|(tx, (recv_memos, sig))| {
-                            match tx {
-                                CapeModelTxn::CAP(note) => note.clone(),
-                                CapeModelTxn::Burn { xfr, .. } => {
-                                    TransactionNote::from(*xfr.clone())
-                                }
-                            }
-                            .verify_receiver_memos_signature(recv_memos, sig)
-                            .is_ok()
-                        })
-                        .collect();
-
-                    // Create LedgerEvent::Memos if memo signature is valid, skip otherwise
-                    let mut memo_events = Vec::new();
-                    let mut index = 0;
-                    fetched_block_with_memos
-                        .memos
+    /// Register the assets sponsored in the blocks after the last indexed one, up to and
+    /// including `to_block`.
+    ///
+    /// `sponsorCapeAsset` emits no event, so sponsorships are decoded from the calldata of the
+    /// successful transactions which call it on the contract. Assets sponsored by another contract
+    /// calling `sponsorCapeAsset` are not seen.
+    async fn index_sponsorships(&mut self, to_block: u64) -> Result<(), Error> {
+        let from_block = self.sponsorships_height.max(self.last_updated_block_height) + 1;
+        let contract = &self.connection.contract;
+        let selector = contract
+            .abi()
+            .function("sponsorCapeAsset")
+            .map_err(provider_error)?
+            .short_signature();
+        let mut sponsored = Vec::new();
+        for block_number in from_block..=to_block {
+            let block = self
+                .connection
+                .provider
+                .get_block_with_txs(block_number)
+                .await
+                .map_err(provider_error)?
+                .ok_or_else(|| provider_error(format!("block {} not found", block_number)))?;
+            for txn in block.transactions {
+                if txn.to != Some(contract.address()) || !txn.input.starts_with(&selector) {
+                    continue;
+                }
+                // A reverted call registers nothing.
+                let status = self
+                    .connection
+                    .provider
+                    .get_transaction_receipt(txn.hash)
+                    .await
+                    .map_err(provider_error)?
+                    .and_then(|receipt| receipt.status);
+                if status != Some(U64::from(1)) {
+                    continue;
+                }
+                let (erc20_address, asset_def): (Address, AssetDefinitionSol) = contract
+                    .decode("sponsorCapeAsset", txn.input)
+                    .map_err(decode_error)?;
+                sponsored.push((
+                    AssetDefinition::from(asset_def),
+                    Erc20Code::from(erc20_address),
+                    EthereumAddr::from(txn.from),
+                ));
+            }
+        }
+        self.sponsorships_height = self.sponsorships_height.max(to_block);
+
+        if !sponsored.is_empty() {
+            let mut updated_state = self.query_result_state.write().await;
+            for (asset_def, erc20_code, sponsor) in sponsored {
+                updated_state.audit.learn_asset(&asset_def);
+                updated_state
+                    .erc20_registrar
+                    .insert(asset_def, (erc20_code, sponsor));
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether an event has already been folded into the state, either by a previous range query
+    /// or by the subscription.
+    async fn already_processed(&self, meta: &LogMeta) -> bool {
+        let block_number = meta.block_number.as_u64();
+        if block_number <= self.last_updated_block_height {
+            return true;
+        }
+        let state = self.query_result_state.read().await;
+        matches!(
+            state.last_processed_event,
+            Some(position) if position >= (block_number, meta.log_index.as_u64())
+        )
+    }
+
+    async fn process_event(&mut self, filter: CAPEEvents, meta: LogMeta) -> Result<(), Error> {
+        if self.already_processed(&meta).await {
+            return Ok(());
+        }
+        let event_position = (meta.block_number.as_u64(), meta.log_index.as_u64());
+
+        match filter {
+            CAPEEvents::BlockCommittedFilter(_) => {
+                let fetched_block_with_memos =
+                    fetch_cape_block(&self.connection, meta.transaction_hash)
+                        .await
+                        .map_err(provider_error)?
+                        .ok_or_else(|| {
+                            provider_error(format!(
+                                "transaction {:?} not found",
+                                meta.transaction_hash
+                            ))
+                        })?;
+
+                let model_txns = fetched_block_with_memos
+                    .block
+                    .clone()
+                    .into_cape_transactions()
+                    .map_err(|err| {
+                        provider_error(format!(
+                            "malformed block in transaction {:?}: {:?}",
+                            meta.transaction_hash, err
+                        ))
+                    })?
+                    .0;
+
+                let num_txn = model_txns.len();
+                let num_txn_memo = fetched_block_with_memos.memos.len();
+                if num_txn != num_txn_memo {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        format!(
+                            "Different number of txns and txn memos in transaction {:?}: {} vs {}",
+                            meta.transaction_hash, num_txn, num_txn_memo
+                        ),
+                    ));
+                }
+
+                //add transactions followed by wraps to pending commit
+                let mut transitions = Vec::new();
+                for tx in model_txns.clone() {
+                    transitions.push(CapeTransition::Transaction(tx.clone()));
+                }
+
+                let output_record_commitments = fetched_block_with_memos
+                    .block
+                    .get_list_of_output_record_commitments();
+
+                let state_lock = self.query_result_state.read().await;
+                let mut merkle_tree = MerkleTree::restore_from_frontier(
+                    state_lock.ledger_state.record_merkle_commitment,
+                    &state_lock.ledger_state.record_merkle_frontier,
+                );
+                drop(state_lock);
+
+                //add commitments to merkle tree
+                let first_uid = merkle_tree.as_ref().map_or(0, |tree| tree.num_leaves());
+                let mut uids = Vec::new();
+                let mut merkle_paths = Vec::new();
+                if let Some(merkle_tree) = merkle_tree.as_mut() {
+                    for (_record_id, record_commitment) in
+                        output_record_commitments.iter().enumerate()
+                    {
+                        uids.push(merkle_tree.num_leaves());
+                        merkle_tree.push(record_commitment.to_field_element());
+                    }
+                    merkle_paths = uids
                         .iter()
-                        .enumerate()
-                        .filter_map(|(txn_id, (txn_memo, _))| match memos_sig_valid[txn_id] {
-                            true => Some((txn_id, txn_memo)),
-                            false => None,
-                        })
-                        .for_each(|(txn_id, txn_memo)| {
-                            let mut outputs = Vec::new();
-                            for memo in txn_memo.iter() {
-                                outputs.push((
-                                    memo.clone(),
-                                    output_record_commitments[index],
-                                    uids[index],
-                                    merkle_paths[index].clone(),
-                                ));
-                                index += 1;
-                            }
-                            let memo_event = LedgerEvent::Memos {
-                                outputs,
-                                transaction: Some((
-                                    meta.block_number.as_u64(),
-                                    txn_id as u64,
-                                    transitions[txn_id].kind(),
-                                )),
-                            };
-                            memo_events.push(memo_event);
-                        });
-                    let new_updated_block_height = meta.block_number.as_u64();
-                    if new_updated_block_height > self.last_updated_block_height {
-                        let mut updated_state = self.query_result_state.write().await;
-                        // update the state block
-                        let pending_commit = mem::take(&mut self.pending_commit_event);
-
-                        //create/push pending commit to QueryResultState events
-                        updated_state.events.push(LedgerEvent::Commit {
-                            block: cap_rust_sandbox::ledger::CapeBlock::new(pending_commit),
-                            block_id: meta.block_number.as_u64(),
-                            state_comm: meta.block_number.as_u64() + 1,
-                        });
-
-                        updated_state.events.append(&mut memo_events);
-                        updated_state.ledger_state.state_number += 1;
-
-                        //update merkle tree
-                        if let Some(merkle_tree) = merkle_tree {
-                            updated_state.ledger_state.record_merkle_commitment =
-                                merkle_tree.commitment();
-                            updated_state.ledger_state.record_merkle_frontier =
-                                merkle_tree.frontier();
-                        }
+                        .map(|uid| merkle_tree.get_leaf(*uid).expect_ok().unwrap().1.path)
+                        .collect::<Vec<_>>();
+                }
 
-                        //update transaction_by_id and transaction_id_by_hash hashmap
-                        let mut record_index = 0;
-                        transitions
-                            .iter()
-                            .enumerate()
-                            .for_each(|(txn_id, transition)| {
-                                updated_state.transaction_by_id.insert(
-                                    (meta.block_number.as_u64(), txn_id as u64),
-                                    cap_rust_sandbox::ledger::CommittedCapeTransition {
-                                        block_id: meta.block_number.as_u64(),
-                                        txn_id: txn_id as u64,
-                                        output_start: uids[record_index],
-                                        output_size: transition.output_len() as u64,
-                                        transition: transition.clone(),
-                                    },
-                                );
-                                updated_state.transaction_id_by_hash.insert(
-                                    transition.commit(),
-                                    (meta.block_number.as_u64(), txn_id as u64),
-                                );
-                                for nullifier in transition.proven_nullifiers().iter() {
-                                    updated_state.nullifiers.insert(nullifier.0);
-                                }
-
-                                record_index += transition.output_len();
-                            });
-
-                        // persist the state block updates (will be more fine grained in r3)
-                        self.state_persistence.store_latest_state(&*updated_state);
+                let memos_sig_valid: Vec<_> = model_txns
+                    .iter()
+                    .zip(fetched_block_with_memos.memos.iter())
+                    .map(|(tx, (recv_memos, sig))| {
+                        match tx {
+                            CapeModelTxn::CAP(note) => note.clone(),
+                            CapeModelTxn::Burn { xfr, .. } => TransactionNote::from(*xfr.clone()),
+                        }
+                        .verify_receiver_memos_signature(recv_memos, sig)
+                        .is_ok()
+                    })
+                    .collect();
+
+                // Create LedgerEvent::Memos if memo signature is valid, skip otherwise
+                let mut memo_events = Vec::new();
+                let mut index = 0;
+                fetched_block_with_memos
+                    .memos
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(txn_id, (txn_memo, _))| match memos_sig_valid[txn_id] {
+                        true => Some((txn_id, txn_memo)),
+                        false => None,
+                    })
+                    .for_each(|(txn_id, txn_memo)| {
+                        let mut outputs = Vec::new();
+                        for memo in txn_memo.iter() {
+                            outputs.push((
+                                memo.clone(),
+                                output_record_commitments[index],
+                                uids[index],
+                                merkle_paths[index].clone(),
+                            ));
+                            index += 1;
+                        }
+                        let memo_event = LedgerEvent::Memos {
+                            outputs,
+                            transaction: Some((
+                                meta.block_number.as_u64(),
+                                txn_id as u64,
+                                transitions[txn_id].kind(),
+                            )),
+                        };
+                        memo_events.push(memo_event);
+                    });
+
+                // details for the block explorer
+                let gas_used = self
+                    .connection
+                    .provider
+                    .get_transaction_receipt(meta.transaction_hash)
+                    .await
+                    .map_err(provider_error)?
+                    .and_then(|receipt| receipt.gas_used)
+                    .map(|gas| gas.as_u64());
+                let timestamp = self
+                    .connection
+                    .provider
+                    .get_block(meta.block_hash)
+                    .await
+                    .map_err(provider_error)?
+                    .ok_or_else(|| {
+                        provider_error(format!("block {:?} not found", meta.block_hash))
+                    })?
+                    .timestamp
+                    .as_u64();
+
+                let mut updated_state = self.query_result_state.write().await;
+                // update the state block
+                let mut wraps = mem::take(&mut updated_state.pending_commit_event);
+
+                // The contract appends the records of pending deposits to the records Merkle tree
+                // after the outputs of the block, in the order they were deposited.
+                if let Some(merkle_tree) = merkle_tree.as_mut() {
+                    for wrap in wraps.iter() {
+                        if let CapeTransition::Wrap { ro, .. } = wrap {
+                            merkle_tree.push(RecordCommitment::from(&**ro).to_field_element());
+                        }
                     }
                 }
 
-                CAPEEvents::Erc20TokensDepositedFilter(filter_data) => {
-                    let ro_bytes = filter_data.ro_bytes.clone();
-                    let ro_sol: RecordOpeningSol = AbiDecode::decode(ro_bytes).unwrap();
-                    let expected_ro = RecordOpening::from(ro_sol);
+                let mut pending_commit = transitions.clone();
+                pending_commit.append(&mut wraps);
+                updated_state.record_erc20_transfers(meta.block_number.as_u64(), &pending_commit);
 
-                    let erc20_code = Erc20Code(EthereumAddr(
-                        filter_data.erc_20_token_address.to_fixed_bytes(),
-                    ));
+                //create/push pending commit to QueryResultState events
+                updated_state.events.push(LedgerEvent::Commit {
+                    block: cap_rust_sandbox::ledger::CapeBlock::new(pending_commit.clone()),
+                    block_id: meta.block_number.as_u64(),
+                    state_comm: meta.block_number.as_u64() + 1,
+                });
+
+                updated_state.events.append(&mut memo_events);
+                updated_state.ledger_state.state_number += 1;
+
+                //update merkle tree
+                if let Some(merkle_tree) = merkle_tree {
+                    updated_state.ledger_state.record_merkle_commitment = merkle_tree.commitment();
+                    updated_state.ledger_state.record_merkle_frontier = merkle_tree.frontier();
+                }
+
+                let snapshot = LedgerSnapshot {
+                    ledger_state: updated_state.ledger_state.clone(),
+                    num_events: updated_state.num_events(),
+                };
+                updated_state.insert_snapshot(
+                    meta.block_number.as_u64(),
+                    snapshot,
+                    self.ledger_history,
+                );
 
-                    let new_transition_wrap = CapeTransition::Wrap {
-                        ro: Box::new(expected_ro),
-                        erc20_code,
-                        src_addr: EthereumAddr(filter_data.from.to_fixed_bytes()),
+                updated_state.block_info.insert(
+                    meta.block_number.as_u64(),
+                    BlockInfo {
+                        eth_txn_hash: meta.transaction_hash,
+                        gas_used,
+                        timestamp,
+                        num_transactions: pending_commit.len() as u64,
+                    },
+                );
+
+                // index the transactions followed by the wraps, whose outputs are contiguous in
+                // the Merkle tree
+                let mut output_start = first_uid;
+                for (txn_id, transition) in pending_commit.into_iter().enumerate() {
+                    let output_size = transition.output_len() as u64;
+                    let txn = CommittedCapeTransition {
+                        block_id: meta.block_number.as_u64(),
+                        txn_id: txn_id as u64,
+                        output_start,
+                        output_size,
+                        transition,
                     };
-                    self.pending_commit_event.push(new_transition_wrap);
+                    updated_state.audit.scan(&self.viewing_keys, &txn);
+                    updated_state.index_transaction(txn);
+                    output_start += output_size;
                 }
-                CAPEEvents::FaucetInitializedFilter(filter_data) => {
-                    let ro_bytes = filter_data.ro_bytes;
 
-                    // Obtain record opening
-                    let ro_sol: RecordOpeningSol = AbiDecode::decode(ro_bytes).unwrap();
-                    let ro = RecordOpening::from(ro_sol);
+                updated_state.last_processed_event = Some(event_position);
+                self.unverified_commit = Some(meta.block_number.as_u64());
 
-                    // Compute record commmitment
-                    let rc = RecordCommitment::from(&ro);
+                // persist the state block updates (will be more fine grained in r3)
+                self.state_persistence.store_latest_state(&*updated_state);
+            }
 
-                    // Compute memo
-                    let mut rng = ChaChaRng::from_entropy();
-                    let memo = ReceiverMemo::from_ro(&mut rng, &ro, &[]).unwrap();
+            CAPEEvents::Erc20TokensDepositedFilter(filter_data) => {
+                let ro_bytes = filter_data.ro_bytes.clone();
+                let ro_sol: RecordOpeningSol = AbiDecode::decode(ro_bytes).map_err(decode_error)?;
+                let expected_ro = RecordOpening::from(ro_sol);
 
-                    // Update the Merkle tree
-                    let state_lock = self.query_result_state.read().await;
-                    let merkle_tree = MerkleTree::restore_from_frontier(
-                        state_lock.ledger_state.record_merkle_commitment,
-                        &state_lock.ledger_state.record_merkle_frontier,
-                    );
+                let erc20_code = Erc20Code(EthereumAddr(
+                    filter_data.erc_20_token_address.to_fixed_bytes(),
+                ));
 
-                    let mut merkle_tree = merkle_tree.unwrap().clone();
-                    let uid = merkle_tree.num_leaves();
-                    merkle_tree.push(rc.to_field_element());
-                    let merkle_path = merkle_tree.get_leaf(uid).expect_ok().unwrap().1.path;
+                let new_transition_wrap = CapeTransition::Wrap {
+                    ro: Box::new(expected_ro),
+                    erc20_code,
+                    src_addr: EthereumAddr(filter_data.from.to_fixed_bytes()),
+                };
 
-                    // Process the memo
-                    let output = (memo.clone(), rc, uid, merkle_path.clone());
+                // Wraps are only committed with the next block, so they are kept (and persisted)
+                // with the state until then.
+                let mut updated_state = self.query_result_state.write().await;
+                updated_state.pending_commit_event.push(new_transition_wrap);
+                updated_state.last_processed_event = Some(event_position);
 
-                    let memo_event = LedgerEvent::Memos {
-                        outputs: vec![output],
-                        transaction: None,
-                    };
+                self.state_persistence.store_latest_state(&*updated_state);
+            }
+            CAPEEvents::FaucetInitializedFilter(filter_data) => {
+                let ro_bytes = filter_data.ro_bytes;
+
+                // The memo is encrypted with randomness derived from the event, so that every EQS
+                // replica publishes the same memo. This leaks nothing, since the record opening is
+                // public in the event anyway.
+                let mut rng = ChaChaRng::from_seed(keccak256(&ro_bytes));
+
+                // Obtain record opening
+                let ro_sol: RecordOpeningSol = AbiDecode::decode(ro_bytes).map_err(decode_error)?;
+                let ro = RecordOpening::from(ro_sol);
+
+                // Compute record commmitment
+                let rc = RecordCommitment::from(&ro);
+
+                // Compute memo
+                let memo = ReceiverMemo::from_ro(&mut rng, &ro, &[])
+                    .map_err(|err| provider_error(format!("{:?}", err)))?;
+
+                // Update the Merkle tree
+                let state_lock = self.query_result_state.read().await;
+                let merkle_tree = MerkleTree::restore_from_frontier(
+                    state_lock.ledger_state.record_merkle_commitment,
+                    &state_lock.ledger_state.record_merkle_frontier,
+                );
+                drop(state_lock);
 
-                    let mut memo_events = vec![memo_event];
+                let mut merkle_tree = merkle_tree.ok_or_else(|| {
+                    provider_error("the record Merkle tree cannot be restored from the frontier")
+                })?;
+                let uid = merkle_tree.num_leaves();
+                merkle_tree.push(rc.to_field_element());
+                let merkle_path = merkle_tree.get_leaf(uid).expect_ok().unwrap().1.path;
 
-                    // Update the local data structures
-                    let mut updated_state = self.query_result_state.write().await;
+                // Process the memo
+                let output = (memo.clone(), rc, uid, merkle_path.clone());
 
-                    updated_state.ledger_state.record_merkle_commitment = merkle_tree.commitment();
-                    updated_state.ledger_state.record_merkle_frontier = merkle_tree.frontier();
+                let memo_event = LedgerEvent::Memos {
+                    outputs: vec![output],
+                    transaction: None,
+                };
 
-                    updated_state.events.append(&mut memo_events);
+                let mut memo_events = vec![memo_event];
 
-                    // persist the state block updates (will be more fine grained in r3)
-                    self.state_persistence.store_latest_state(&*updated_state);
-                }
+                // Update the local data structures
+                let mut updated_state = self.query_result_state.write().await;
+
+                updated_state.ledger_state.record_merkle_commitment = merkle_tree.commitment();
+                updated_state.ledger_state.record_merkle_frontier = merkle_tree.frontier();
+
+                updated_state.events.append(&mut memo_events);
+                updated_state.last_processed_event = Some(event_position);
+                self.unverified_commit = Some(meta.block_number.as_u64());
+
+                // persist the state block updates (will be more fine grained in r3)
+                self.state_persistence.store_latest_state(&*updated_state);
             }
         }
-        Ok(0)
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use cap_rust_sandbox::{
+        cape::{submit_block::submit_cape_block_with_memos, BlockWithMemos, CapeBlock},
+        deploy::deploy_erc20_token,
+        model::erc20_asset_description,
+        types::{GenericInto, TestCAPE},
+    };
+    use ethers::prelude::U256;
+    use jf_cap::{
+        keys::UserKeyPair,
+        structs::{AssetCode, AssetPolicy, FreezeFlag},
+    };
+    use structopt::StructOpt;
+    use tempdir::TempDir;
+
+    async fn replica(opt: &EQSOptions, connection: EthConnection, dir: &TempDir) -> EthPolling {
+        EthPolling::new(
+            opt,
+            connection,
+            Arc::new(RwLock::new(QueryResultState::new(opt.verifier_keys()))),
+            StatePersistence::new(dir.path(), "eqs").unwrap(),
+        )
+        .await
+    }
+
+    /// Set up the faucet, wrap some ERC20 tokens, and commit the wrap with an empty block.
+    async fn commit_wrap(rng: &mut ChaChaRng, connection: &EthConnection) {
+        let contract = connection.test_contract();
+
+        let faucet_manager = UserKeyPair::generate(rng);
+        contract
+            .faucet_setup_for_testnet(faucet_manager.address().into())
+            .send()
+            .await
+            .unwrap()
+            .await
+            .unwrap();
+
+        let erc20_contract = deploy_erc20_token().await;
+        let erc20_owner = erc20_contract.client().clone();
+        let erc20_code = Erc20Code(EthereumAddr(erc20_contract.address().to_fixed_bytes()));
+        let description = erc20_asset_description(
+            &erc20_code,
+            &EthereumAddr(erc20_owner.address().to_fixed_bytes()),
+        );
+        let asset_def = AssetDefinition::new(
+            AssetCode::new_foreign(&description),
+            AssetPolicy::rand_for_test(rng),
+        )
+        .unwrap();
+        let contract_for_owner = TestCAPE::new(contract.address(), Arc::new(erc20_owner));
+        contract_for_owner
+            .sponsor_cape_asset(
+                erc20_contract.address(),
+                asset_def.clone().generic_into::<AssetDefinitionSol>(),
+            )
+            .send()
+            .await
+            .unwrap()
+            .await
+            .unwrap();
+        erc20_contract
+            .approve(contract.address(), U256::from(1000))
+            .send()
+            .await
+            .unwrap()
+            .await
+            .unwrap();
+        let ro = RecordOpening::new(
+            rng,
+            1000,
+            asset_def,
+            faucet_manager.pub_key(),
+            FreezeFlag::Unfrozen,
+        );
+        contract_for_owner
+            .deposit_erc_20(
+                ro.generic_into::<RecordOpeningSol>(),
+                erc20_contract.address(),
+            )
+            .send()
+            .await
+            .unwrap()
+            .await
+            .unwrap();
+
+        let block = CapeBlock::generate(vec![], vec![], faucet_manager.address()).unwrap();
+        submit_cape_block_with_memos(&connection.contract, BlockWithMemos::new(block, vec![]))
+            .await
+            .unwrap()
+            .await
+            .unwrap();
+    }
+
+    #[async_std::test]
+    async fn test_root_with_wraps() {
+        let mut rng = ChaChaRng::from_seed([7; 32]);
+        let connection = EthConnection::for_test().await;
+        commit_wrap(&mut rng, &connection).await;
+
+        let opt = EQSOptions::from_iter(["eqs", "--temp_test_run"]);
+        let dir = TempDir::new("eqs_root").unwrap();
+        let mut eth_poll = replica(&opt, connection.clone(), &dir).await;
+        eth_poll.check().await.unwrap();
+
+        let state = eth_poll.query_result_state.read().await;
+        assert!(state.integrity_failure.is_none());
+        // The faucet record and the wrapped record.
+        assert_eq!(state.ledger_state.record_merkle_commitment.num_leaves, 2);
+        // The sponsored asset is registered from the calldata of its sponsorCapeAsset call.
+        assert_eq!(state.erc20_registrar.len(), 1);
+        let (asset_def, (erc20_code, _sponsor)) = state.erc20_registrar.iter().next().unwrap();
+        assert_eq!(&state.wrapped_erc20[&asset_def.code], erc20_code);
+        let contract_root = connection.contract.get_root_value().call().await.unwrap();
+        assert!(compare_merkle_root_from_contract_and_jf_tree(
+            contract_root,
+            state.ledger_state.record_merkle_commitment.root_value
+        ));
+    }
+
+    #[async_std::test]
+    async fn test_replicas_agree() {
+        let mut rng = ChaChaRng::from_seed([7; 32]);
+        let connection = EthConnection::for_test().await;
+        commit_wrap(&mut rng, &connection).await;
+
+        // Sync two independent replicas of the EQS against the contract.
+        let opt = EQSOptions::from_iter(["eqs", "--temp_test_run"]);
+        let dirs = [
+            TempDir::new("eqs_replica").unwrap(),
+            TempDir::new("eqs_replica").unwrap(),
+        ];
+        let mut events = Vec::new();
+        for dir in dirs.iter() {
+            let mut eth_poll = replica(&opt, connection.clone(), dir).await;
+            eth_poll.check().await.unwrap();
+            let state = eth_poll.query_result_state.read().await;
+            assert!(state.integrity_failure.is_none());
+            // The faucet memo and the commit of the block with the wrap.
+            assert_eq!(state.events.len(), 2);
+            events.push(bincode::serialize(&state.events).unwrap());
+        }
+        assert_eq!(events[0], events[1]);
+    }
+
+    #[async_std::test]
+    async fn test_replay_overlapping_range() {
+        let mut rng = ChaChaRng::from_seed([7; 32]);
+        let connection = EthConnection::for_test().await;
+        commit_wrap(&mut rng, &connection).await;
+
+        let opt = EQSOptions::from_iter(["eqs", "--temp_test_run"]);
+        let dir = TempDir::new("eqs_replay").unwrap();
+        let mut eth_poll = replica(&opt, connection.clone(), &dir).await;
+        eth_poll.check().await.unwrap();
+        let events = bincode::serialize(&eth_poll.query_result_state.read().await.events).unwrap();
+
+        // Query every event again, as happens when a backfill overlaps events already delivered
+        // by the subscription. Only the position of the last processed event prevents them from
+        // being applied twice.
+        eth_poll.last_updated_block_height = 0;
+        eth_poll.check().await.unwrap();
+        let state = eth_poll.query_result_state.read().await;
+        assert!(state.integrity_failure.is_none());
+        assert_eq!(bincode::serialize(&state.events).unwrap(), events);
+        assert_eq!(state.ledger_state.record_merkle_commitment.num_leaves, 2);
     }
 }
@@ -0,0 +1,300 @@
+// Copyright (c) 2022 Espresso Systems (espressosys.com)
+// This file is part of the Configurable Asset Privacy for Ethereum (CAPE) library.
+
+// This program is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+// You should have received a copy of the GNU General Public License along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! HTML pages for browsing the committed blocks, transactions and nullifiers of a contract.
+//!
+//! Like [crate::disco::compose_help], the pages are composed from text in `api.toml`, which has
+//! the page header, footer and headings in its `[explorer]` table. Links stay under the
+//! `/:contract` prefix of the page they are on, if it has one.
+
+use crate::api_server::{contract_state, WebState};
+use crate::errors::{server_error, EQSNetError};
+use crate::query_result_state::QueryResultState;
+use crate::route_parsing::{UrlSegmentType, UrlSegmentValue};
+use crate::routes::{committed_block, latest_blocks, CommittedBlock};
+use cap_rust_sandbox::ledger::{CommitmentToCapeTransition, CommittedCapeTransition};
+use commit::Committable;
+use jf_cap::structs::Nullifier;
+use reef::traits::Transaction;
+
+/// Number of blocks listed on the front page.
+const FRONT_PAGE_BLOCKS: usize = 20;
+
+fn text<'a>(api: &'a toml::Value, key: &str) -> &'a str {
+    api["explorer"][key].as_str().unwrap_or_else(|| {
+        panic!(
+            "{} must be a string in the [explorer] table of api.toml",
+            key
+        )
+    })
+}
+
+/// Wrap `body` with the page header and footer from api.toml.
+fn page(req: &tide::Request<WebState>, body: String) -> tide::Response {
+    let api = &req.state().api;
+    tide::Response::builder(200)
+        .content_type(tide::http::mime::HTML)
+        .body(format!(
+            "{}\n{}\n{}\n",
+            text(api, "HTML_TOP"),
+            body,
+            text(api, "HTML_BOTTOM")
+        ))
+        .build()
+}
+
+/// The prefix of links from the page for `req`.
+fn prefix(req: &tide::Request<WebState>) -> String {
+    match req.param("contract") {
+        Ok(contract) => format!("/{}", contract),
+        Err(_) => String::new(),
+    }
+}
+
+/// Parse the URL parameter `name` as a `ptype`.
+fn param(
+    req: &tide::Request<WebState>,
+    name: &str,
+    ptype: UrlSegmentType,
+) -> Result<UrlSegmentValue, tide::Error> {
+    let value = req.param(name)?;
+    UrlSegmentValue::parse(ptype, value).ok_or_else(|| {
+        server_error(EQSNetError::RouteParam {
+            param: name.to_string(),
+            value: value.to_string(),
+            msg: format!("expected {:?}", ptype),
+        })
+    })
+}
+
+/// Run `f` on the state of the contract of `req`, unless it failed an integrity check.
+async fn with_state<T>(
+    req: &tide::Request<WebState>,
+    f: impl FnOnce(&QueryResultState) -> T,
+) -> Result<T, tide::Error> {
+    let state = contract_state(req)?;
+    let state = state.read().await;
+    if let Some(msg) = &state.integrity_failure {
+        return Err(server_error(EQSNetError::IntegrityFailure {
+            msg: msg.clone(),
+        }));
+    }
+    Ok(f(&state))
+}
+
+fn block_link(prefix: &str, block_id: u64) -> String {
+    format!(
+        "<a href='{}/explorer/block/{}'>{}</a>",
+        prefix, block_id, block_id
+    )
+}
+
+fn transaction_link(prefix: &str, block_id: u64, txn_id: u64) -> String {
+    format!(
+        "<a href='{}/explorer/transaction/{}/{}'>{}.{}</a>",
+        prefix, block_id, txn_id, block_id, txn_id
+    )
+}
+
+fn nullifier_link(prefix: &str, nullifier: &Nullifier) -> String {
+    format!(
+        "<a href='{}/explorer/nullifier/{}'>{}</a>",
+        prefix, nullifier, nullifier
+    )
+}
+
+fn table(headings: &[&str], rows: Vec<Vec<String>>) -> String {
+    let mut html = String::from("<table>\n<tr>");
+    for heading in headings {
+        html += &format!("<th>{}</th>", heading);
+    }
+    html += "</tr>\n";
+    for row in rows {
+        html += "<tr>";
+        for cell in row {
+            html += &format!("<td>{}</td>", cell);
+        }
+        html += "</tr>\n";
+    }
+    html + "</table>\n"
+}
+
+fn uids(txn: &CommittedCapeTransition) -> String {
+    if txn.output_size == 0 {
+        String::new()
+    } else {
+        format!(
+            "{}&ndash;{}",
+            txn.output_start,
+            txn.output_start + txn.output_size - 1
+        )
+    }
+}
+
+fn block_rows(prefix: &str, blocks: &[CommittedBlock]) -> Vec<Vec<String>> {
+    blocks
+        .iter()
+        .map(|block| {
+            vec![
+                block_link(prefix, block.block_id),
+                format!("{:?}", block.eth_txn_hash),
+                block
+                    .gas_used
+                    .map(|gas| gas.to_string())
+                    .unwrap_or_default(),
+                block.timestamp.to_string(),
+                block.transactions.len().to_string(),
+            ]
+        })
+        .collect()
+}
+
+const BLOCK_HEADINGS: [&str; 5] = [
+    "Block",
+    "Ethereum transaction",
+    "Gas used",
+    "Timestamp (Unix time)",
+    "Transactions",
+];
+
+/// The most recently committed blocks.
+pub async fn explorer_home(req: tide::Request<WebState>) -> Result<tide::Response, tide::Error> {
+    let prefix = prefix(&req);
+    let blocks = with_state(&req, |state| latest_blocks(FRONT_PAGE_BLOCKS, state)).await?;
+    let api = &req.state().api;
+    let body = format!(
+        "<h2>{}</h2>\n{}",
+        text(api, "HEADING_LATEST_BLOCKS"),
+        table(&BLOCK_HEADINGS, block_rows(&prefix, &blocks))
+    );
+    Ok(page(&req, body))
+}
+
+/// A block and its transactions.
+pub async fn explorer_block(req: tide::Request<WebState>) -> Result<tide::Response, tide::Error> {
+    let prefix = prefix(&req);
+    let block_id = param(&req, "block_id", UrlSegmentType::Integer)?.as_u64()?;
+    let block = with_state(&req, |state| committed_block(block_id, state)).await?;
+    let api = &req.state().api;
+    let mut body = format!("<h2>{} {}</h2>\n", text(api, "HEADING_BLOCK"), block_id);
+    match block {
+        Some(block) => {
+            body += &table(&BLOCK_HEADINGS, block_rows(&prefix, &[block.clone()]));
+            body += &format!(
+                "<p><a href='{}/get_block/{}'>JSON</a></p>\n<h3>{}</h3>\n",
+                prefix,
+                block_id,
+                text(api, "HEADING_TRANSACTIONS")
+            );
+            let rows = block
+                .transactions
+                .iter()
+                .map(|txn| {
+                    vec![
+                        transaction_link(&prefix, txn.block_id, txn.txn_id),
+                        format!("{:?}", txn.transition.kind()),
+                        uids(txn),
+                        txn.transition.proven_nullifiers().len().to_string(),
+                    ]
+                })
+                .collect();
+            body += &table(&["Transaction", "Kind", "Output uids", "Nullifiers"], rows);
+        }
+        None => body += &format!("<p>{}</p>\n", text(api, "NOT_FOUND")),
+    }
+    Ok(page(&req, body))
+}
+
+/// A transaction, with its outputs and the nullifiers it spent.
+pub async fn explorer_transaction(
+    req: tide::Request<WebState>,
+) -> Result<tide::Response, tide::Error> {
+    let prefix = prefix(&req);
+    let block_id = param(&req, "block_id", UrlSegmentType::Integer)?.as_u64()?;
+    let txn_id = param(&req, "txn_id", UrlSegmentType::Integer)?.as_u64()?;
+    let txn = with_state(&req, |state| {
+        state.transaction_by_id.get(&(block_id, txn_id)).cloned()
+    })
+    .await?;
+    let api = &req.state().api;
+    let mut body = format!(
+        "<h2>{} {}.{}</h2>\n",
+        text(api, "HEADING_TRANSACTION"),
+        block_id,
+        txn_id
+    );
+    match txn {
+        Some(txn) => {
+            let hash = CommitmentToCapeTransition(txn.transition.commit());
+            body += &table(
+                &["Block", "Kind", "Hash"],
+                vec![vec![
+                    block_link(&prefix, block_id),
+                    format!("{:?}", txn.transition.kind()),
+                    hash.to_string(),
+                ]],
+            );
+            body += &format!(
+                "<p><a href='{}/get_transaction/{}/{}'>JSON</a></p>\n<h3>{}</h3>\n",
+                prefix,
+                block_id,
+                txn_id,
+                text(api, "HEADING_OUTPUTS")
+            );
+            let outputs = txn
+                .transition
+                .output_commitments()
+                .into_iter()
+                .enumerate()
+                .map(|(i, commitment)| {
+                    vec![
+                        (txn.output_start + i as u64).to_string(),
+                        commitment.to_string(),
+                    ]
+                })
+                .collect();
+            body += &table(&["Uid", "Record commitment"], outputs);
+            body += &format!("<h3>{}</h3>\n", text(api, "HEADING_NULLIFIERS"));
+            let nullifiers = txn
+                .transition
+                .proven_nullifiers()
+                .iter()
+                .map(|(nullifier, _)| vec![nullifier_link(&prefix, nullifier)])
+                .collect();
+            body += &table(&["Nullifier"], nullifiers);
+        }
+        None => body += &format!("<p>{}</p>\n", text(api, "NOT_FOUND")),
+    }
+    Ok(page(&req, body))
+}
+
+/// Whether a nullifier was spent, and by which transaction.
+pub async fn explorer_nullifier(
+    req: tide::Request<WebState>,
+) -> Result<tide::Response, tide::Error> {
+    let prefix = prefix(&req);
+    let nullifier = param(&req, "nullifier", UrlSegmentType::TaggedBase64)?.to::<Nullifier>()?;
+    let spent_by = with_state(&req, |state| {
+        state.transaction_id_by_nullifier.get(&nullifier).cloned()
+    })
+    .await?;
+    let api = &req.state().api;
+    let mut body = format!(
+        "<h2>{} {}</h2>\n",
+        text(api, "HEADING_NULLIFIER"),
+        nullifier
+    );
+    body += &match spent_by {
+        Some((block_id, txn_id)) => format!(
+            "<p>{} {}</p>\n",
+            text(api, "SPENT_BY"),
+            transaction_link(&prefix, block_id, txn_id)
+        ),
+        None => format!("<p>{}</p>\n", text(api, "NOT_SPENT")),
+    };
+    Ok(page(&req, body))
+}
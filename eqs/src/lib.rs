@@ -5,12 +5,19 @@
 // This program is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
 // You should have received a copy of the GNU General Public License along with this program. If not, see <https://www.gnu.org/licenses/>.
 
+pub mod admin;
 pub mod api_server;
+pub mod audit;
+pub mod checkpoint;
 pub mod configuration;
 pub mod disco;
 pub mod entry;
 pub mod errors;
 pub mod eth_polling;
+pub mod explorer;
+pub mod metrics;
+pub mod nullifier_filter;
+pub mod openapi;
 pub mod query_result_state;
 pub mod route_parsing;
 pub mod routes;
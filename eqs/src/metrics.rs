@@ -0,0 +1,243 @@
+// Copyright (c) 2022 Espresso Systems (espressosys.com)
+// This file is part of the Configurable Asset Privacy for Ethereum (CAPE) library.
+
+// This program is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+// You should have received a copy of the GNU General Public License along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Health, readiness and Prometheus metrics endpoints.
+//!
+//! * `/healthz` fails (503) once any contract has failed an integrity check, since the EQS stops
+//!   following it until it is rebuilt.
+//! * `/readyz` fails (503) until every contract has been polled and is within `--max_ready_lag`
+//!   blocks of the head of the chain.
+//! * `/metrics` serves the counters and gauges below in the Prometheus text format. Most of them
+//!   are read from the state of each contract when scraped; only poll errors, persistence latency
+//!   and request counts are tracked separately.
+
+use crate::api_server::WebState;
+use crate::query_result_state::QueryResultState;
+use crate::routes::ApiRouteKey;
+
+use async_std::sync::{Arc, RwLock};
+use ethers::prelude::Address;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use strum::IntoEnumIterator;
+
+/// Number and total duration of the state snapshots stored by a `StatePersistence`.
+#[derive(Debug, Default)]
+pub struct PersistenceStats {
+    stores: AtomicU64,
+    nanos: AtomicU64,
+}
+
+impl PersistenceStats {
+    pub fn record(&self, elapsed: Duration) {
+        self.stores.fetch_add(1, Ordering::Relaxed);
+        self.nanos
+            .fetch_add(elapsed.as_nanos() as u64, Ordering::Relaxed);
+    }
+}
+
+/// Metrics of one contract which are not part of its state.
+#[derive(Clone, Debug, Default)]
+pub struct ContractMetrics {
+    /// Number of failed polls or subscriptions for events of the contract.
+    pub poll_errors: Arc<AtomicU64>,
+    pub persistence: Arc<PersistenceStats>,
+}
+
+#[derive(Debug)]
+pub struct Metrics {
+    pub(crate) contracts: HashMap<Address, ContractMetrics>,
+    requests: HashMap<ApiRouteKey, AtomicU64>,
+    /// Maximum number of blocks behind the head of the chain at which the EQS is ready.
+    max_ready_lag: u64,
+}
+
+impl Metrics {
+    pub fn new(contracts: HashMap<Address, ContractMetrics>, max_ready_lag: u64) -> Self {
+        Self {
+            contracts,
+            requests: ApiRouteKey::iter()
+                .map(|key| (key, AtomicU64::new(0)))
+                .collect(),
+            max_ready_lag,
+        }
+    }
+
+    pub fn count_request(&self, key: ApiRouteKey) {
+        self.requests[&key].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Whether `state` is close enough to the head of the chain to serve requests.
+    fn ready(&self, state: &QueryResultState) -> bool {
+        state.integrity_failure.is_none()
+            && state.head_block_height > 0
+            && state.head_block_height <= state.last_updated_block_height + self.max_ready_lag
+    }
+
+    /// The metrics in the Prometheus text exposition format.
+    async fn render(&self, contracts: &HashMap<Address, Arc<RwLock<QueryResultState>>>) -> String {
+        let mut gauges: Vec<(&str, &str, Vec<(String, u64)>)> = vec![
+            (
+                "eqs_events_indexed",
+                "Number of events in the event log of the contract.",
+                vec![],
+            ),
+            (
+                "eqs_last_processed_block",
+                "Last Ethereum block whose contract events have been processed.",
+                vec![],
+            ),
+            (
+                "eqs_head_block",
+                "Head of the chain at the last poll.",
+                vec![],
+            ),
+            (
+                "eqs_sync_lag_blocks",
+                "Number of blocks between the head of the chain and the last processed block.",
+                vec![],
+            ),
+            (
+                "eqs_integrity_failure",
+                "1 if the state of the contract failed an integrity check, else 0.",
+                vec![],
+            ),
+        ];
+        let mut addresses: Vec<_> = contracts.keys().collect();
+        addresses.sort();
+        for address in addresses.iter() {
+            let state = contracts[*address].read().await;
+            let label = format!("contract=\"{:?}\"", address);
+            let values = [
+                state.num_events(),
+                state.last_updated_block_height,
+                state.head_block_height,
+                state
+                    .head_block_height
+                    .saturating_sub(state.last_updated_block_height),
+                state.integrity_failure.is_some() as u64,
+            ];
+            for ((_, _, samples), value) in gauges.iter_mut().zip(values) {
+                samples.push((label.clone(), value));
+            }
+        }
+
+        let mut text = String::new();
+        for (name, help, samples) in gauges {
+            metric(&mut text, name, help, "gauge", samples);
+        }
+
+        let mut poll_errors = vec![];
+        let mut persistence_count = vec![];
+        let mut persistence_sum = vec![];
+        for address in addresses {
+            if let Some(metrics) = self.contracts.get(address) {
+                let label = format!("contract=\"{:?}\"", address);
+                poll_errors.push((label.clone(), metrics.poll_errors.load(Ordering::Relaxed)));
+                persistence_count.push((
+                    label.clone(),
+                    metrics.persistence.stores.load(Ordering::Relaxed),
+                ));
+                persistence_sum.push((
+                    label,
+                    metrics.persistence.nanos.load(Ordering::Relaxed) as f64 / 1e9,
+                ));
+            }
+        }
+        metric(
+            &mut text,
+            "eqs_poll_errors_total",
+            "Number of failed polls or subscriptions for contract events.",
+            "counter",
+            poll_errors,
+        );
+        text += "# HELP eqs_persistence_seconds Time taken to persist snapshots of the state.\n";
+        text += "# TYPE eqs_persistence_seconds summary\n";
+        for (label, sum) in persistence_sum {
+            text += &format!("eqs_persistence_seconds_sum{{{}}} {}\n", label, sum);
+        }
+        for (label, count) in persistence_count {
+            text += &format!("eqs_persistence_seconds_count{{{}}} {}\n", label, count);
+        }
+
+        let mut requests: Vec<_> = self
+            .requests
+            .iter()
+            .map(|(key, count)| {
+                let route: &str = key.as_ref();
+                (
+                    format!("route=\"{}\"", route),
+                    count.load(Ordering::Relaxed),
+                )
+            })
+            .collect();
+        requests.sort();
+        metric(
+            &mut text,
+            "eqs_requests_total",
+            "Number of requests for each route in api.toml.",
+            "counter",
+            requests,
+        );
+        text
+    }
+}
+
+fn metric<T: ToString>(
+    text: &mut String,
+    name: &str,
+    help: &str,
+    kind: &str,
+    samples: Vec<(String, T)>,
+) {
+    *text += &format!("# HELP {} {}\n# TYPE {} {}\n", name, help, name, kind);
+    for (labels, value) in samples {
+        *text += &format!("{}{{{}}} {}\n", name, labels, value.to_string());
+    }
+}
+
+fn plain(status: u16, body: &str) -> tide::Response {
+    tide::Response::builder(status)
+        .content_type(tide::http::mime::PLAIN)
+        .body(format!("{}\n", body))
+        .build()
+}
+
+pub async fn healthz(req: tide::Request<WebState>) -> Result<tide::Response, tide::Error> {
+    for (address, state) in req.state().contracts.iter() {
+        if let Some(failure) = &state.read().await.integrity_failure {
+            return Ok(plain(503, &format!("contract {:?}: {}", address, failure)));
+        }
+    }
+    Ok(plain(200, "ok"))
+}
+
+pub async fn readyz(req: tide::Request<WebState>) -> Result<tide::Response, tide::Error> {
+    let metrics = &req.state().metrics;
+    for (address, state) in req.state().contracts.iter() {
+        let state = state.read().await;
+        if !metrics.ready(&state) {
+            return Ok(plain(
+                503,
+                &format!(
+                    "contract {:?} is synced up to block {} of {}",
+                    address, state.last_updated_block_height, state.head_block_height
+                ),
+            ));
+        }
+    }
+    Ok(plain(200, "ready"))
+}
+
+pub async fn metrics(req: tide::Request<WebState>) -> Result<tide::Response, tide::Error> {
+    let text = req.state().metrics.render(&req.state().contracts).await;
+    Ok(tide::Response::builder(200)
+        .content_type("text/plain; version=0.0.4")
+        .body(text)
+        .build())
+}
@@ -0,0 +1,188 @@
+// Copyright (c) 2022 Espresso Systems (espressosys.com)
+// This file is part of the Configurable Asset Privacy for Ethereum (CAPE) library.
+
+// This program is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+// You should have received a copy of the GNU General Public License along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! A Bloom filter summarizing the set of spent nullifiers.
+//!
+//! Wallets download the filter to check locally whether their nullifiers may have been spent. The
+//! filter never reports a spent nullifier as absent, but it reports an unspent nullifier as present
+//! about 1% of the time, so positive answers must be confirmed with the EQS.
+//!
+//! The serialized filter ([NullifierFilter::to_bytes]) is the number of hash functions `k` as a
+//! little-endian u32, followed by the bit array: bit `i` of the filter is bit `i % 8` of byte
+//! `i / 8`, and the number of bits `m` is 8 times the length of the array. To find the bits of a
+//! nullifier, take `h = keccak256(b)`, where `b` is the byte string encoded in the nullifier's
+//! TaggedBase64 form, and let `h1` and `h2` be the little-endian u64s in bytes 0..8 and 8..16 of
+//! `h`. Then the bits are `(h1 + i * h2) % m` for `i` in `0..k`, using wrapping u64 arithmetic.
+
+use ethers::utils::keccak256;
+use jf_cap::structs::Nullifier;
+use net::TaggedBlob;
+use serde::{Deserialize, Serialize};
+use std::convert::TryInto;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct NullifierFilter {
+    bits: Vec<u8>,
+    num_hashes: u32,
+    num_items: u64,
+}
+
+impl NullifierFilter {
+    /// Bits per nullifier, which with [Self::NUM_HASHES] gives a false positive rate below 1%.
+    const BITS_PER_ITEM: u64 = 10;
+    const NUM_HASHES: u32 = 7;
+    /// Size of the filter when there are few nullifiers, in bytes.
+    const MIN_BYTES: usize = 1 << 13;
+
+    /// A filter sized for `capacity` nullifiers.
+    pub fn with_capacity(capacity: usize) -> Self {
+        let bytes = (capacity as u64 * Self::BITS_PER_ITEM / 8) as usize;
+        Self {
+            bits: vec![0; std::cmp::max(bytes, Self::MIN_BYTES).next_power_of_two()],
+            num_hashes: Self::NUM_HASHES,
+            num_items: 0,
+        }
+    }
+
+    /// A filter containing `nullifiers`, with room for at least as many more.
+    pub fn from_nullifiers<'a>(nullifiers: impl ExactSizeIterator<Item = &'a Nullifier>) -> Self {
+        let mut filter = Self::with_capacity(2 * nullifiers.len());
+        for nullifier in nullifiers {
+            filter.insert(nullifier);
+        }
+        filter
+    }
+
+    pub fn insert(&mut self, nullifier: &Nullifier) {
+        for bit in self.bit_indices(nullifier) {
+            self.bits[(bit / 8) as usize] |= 1 << (bit % 8);
+        }
+        self.num_items += 1;
+    }
+
+    /// Whether `nullifier` may have been inserted. If this returns `false`, it was not.
+    pub fn may_contain(&self, nullifier: &Nullifier) -> bool {
+        self.bit_indices(nullifier)
+            .all(|bit| self.bits[(bit / 8) as usize] & (1 << (bit % 8)) != 0)
+    }
+
+    /// Whether the filter holds more nullifiers than it was sized for, and should be rebuilt with
+    /// [Self::from_nullifiers] to keep the false positive rate down.
+    pub fn is_full(&self) -> bool {
+        self.num_items * Self::BITS_PER_ITEM > self.num_bits()
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.num_hashes.to_le_bytes().to_vec();
+        bytes.extend_from_slice(&self.bits);
+        bytes
+    }
+
+    fn num_bits(&self) -> u64 {
+        self.bits.len() as u64 * 8
+    }
+
+    fn bit_indices(&self, nullifier: &Nullifier) -> impl Iterator<Item = u64> {
+        let hash = keccak256(nullifier.tagged_blob().value());
+        let h1 = u64::from_le_bytes(hash[0..8].try_into().unwrap());
+        let h2 = u64::from_le_bytes(hash[8..16].try_into().unwrap());
+        let num_bits = self.num_bits();
+        (0..self.num_hashes as u64).map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) % num_bits)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rand_chacha::rand_core::SeedableRng;
+    use rand_chacha::ChaChaRng;
+
+    fn nullifiers(rng: &mut ChaChaRng, count: usize) -> Vec<Nullifier> {
+        (0..count)
+            .map(|_| Nullifier::random_for_test(rng))
+            .collect()
+    }
+
+    #[test]
+    fn test_insert_and_contains() {
+        let mut rng = ChaChaRng::from_seed([0; 32]);
+        let inserted = nullifiers(&mut rng, 1000);
+        let mut filter = NullifierFilter::with_capacity(1000);
+        for nullifier in &inserted {
+            assert!(!filter.is_full());
+            filter.insert(nullifier);
+        }
+        // No false negatives.
+        for nullifier in &inserted {
+            assert!(filter.may_contain(nullifier));
+        }
+
+        let rebuilt = NullifierFilter::from_nullifiers(inserted.iter());
+        assert_eq!(rebuilt.num_items, inserted.len() as u64);
+        for nullifier in &inserted {
+            assert!(rebuilt.may_contain(nullifier));
+        }
+    }
+
+    #[test]
+    fn test_false_positive_rate() {
+        let mut rng = ChaChaRng::from_seed([1; 32]);
+        // Fill the filter to exactly the capacity it was sized for, which is the worst case.
+        let mut filter = NullifierFilter::with_capacity(10_000);
+        let capacity = filter.num_bits() / NullifierFilter::BITS_PER_ITEM;
+        for nullifier in nullifiers(&mut rng, capacity as usize) {
+            filter.insert(&nullifier);
+        }
+        assert!(!filter.is_full());
+
+        let trials = 10_000;
+        let false_positives = nullifiers(&mut rng, trials)
+            .iter()
+            .filter(|nullifier| filter.may_contain(nullifier))
+            .count();
+        // The expected rate is below 1%; allow for sampling noise.
+        assert!(
+            false_positives * 100 < trials * 2,
+            "{} false positives in {} trials",
+            false_positives,
+            trials
+        );
+
+        filter.insert(&Nullifier::random_for_test(&mut rng));
+        assert!(filter.is_full());
+    }
+
+    #[test]
+    fn test_serialization_format() {
+        let mut rng = ChaChaRng::from_seed([2; 32]);
+        let inserted = nullifiers(&mut rng, 100);
+        let filter = NullifierFilter::from_nullifiers(inserted.iter());
+        let bytes = filter.to_bytes();
+
+        // Check membership from the bytes alone, as documented for clients.
+        let k = u32::from_le_bytes(bytes[0..4].try_into().unwrap());
+        assert_eq!(k, NullifierFilter::NUM_HASHES);
+        let bits = &bytes[4..];
+        assert!(bits.len() >= NullifierFilter::MIN_BYTES);
+        let m = bits.len() as u64 * 8;
+        let may_contain = |nullifier: &Nullifier| {
+            let h = keccak256(nullifier.tagged_blob().value());
+            let h1 = u64::from_le_bytes(h[0..8].try_into().unwrap());
+            let h2 = u64::from_le_bytes(h[8..16].try_into().unwrap());
+            (0..k as u64).all(|i| {
+                let bit = h1.wrapping_add(i.wrapping_mul(h2)) % m;
+                bits[(bit / 8) as usize] & (1 << (bit % 8)) != 0
+            })
+        };
+        for nullifier in &inserted {
+            assert!(may_contain(nullifier));
+        }
+        for nullifier in nullifiers(&mut rng, 100) {
+            assert_eq!(may_contain(&nullifier), filter.may_contain(&nullifier));
+        }
+    }
+}
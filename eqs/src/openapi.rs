@@ -0,0 +1,337 @@
+// Copyright (c) 2022 Espresso Systems (espressosys.com)
+// This file is part of the Configurable Asset Privacy for Ethereum (CAPE) library.
+
+// This program is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+// You should have received a copy of the GNU General Public License along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! OpenAPI 3 description of the EQS web API.
+//!
+//! The document is built from `api.toml` by the [openapi](::openapi) crate. The schemas here
+//! describe the JSON serialization of the types taken and returned by the endpoints in
+//! [crate::routes], so they must be kept in sync with those types.
+
+use ::openapi::{array, object, opaque, schema_ref, tagged, tuple, uint, variant};
+use serde_json::{json, Map, Value};
+
+/// Builds the OpenAPI document for the routes in `api`.
+pub fn openapi_document(api: &toml::Value) -> Value {
+    ::openapi::openapi_document(api, response_schemas())
+}
+
+/// Schemas of the request and response types, by the names used for `REQUEST` and `RESPONSE` in
+/// api.toml.
+pub fn response_schemas() -> Map<String, Value> {
+    let mut schemas = Map::new();
+    let mut add = |name: &str, schema: Value| {
+        schemas.insert(name.to_string(), schema);
+    };
+
+    add("Boolean", json!({ "type": "boolean" }));
+    add("Nullifier", tagged("NUL"));
+    add("NullifierSet", array(schema_ref("Nullifier")));
+    add("Nullifiers", array(schema_ref("Nullifier")));
+    add("Booleans", array(json!({ "type": "boolean" })));
+    add(
+        "NullifierPage",
+        object(vec![
+            ("nullifiers", array(schema_ref("Nullifier"))),
+            ("next", uint()),
+            ("total", uint()),
+        ]),
+    );
+    add(
+        "NullifierFilter",
+        json!({
+            "type": "array",
+            "items": { "type": "integer", "minimum": 0, "maximum": 255 },
+            "description": "A Bloom filter of the spent nullifiers; see get_nullifier_filter",
+        }),
+    );
+    add("Erc20Code", tagged("ERC20"));
+    add("EthereumAddr", tagged("EADDR"));
+    add("AssetDefinition", tagged("ASSET_DEF"));
+    add("AssetCode", tagged("ASSET_CODE"));
+    add("UserAddress", tagged("ADDR"));
+    add(
+        "Error",
+        opaque("An EQSNetError, serialized as an object with the error kind as its only key."),
+    );
+
+    add(
+        "CapeLedgerState",
+        object(vec![
+            ("state_number", uint()),
+            (
+                "record_merkle_commitment",
+                object(vec![
+                    ("root_value", opaque("Merkle root")),
+                    ("height", uint()),
+                    ("num_leaves", uint()),
+                ]),
+            ),
+            (
+                "record_merkle_frontier",
+                opaque("Frontier of the record Merkle tree"),
+            ),
+            (
+                "past_record_merkle_roots",
+                array(opaque("Recent Merkle root, most recent first")),
+            ),
+        ]),
+    );
+    add(
+        "CapState",
+        object(vec![
+            ("ledger", schema_ref("CapeLedgerState")),
+            ("num_events", uint()),
+        ]),
+    );
+    add(
+        "SyncStatus",
+        object(vec![
+            ("last_updated_block_height", uint()),
+            ("head_block_height", uint()),
+        ]),
+    );
+
+    add(
+        "CapeModelTxn",
+        json!({
+            "oneOf": [
+                variant("CAP", opaque("A CAP transaction note")),
+                variant("Burn", object(vec![
+                    ("xfr", opaque("The transfer note of the burn")),
+                    ("ro", opaque("The opening of the burned record")),
+                ])),
+            ]
+        }),
+    );
+    add(
+        "CapeTransition",
+        json!({
+            "oneOf": [
+                variant("Transaction", schema_ref("CapeModelTxn")),
+                variant("Wrap", object(vec![
+                    ("erc20_code", schema_ref("Erc20Code")),
+                    ("src_addr", schema_ref("EthereumAddr")),
+                    ("ro", opaque("The opening of the wrapped record")),
+                ])),
+            ]
+        }),
+    );
+    add(
+        "CommittedCapeTransition",
+        object(vec![
+            ("block_id", uint()),
+            ("txn_id", uint()),
+            ("output_start", uint()),
+            ("output_size", uint()),
+            ("transition", schema_ref("CapeTransition")),
+        ]),
+    );
+    add(
+        "CommittedBlock",
+        object(vec![
+            ("block_id", uint()),
+            (
+                "eth_txn_hash",
+                json!({ "type": "string", "pattern": "^0x[0-9a-f]{64}$" }),
+            ),
+            (
+                "gas_used",
+                json!({ "type": "integer", "minimum": 0, "nullable": true }),
+            ),
+            ("timestamp", uint()),
+            ("transactions", array(schema_ref("CommittedCapeTransition"))),
+        ]),
+    );
+    add("CommittedBlocks", array(schema_ref("CommittedBlock")));
+    add(
+        "CapeTransactionKind",
+        json!({
+            "oneOf": [
+                variant("CAP", opaque("The kind of a CAP transaction, such as Send or Mint")),
+                { "type": "string", "enum": ["Burn", "Wrap"] },
+            ]
+        }),
+    );
+    add(
+        "LedgerEvent",
+        json!({
+            "oneOf": [
+                variant("Commit", object(vec![
+                    ("block", array(schema_ref("CapeTransition"))),
+                    ("block_id", uint()),
+                    ("state_comm", uint()),
+                ])),
+                variant("Reject", object(vec![
+                    ("block", array(schema_ref("CapeTransition"))),
+                    ("error", opaque("The reason the block was rejected")),
+                ])),
+                variant("Memos", object(vec![
+                    (
+                        "outputs",
+                        array(tuple(vec![
+                            opaque("Receiver memo"),
+                            opaque("Record commitment"),
+                            uint(),
+                            opaque("Merkle path of the record"),
+                        ])),
+                    ),
+                    (
+                        "transaction",
+                        json!({
+                            "allOf": [tuple(vec![uint(), uint(), schema_ref("CapeTransactionKind")])],
+                            "nullable": true,
+                            "description": "Block id, transaction id and kind of the transaction which produced the outputs",
+                        }),
+                    ),
+                ])),
+            ]
+        }),
+    );
+    add("LedgerEvents", array(schema_ref("LedgerEvent")));
+    add(
+        "FilteredEvents",
+        object(vec![
+            (
+                "events",
+                array(tuple(vec![uint(), schema_ref("LedgerEvent")])),
+            ),
+            ("next", uint()),
+        ]),
+    );
+
+    add(
+        "CheckpointSummary",
+        object(vec![
+            (
+                "contract",
+                json!({ "type": "string", "pattern": "^0x[0-9a-f]{40}$" }),
+            ),
+            ("block_height", uint()),
+            ("ledger_state", schema_ref("CapeLedgerState")),
+            ("num_events", uint()),
+            (
+                "nullifier_digest",
+                array(json!({ "type": "integer", "minimum": 0, "maximum": 255 })),
+            ),
+            (
+                "sponsored_assets_digest",
+                array(json!({ "type": "integer", "minimum": 0, "maximum": 255 })),
+            ),
+        ]),
+    );
+    add(
+        "SignedCheckpoint",
+        object(vec![
+            ("summary", schema_ref("CheckpointSummary")),
+            (
+                "signature",
+                object(vec![
+                    ("r", opaque("U256")),
+                    ("s", opaque("U256")),
+                    ("v", uint()),
+                ]),
+            ),
+        ]),
+    );
+    add(
+        "ViewedRecord",
+        object(vec![
+            ("asset_code", schema_ref("AssetCode")),
+            (
+                "address",
+                json!({ "allOf": [schema_ref("UserAddress")], "nullable": true }),
+            ),
+            (
+                "amount",
+                json!({ "type": "integer", "minimum": 0, "nullable": true }),
+            ),
+        ]),
+    );
+    add(
+        "ViewedTransaction",
+        object(vec![
+            ("block_id", uint()),
+            ("txn_id", uint()),
+            ("kind", schema_ref("CapeTransactionKind")),
+            ("inputs", array(schema_ref("ViewedRecord"))),
+            ("outputs", array(schema_ref("ViewedRecord"))),
+        ]),
+    );
+    add(
+        "ViewedTransactions",
+        object(vec![
+            ("transactions", array(schema_ref("ViewedTransaction"))),
+            ("next", uint()),
+        ]),
+    );
+
+    add(
+        "Erc20Transfer",
+        object(vec![
+            ("erc20_code", schema_ref("Erc20Code")),
+            ("eth_addr", schema_ref("EthereumAddr")),
+            ("amount", uint()),
+            ("block_id", uint()),
+            ("txn_id", uint()),
+        ]),
+    );
+    add(
+        "Erc20History",
+        object(vec![
+            ("deposits", array(schema_ref("Erc20Transfer"))),
+            ("withdrawals", array(schema_ref("Erc20Transfer"))),
+            ("net_deposited", uint()),
+        ]),
+    );
+    add(
+        "Erc20AddressHistory",
+        object(vec![
+            ("deposits", array(schema_ref("Erc20Transfer"))),
+            ("withdrawals", array(schema_ref("Erc20Transfer"))),
+            (
+                "net_deposited",
+                array(tuple(vec![
+                    schema_ref("Erc20Code"),
+                    json!({ "type": "integer" }),
+                ])),
+            ),
+        ]),
+    );
+    add(
+        "Erc20Locked",
+        array(tuple(vec![schema_ref("Erc20Code"), uint()])),
+    );
+    add(
+        "SponsoredAsset",
+        object(vec![
+            ("asset_def", schema_ref("AssetDefinition")),
+            ("erc20_code", schema_ref("Erc20Code")),
+            ("sponsor", schema_ref("EthereumAddr")),
+        ]),
+    );
+    add("SponsoredAssets", array(schema_ref("SponsoredAsset")));
+
+    schemas
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::disco::load_messages;
+    use std::path::Path;
+
+    #[test]
+    fn test_response_schemas() {
+        let api = load_messages(&Path::new(env!("CARGO_MANIFEST_DIR")).join("api/api.toml"));
+        // Every RESPONSE and REQUEST in api.toml has a schema.
+        if let Err(errors) = ::openapi::check_schemas(&api, &response_schemas()) {
+            panic!("{}", errors.join("\n"));
+        }
+        let document = openapi_document(&api);
+        assert!(document["components"]["schemas"]["Error"].is_object());
+    }
+}
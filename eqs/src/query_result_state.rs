@@ -5,32 +5,109 @@
 // This program is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
 // You should have received a copy of the GNU General Public License along with this program. If not, see <https://www.gnu.org/licenses/>.
 
+use crate::audit::AuditState;
+use crate::checkpoint::SignedCheckpoint;
+use crate::nullifier_filter::NullifierFilter;
 use cap_rust_sandbox::ledger::{CapeLedger, CapeTransition, CommittedCapeTransition};
-use cap_rust_sandbox::model::{CapeLedgerState, CapeRecordMerkleHistory, CAPE_MERKLE_HEIGHT};
-use commit::Commitment;
-use ethers::prelude::Address;
-use jf_cap::structs::Nullifier;
+use cap_rust_sandbox::model::{
+    extract_burn_dst, CapeLedgerState, CapeModelTxn, CapeRecordMerkleHistory, Erc20Code,
+    EthereumAddr, CAPE_MERKLE_HEIGHT,
+};
+use commit::{Commitment, Committable};
+use ethers::prelude::{Address, H256};
+use jf_cap::structs::{AssetCode, AssetDefinition, Nullifier, RecordCommitment};
 use jf_cap::MerkleTree;
 use key_set::VerifierKeySet;
+use reef::traits::Transaction;
 use seahorse::events::LedgerEvent;
 use serde::{Deserialize, Serialize};
-use std::collections::{HashMap, HashSet, VecDeque};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+
+/// The state of the ledger right after a block was committed.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LedgerSnapshot {
+    pub ledger_state: CapeLedgerState,
+    pub num_events: u64,
+}
+
+/// An ERC-20 deposit into (wrap) or withdrawal from (burn) the CAPE contract.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Erc20Transfer {
+    pub erc20_code: Erc20Code,
+    // source of a deposit, destination of a withdrawal
+    pub eth_addr: EthereumAddr,
+    pub amount: u64,
+    pub block_id: u64,
+    pub txn_id: u64,
+}
+
+/// Details of the Ethereum transaction which committed a block.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BlockInfo {
+    pub eth_txn_hash: H256,
+    // not known if the node did not report it in the transaction receipt
+    pub gas_used: Option<u64>,
+    // Unix time of the Ethereum block
+    pub timestamp: u64,
+    // transactions followed by wraps
+    pub num_transactions: u64,
+}
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct QueryResultState {
     // latest state, primary source
     pub ledger_state: CapeLedgerState,
     pub nullifiers: HashSet<Nullifier>,
+    // spent nullifiers in the order they were committed, for paging
+    pub nullifier_list: Vec<Nullifier>,
+    pub nullifier_filter: NullifierFilter,
     pub verifier_keys: VerifierKeySet,
     pub last_updated_block_height: u64,
+    // most recent head of the chain seen while syncing
+    pub head_block_height: u64,
     pub contract_address: Option<Address>,
+    // (block number, log index) of the last contract event included in this state
+    pub last_processed_event: Option<(u64, u64)>,
+    // set if the record Merkle root stopped matching the contract; the state is not served or
+    // updated any more
+    pub integrity_failure: Option<String>,
+    // signed summary of the latest checkpoint of this state
+    pub last_checkpoint: Option<SignedCheckpoint>,
+    // wraps which will be included in the next committed block
+    pub pending_commit_event: Vec<CapeTransition>,
 
     // accumulated list of CAPE events
     pub events: Vec<LedgerEvent<CapeLedger>>,
+    // index in the full event log of `events[0]`: the events before a checkpoint are missing from
+    // a state bootstrapped from it
+    pub first_event: u64,
+
+    // ledger state after each of the most recent committed blocks, by block id
+    pub ledger_history: BTreeMap<u64, LedgerSnapshot>,
+    // Ethereum details of each committed block, by block id
+    pub block_info: BTreeMap<u64, BlockInfo>,
 
     // additional indexed data for queries
     pub transaction_by_id: HashMap<(u64, u64), CommittedCapeTransition>,
     pub transaction_id_by_hash: HashMap<Commitment<CapeTransition>, (u64, u64)>,
+    pub transaction_id_by_nullifier: HashMap<Nullifier, (u64, u64)>,
+    pub transaction_id_by_output: HashMap<RecordCommitment, (u64, u64)>,
+    // transactions by the uid of their first output, for looking up the transaction of a uid
+    pub transaction_id_by_output_start: BTreeMap<u64, (u64, u64)>,
+
+    // ERC-20 deposits and withdrawals, in the order they were committed
+    pub erc20_deposits: Vec<Erc20Transfer>,
+    pub erc20_withdrawals: Vec<Erc20Transfer>,
+    // amount of each ERC-20 currently locked in the contract
+    pub erc20_deposited: HashMap<Erc20Code, u128>,
+    // ERC-20 wrapped by each asset which has been deposited, to attribute its withdrawals
+    pub wrapped_erc20: HashMap<AssetCode, Erc20Code>,
+
+    // sponsored CAPE assets, with the ERC-20 they wrap and their sponsor
+    pub erc20_registrar: HashMap<AssetDefinition, (Erc20Code, EthereumAddr)>,
+
+    // transactions viewable by the registered viewing keys
+    pub audit: AuditState,
 }
 
 impl QueryResultState {
@@ -48,14 +125,269 @@ impl QueryResultState {
                 )),
             },
             nullifiers: HashSet::new(),
+            nullifier_list: Vec::new(),
+            nullifier_filter: NullifierFilter::with_capacity(0),
             verifier_keys,
             last_updated_block_height: 0,
+            head_block_height: 0,
             contract_address: None,
+            last_processed_event: None,
+            integrity_failure: None,
+            last_checkpoint: None,
+            pending_commit_event: Vec::new(),
 
             events: Vec::new(),
+            first_event: 0,
+
+            ledger_history: BTreeMap::new(),
+            block_info: BTreeMap::new(),
 
             transaction_by_id: HashMap::new(),
             transaction_id_by_hash: HashMap::new(),
+            transaction_id_by_nullifier: HashMap::new(),
+            transaction_id_by_output: HashMap::new(),
+            transaction_id_by_output_start: BTreeMap::new(),
+
+            erc20_deposits: Vec::new(),
+            erc20_withdrawals: Vec::new(),
+            erc20_deposited: HashMap::new(),
+            wrapped_erc20: HashMap::new(),
+
+            erc20_registrar: HashMap::new(),
+
+            audit: AuditState::default(),
+        }
+    }
+
+    /// Number of events in the full event log, including any this state does not have.
+    pub fn num_events(&self) -> u64 {
+        self.first_event + self.events.len() as u64
+    }
+
+    /// Record the ledger state after a committed block, keeping the snapshots of at most
+    /// `max_snapshots` of the most recent blocks.
+    pub fn insert_snapshot(
+        &mut self,
+        block_id: u64,
+        snapshot: LedgerSnapshot,
+        max_snapshots: usize,
+    ) {
+        self.ledger_history.insert(block_id, snapshot);
+        while self.ledger_history.len() > max_snapshots {
+            let oldest = *self.ledger_history.keys().next().unwrap();
+            self.ledger_history.remove(&oldest);
+        }
+    }
+
+    /// Record a nullifier spent by a committed transaction.
+    pub fn insert_nullifier(&mut self, nullifier: Nullifier) {
+        if !self.nullifiers.insert(nullifier) {
+            return;
+        }
+        self.nullifier_list.push(nullifier);
+        self.nullifier_filter.insert(&nullifier);
+        if self.nullifier_filter.is_full() {
+            self.nullifier_filter = NullifierFilter::from_nullifiers(self.nullifier_list.iter());
+        }
+    }
+
+    /// Record a committed transaction, indexed by id, hash, spent nullifiers and outputs.
+    pub fn index_transaction(&mut self, txn: CommittedCapeTransition) {
+        let txn_id = (txn.block_id, txn.txn_id);
+        self.transaction_id_by_hash
+            .insert(txn.transition.commit(), txn_id);
+        for (nullifier, _) in txn.transition.proven_nullifiers() {
+            self.insert_nullifier(nullifier);
+            self.transaction_id_by_nullifier.insert(nullifier, txn_id);
+        }
+        for output in txn.transition.output_commitments() {
+            self.transaction_id_by_output.insert(output, txn_id);
+        }
+        if txn.output_size > 0 {
+            self.transaction_id_by_output_start
+                .insert(txn.output_start, txn_id);
+        }
+        self.transaction_by_id.insert(txn_id, txn);
+    }
+
+    /// The committed transaction which created the record with the given uid, if any. Records
+    /// which were not created by a transaction, like the faucet record, have none.
+    pub fn transaction_by_uid(&self, uid: u64) -> Option<&CommittedCapeTransition> {
+        let (_, txn_id) = self
+            .transaction_id_by_output_start
+            .range(..=uid)
+            .next_back()?;
+        self.transaction_by_id
+            .get(txn_id)
+            .filter(|txn| uid < txn.output_start + txn.output_size)
+    }
+
+    /// Index the ERC-20 deposits and withdrawals of a committed block.
+    pub fn record_erc20_transfers(&mut self, block_id: u64, transitions: &[CapeTransition]) {
+        for (txn_id, transition) in transitions.iter().enumerate() {
+            match transition {
+                CapeTransition::Wrap {
+                    erc20_code,
+                    src_addr,
+                    ro,
+                } => {
+                    self.wrapped_erc20
+                        .insert(ro.asset_def.code, erc20_code.clone());
+                    *self.erc20_deposited.entry(erc20_code.clone()).or_insert(0) +=
+                        ro.amount as u128;
+                    self.erc20_deposits.push(Erc20Transfer {
+                        erc20_code: erc20_code.clone(),
+                        eth_addr: src_addr.clone(),
+                        amount: ro.amount,
+                        block_id,
+                        txn_id: txn_id as u64,
+                    });
+                }
+                CapeTransition::Transaction(CapeModelTxn::Burn { xfr, ro }) => {
+                    let erc20_code = match self.wrapped_erc20.get(&ro.asset_def.code) {
+                        Some(erc20_code) => erc20_code.clone(),
+                        None => {
+                            // Only wrapped records can be burned, so a deposit of this asset is
+                            // missing from the state.
+                            tracing::warn!(
+                                "Burn {} of block {} is of asset {:?}, which was never wrapped; \
+                                 not recording it as a withdrawal",
+                                txn_id,
+                                block_id,
+                                ro.asset_def.code
+                            );
+                            continue;
+                        }
+                    };
+                    let dst_addr = match extract_burn_dst(xfr) {
+                        Some(Some(dst_addr)) => dst_addr,
+                        _ => continue,
+                    };
+                    if let Some(deposited) = self.erc20_deposited.get_mut(&erc20_code) {
+                        *deposited = deposited.saturating_sub(ro.amount as u128);
+                    }
+                    self.erc20_withdrawals.push(Erc20Transfer {
+                        erc20_code,
+                        eth_addr: dst_addr,
+                        amount: ro.amount,
+                        block_id,
+                        txn_id: txn_id as u64,
+                    });
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::configuration::EQSOptions;
+    use cap_rust_sandbox::model::erc20_asset_description;
+    use cap_rust_sandbox::test_utils::generate_burn_tx;
+    use jf_cap::keys::{CredIssuerPubKey, UserKeyPair};
+    use jf_cap::structs::{AssetCode, AssetPolicy, FreezeFlag, RecordOpening};
+    use rand_chacha::rand_core::SeedableRng;
+    use rand_chacha::ChaChaRng;
+    use structopt::StructOpt;
+
+    #[test]
+    fn test_erc20_transfers() {
+        let mut rng = ChaChaRng::from_seed([3; 32]);
+        let mut state = QueryResultState::new(EQSOptions::from_iter(["eqs"]).verifier_keys());
+
+        let erc20_code = Erc20Code(EthereumAddr([1; 20]));
+        let sponsor = EthereumAddr([2; 20]);
+        let depositor = EthereumAddr([3; 20]);
+        let recipient = EthereumAddr([4; 20]);
+        let asset_def = AssetDefinition::new(
+            AssetCode::new_foreign(&erc20_asset_description(&erc20_code, &sponsor)),
+            AssetPolicy::rand_for_test(&mut rng)
+                .set_cred_issuer_pub_key(CredIssuerPubKey::default()),
+        )
+        .unwrap();
+
+        let owner = UserKeyPair::generate(&mut rng);
+        let fee_ro = RecordOpening::new(
+            &mut rng,
+            100,
+            AssetDefinition::native(),
+            owner.pub_key(),
+            FreezeFlag::Unfrozen,
+        );
+        let mut wrap = |amount| {
+            let ro = RecordOpening::new(
+                &mut rng,
+                amount,
+                asset_def.clone(),
+                owner.pub_key(),
+                FreezeFlag::Unfrozen,
+            );
+            (
+                ro.clone(),
+                CapeTransition::Wrap {
+                    ro: Box::new(ro),
+                    erc20_code: erc20_code.clone(),
+                    src_addr: depositor.clone(),
+                },
+            )
+        };
+        let (wrapped_ro, first_wrap) = wrap(1000);
+        let (_, second_wrap) = wrap(500);
+
+        state.record_erc20_transfers(1, &[first_wrap, second_wrap]);
+        assert_eq!(state.erc20_deposits.len(), 2);
+        assert_eq!(state.erc20_deposits[1].amount, 500);
+        assert_eq!(state.erc20_deposits[1].txn_id, 1);
+        assert_eq!(state.erc20_deposited[&erc20_code], 1500);
+
+        let mut mt = MerkleTree::new(CAPE_MERKLE_HEIGHT).unwrap();
+        mt.push(RecordCommitment::from(&fee_ro).to_field_element());
+        mt.push(RecordCommitment::from(&wrapped_ro).to_field_element());
+        let burn_note = generate_burn_tx(
+            &owner,
+            fee_ro,
+            wrapped_ro,
+            &mt,
+            0,
+            1,
+            Address::from(recipient.0),
+        );
+        let burn = CapeTransition::Transaction(CapeModelTxn::Burn {
+            xfr: Box::new(burn_note.transfer_note),
+            ro: Box::new(burn_note.burned_ro),
+        });
+
+        // Without the deposits, the burned asset wraps no known ERC-20.
+        let mut unwrapped = QueryResultState::new(EQSOptions::from_iter(["eqs"]).verifier_keys());
+        unwrapped.record_erc20_transfers(2, &[burn.clone()]);
+        assert!(unwrapped.erc20_withdrawals.is_empty());
+        assert!(unwrapped.erc20_deposited.is_empty());
+
+        state.record_erc20_transfers(3, &[burn]);
+        assert_eq!(state.erc20_withdrawals.len(), 1);
+        let withdrawal = &state.erc20_withdrawals[0];
+        assert_eq!(withdrawal.erc20_code, erc20_code);
+        assert_eq!(withdrawal.eth_addr, recipient);
+        assert_eq!(withdrawal.amount, 1000);
+        assert_eq!(withdrawal.block_id, 3);
+        assert_eq!(state.erc20_deposited[&erc20_code], 500);
+    }
+
+    #[test]
+    fn test_ledger_history_retention() {
+        let mut state = QueryResultState::new(EQSOptions::from_iter(["eqs"]).verifier_keys());
+        for block_id in 1..=5 {
+            let snapshot = LedgerSnapshot {
+                ledger_state: state.ledger_state.clone(),
+                num_events: block_id,
+            };
+            state.insert_snapshot(block_id, snapshot, 3);
         }
+        assert_eq!(
+            state.ledger_history.keys().cloned().collect::<Vec<_>>(),
+            vec![3, 4, 5]
+        );
     }
 }
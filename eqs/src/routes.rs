@@ -5,49 +5,32 @@
 // This program is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
 // You should have received a copy of the GNU General Public License along with this program. If not, see <https://www.gnu.org/licenses/>.
 
-use crate::api_server::WebState;
-use crate::query_result_state::QueryResultState;
+use crate::api_server::{contract_state, WebState};
+use crate::audit::ViewedTransaction;
+use crate::checkpoint::SignedCheckpoint;
+use crate::errors::{server_error, EQSNetError};
+use crate::query_result_state::{Erc20Transfer, QueryResultState};
 use crate::route_parsing::*;
 
-use cap_rust_sandbox::ledger::{CapeLedger, CommitmentToCapeTransition, CommittedCapeTransition};
-use cap_rust_sandbox::model::CapeLedgerState;
-use jf_cap::structs::Nullifier;
+use async_std::io::ReadExt;
+use cap_rust_sandbox::ledger::{
+    CapeLedger, CapeTransactionKind, CapeTransition, CommitmentToCapeTransition,
+    CommittedCapeTransition,
+};
+use cap_rust_sandbox::model::{CapeLedgerState, Erc20Code, EthereumAddr};
+use ethers::prelude::H256;
+use jf_cap::keys::AuditorPubKey;
+use jf_cap::structs::{AssetDefinition, Nullifier, RecordCommitment};
 use net::server::response;
+use reef::traits::{Block, Transaction, TransactionKind};
 use seahorse::events::LedgerEvent;
 use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, HashSet};
 use std::str::FromStr;
-use strum::IntoEnumIterator;
 use strum_macros::{AsRefStr, EnumIter, EnumString};
 
-/// Index entries for documentation fragments
-#[allow(non_camel_case_types)]
-#[derive(AsRefStr, Copy, Clone, Debug, EnumIter, EnumString)]
-pub enum ApiRouteKey {
-    get_cap_state,
-    get_all_nullifiers,
-    check_nullifier,
-    get_events_since,
-    get_transaction,
-    get_transaction_by_hash,
-}
-
-/// Verify that every variant of enum ApiRouteKey is defined in api.toml
-// TODO !corbett Check all the other things that might fail after startup.
-pub fn check_api(api: toml::Value) -> bool {
-    let mut missing_definition = false;
-    for key in ApiRouteKey::iter() {
-        let key_str = key.as_ref();
-        if api["route"].get(key_str).is_none() {
-            println!("Missing API definition for [route.{}]", key_str);
-            missing_definition = true;
-        }
-    }
-    if missing_definition {
-        panic!("api.toml is inconsistent with enum ApiRouteKey");
-    }
-    !missing_definition
-}
+// Defines `ApiRouteKey`, with a variant for each route in api.toml. See build.rs.
+include!(concat!(env!("OUT_DIR"), "/api_routes.rs"));
 
 #[allow(dead_code)]
 pub fn dummy_url_eval(
@@ -95,7 +78,34 @@ pub struct CapState {
 pub async fn get_cap_state(query_result_state: &QueryResultState) -> Result<CapState, tide::Error> {
     Ok(CapState {
         ledger: query_result_state.ledger_state.clone(),
-        num_events: query_result_state.events.len() as u64,
+        num_events: query_result_state.num_events(),
+    })
+}
+
+/// The state of the ledger right after the block with the given id was committed, if any.
+pub async fn get_cap_state_at_block(
+    bindings: &HashMap<String, RouteBinding>,
+    query_result_state: &QueryResultState,
+) -> Result<Option<CapState>, tide::Error> {
+    Ok(query_result_state
+        .ledger_history
+        .get(&bindings[":block_id"].value.as_u64()?)
+        .map(|snapshot| CapState {
+            ledger: snapshot.ledger_state.clone(),
+            num_events: snapshot.num_events,
+        }))
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SyncStatus {
+    pub last_updated_block_height: u64,
+    pub head_block_height: u64,
+}
+
+pub async fn sync_status(query_result_state: &QueryResultState) -> Result<SyncStatus, tide::Error> {
+    Ok(SyncStatus {
+        last_updated_block_height: query_result_state.last_updated_block_height,
+        head_block_height: query_result_state.head_block_height,
     })
 }
 
@@ -105,6 +115,43 @@ pub async fn get_all_nullifiers(
     Ok(query_result_state.nullifiers.clone())
 }
 
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct NullifierPage {
+    pub nullifiers: Vec<Nullifier>,
+    /// Index to pass as `:first` to get the next page.
+    pub next: u64,
+    /// Number of nullifiers spent so far.
+    pub total: u64,
+}
+
+/// The most nullifiers `get_nullifiers` returns in one request.
+pub const MAX_NULLIFIER_PAGE: usize = 1000;
+
+pub async fn get_nullifiers(
+    bindings: &HashMap<String, RouteBinding>,
+    query_result_state: &QueryResultState,
+) -> Result<NullifierPage, tide::Error> {
+    let total = query_result_state.nullifier_list.len();
+    let first = std::cmp::min(bindings[":first"].value.as_u64()? as usize, total);
+    let max_count = std::cmp::min(
+        bindings[":max_count"].value.as_u64()? as usize,
+        MAX_NULLIFIER_PAGE,
+    );
+    let last = std::cmp::min(first.saturating_add(max_count), total);
+    Ok(NullifierPage {
+        nullifiers: query_result_state.nullifier_list[first..last].to_vec(),
+        next: last as u64,
+        total: total as u64,
+    })
+}
+
+/// The serialized Bloom filter of the spent nullifiers.
+pub async fn get_nullifier_filter(
+    query_result_state: &QueryResultState,
+) -> Result<Vec<u8>, tide::Error> {
+    Ok(query_result_state.nullifier_filter.to_bytes())
+}
+
 pub async fn check_nullifier(
     bindings: &HashMap<String, RouteBinding>,
     query_result_state: &QueryResultState,
@@ -114,25 +161,255 @@ pub async fn check_nullifier(
         .contains(&bindings[":nullifier"].value.to::<Nullifier>()?))
 }
 
+/// Maximum number of nullifiers in a `check_nullifiers` request.
+pub const MAX_NULLIFIER_BATCH: usize = 1000;
+
+/// Bound on the size of one nullifier in a `check_nullifiers` request body, as a JSON string or as
+/// bincode, with room to spare.
+pub(crate) const MAX_NULLIFIER_SIZE: usize = 128;
+
+/// Read the nullifiers in the body of a `check_nullifiers` request.
+///
+/// At most the size of a full batch is read, so an oversized body is rejected before it is buffered
+/// or deserialized.
+async fn nullifiers_body(req: &mut tide::Request<WebState>) -> Result<Vec<Nullifier>, tide::Error> {
+    let max = MAX_NULLIFIER_BATCH * MAX_NULLIFIER_SIZE;
+    let mut bytes = Vec::new();
+    req.take_body()
+        .take(max as u64 + 1)
+        .read_to_end(&mut bytes)
+        .await?;
+    if bytes.len() > max {
+        return Err(server_error(EQSNetError::BodyTooLarge { max }));
+    }
+    let nullifiers: Result<Vec<Nullifier>, String> =
+        match req.content_type().as_ref().map(|mime| mime.essence()) {
+            Some("application/json") => {
+                serde_json::from_slice(&bytes).map_err(|err| err.to_string())
+            }
+            Some("application/octet-stream") => {
+                bincode::deserialize(&bytes).map_err(|err| err.to_string())
+            }
+            Some(content_type) => Err(format!("unsupported content type {}", content_type)),
+            None => Err(String::from("unspecified content type")),
+        };
+    let nullifiers = nullifiers.map_err(|msg| server_error(EQSNetError::Deserialize { msg }))?;
+    if nullifiers.len() > MAX_NULLIFIER_BATCH {
+        return Err(server_error(EQSNetError::BatchTooLarge {
+            size: nullifiers.len(),
+            max: MAX_NULLIFIER_BATCH,
+        }));
+    }
+    Ok(nullifiers)
+}
+
+pub async fn check_nullifiers(
+    nullifiers: &[Nullifier],
+    query_result_state: &QueryResultState,
+) -> Result<Vec<bool>, tide::Error> {
+    Ok(nullifiers
+        .iter()
+        .map(|nullifier| query_result_state.nullifiers.contains(nullifier))
+        .collect())
+}
+
+/// The position in `events` of the event with index `first` in the full event log.
+///
+/// A state bootstrapped from a checkpoint does not have the events before the checkpoint.
+fn event_position(first: u64, query_result_state: &QueryResultState) -> Result<usize, tide::Error> {
+    if first < query_result_state.first_event {
+        return Err(server_error(EQSNetError::EventsUnavailable {
+            first,
+            first_available: query_result_state.first_event,
+        }));
+    }
+    Ok((first - query_result_state.first_event) as usize)
+}
+
 pub async fn get_events_since(
     bindings: &HashMap<String, RouteBinding>,
     query_result_state: &QueryResultState,
 ) -> Result<Vec<LedgerEvent<CapeLedger>>, tide::Error> {
     let first = if let Some(first) = bindings.get(":first") {
-        first.value.as_u64()? as usize
+        first.value.as_u64()?
     } else {
         0
     };
-    let events_len = query_result_state.events.len();
-    if first >= events_len {
+    let num_events = query_result_state.num_events();
+    if first >= num_events {
         return Ok(Vec::new());
     }
     let last = if let Some(max_count) = bindings.get(":max_count") {
-        std::cmp::min(first + max_count.value.as_u64()? as usize, events_len)
+        std::cmp::min(first.saturating_add(max_count.value.as_u64()?), num_events)
     } else {
-        events_len
+        num_events
     };
-    Ok(query_result_state.events[first..last].to_vec())
+    Ok(query_result_state.events
+        [event_position(first, query_result_state)?..event_position(last, query_result_state)?]
+        .to_vec())
+}
+
+/// Which kind of [LedgerEvent] to return from `get_filtered_events`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, EnumString)]
+#[strum(serialize_all = "snake_case")]
+pub enum EventKindFilter {
+    All,
+    Commit,
+    Memos,
+}
+
+/// Which kind of transaction the events returned from `get_filtered_events` must involve.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, EnumString)]
+#[strum(serialize_all = "snake_case")]
+pub enum TxnKindFilter {
+    All,
+    Wrap,
+    Burn,
+    Mint,
+    Freeze,
+    Transfer,
+}
+
+impl TxnKindFilter {
+    fn matches(&self, kind: &CapeTransactionKind) -> bool {
+        match self {
+            Self::All => true,
+            Self::Wrap => *kind == CapeTransactionKind::Wrap,
+            Self::Burn => *kind == CapeTransactionKind::Burn,
+            Self::Mint => *kind == CapeTransactionKind::mint(),
+            Self::Freeze => {
+                *kind == CapeTransactionKind::freeze() || *kind == CapeTransactionKind::unfreeze()
+            }
+            Self::Transfer => *kind == CapeTransactionKind::send(),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct EventFilter {
+    pub event_kind: EventKindFilter,
+    pub txn_kind: TxnKindFilter,
+    pub from_block: u64,
+    pub to_block: u64,
+}
+
+impl EventFilter {
+    fn from_bindings(bindings: &HashMap<String, RouteBinding>) -> Result<Self, tide::Error> {
+        let event_kind = EventKindFilter::from_str(&bindings[":event_kind"].value.as_string()?)
+            .map_err(|_| {
+                server_error(EQSNetError::Param {
+                    expected: String::from("all, commit or memos"),
+                    actual: bindings[":event_kind"].value.to_string(),
+                })
+            })?;
+        let txn_kind = if let Some(txn_kind) = bindings.get(":txn_kind") {
+            TxnKindFilter::from_str(&txn_kind.value.as_string()?).map_err(|_| {
+                server_error(EQSNetError::Param {
+                    expected: String::from("all, wrap, burn, mint, freeze or transfer"),
+                    actual: txn_kind.value.to_string(),
+                })
+            })?
+        } else {
+            TxnKindFilter::All
+        };
+        let from_block = if let Some(from_block) = bindings.get(":from_block") {
+            from_block.value.as_u64()?
+        } else {
+            0
+        };
+        let to_block = if let Some(to_block) = bindings.get(":to_block") {
+            to_block.value.as_u64()?
+        } else {
+            u64::MAX
+        };
+        Ok(Self {
+            event_kind,
+            txn_kind,
+            from_block,
+            to_block,
+        })
+    }
+
+    fn in_block_range(&self, block_id: u64) -> bool {
+        self.from_block <= block_id && block_id <= self.to_block
+    }
+
+    fn matches(&self, event: &LedgerEvent<CapeLedger>) -> bool {
+        match event {
+            LedgerEvent::Commit {
+                block, block_id, ..
+            } => {
+                self.event_kind != EventKindFilter::Memos
+                    && self.in_block_range(*block_id)
+                    && block
+                        .txns()
+                        .iter()
+                        .any(|txn: &CapeTransition| self.txn_kind.matches(&txn.kind()))
+            }
+            LedgerEvent::Memos { transaction, .. } => {
+                self.event_kind != EventKindFilter::Commit
+                    && match transaction {
+                        Some((block_id, _, kind)) => {
+                            self.in_block_range(*block_id) && self.txn_kind.matches(kind)
+                        }
+                        // Faucet memos are not part of any transaction or block.
+                        None => {
+                            self.txn_kind == TxnKindFilter::All
+                                && self.from_block == 0
+                                && self.to_block == u64::MAX
+                        }
+                    }
+            }
+            _ => false,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct FilteredEvents {
+    /// Matching events, each with its index in the full event list.
+    pub events: Vec<(u64, LedgerEvent<CapeLedger>)>,
+    /// Index to pass as `:first` to continue scanning after these events.
+    pub next: u64,
+}
+
+/// The most events `get_filtered_events` examines in one request, so that a filter which matches
+/// few events does not hold the state lock while the whole event log is scanned. A response may
+/// then contain fewer than `max_count` events even though more match later on; scanning resumes
+/// from `next`.
+pub const MAX_EVENTS_SCANNED: usize = 10_000;
+
+pub async fn get_filtered_events(
+    bindings: &HashMap<String, RouteBinding>,
+    query_result_state: &QueryResultState,
+) -> Result<FilteredEvents, tide::Error> {
+    let first = bindings[":first"].value.as_u64()?;
+    let max_count = bindings[":max_count"].value.as_u64()? as usize;
+    let filter = EventFilter::from_bindings(bindings)?;
+    let first_event = query_result_state.first_event;
+
+    let mut events = Vec::new();
+    let mut next = std::cmp::max(first, query_result_state.num_events());
+    if first < next {
+        let skip = event_position(first, query_result_state)?;
+        for (scanned, (position, event)) in query_result_state
+            .events
+            .iter()
+            .enumerate()
+            .skip(skip)
+            .enumerate()
+        {
+            let index = first_event + position as u64;
+            if events.len() >= max_count || scanned >= MAX_EVENTS_SCANNED {
+                next = index;
+                break;
+            }
+            if filter.matches(event) {
+                events.push((index, event.clone()));
+            }
+        }
+    }
+    Ok(FilteredEvents { events, next })
 }
 
 pub async fn get_transaction(
@@ -148,22 +425,18 @@ pub async fn get_transaction(
         .cloned())
 }
 
-pub async fn get_transaction_by_hash(
-    bindings: &HashMap<String, RouteBinding>,
+/// The transaction with an id found in one of the secondary indexes.
+fn indexed_transaction(
+    txn_id: Option<&(u64, u64)>,
     query_result_state: &QueryResultState,
 ) -> Result<Option<CommittedCapeTransition>, tide::Error> {
-    if let Some(txn_id) = query_result_state.transaction_id_by_hash.get(
-        &bindings[":hash"]
-            .value
-            .to::<CommitmentToCapeTransition>()?
-            .0,
-    ) {
+    if let Some(txn_id) = txn_id {
         if let Some(txn) = query_result_state.transaction_by_id.get(txn_id).cloned() {
             Ok(Some(txn))
         } else {
             Err(tide::Error::from_str(
                 tide::StatusCode::InternalServerError,
-                "Commitment indexed, but transaction not found",
+                "Transaction indexed, but not found",
             ))
         }
     } else {
@@ -171,29 +444,742 @@ pub async fn get_transaction_by_hash(
     }
 }
 
+pub async fn get_transaction_by_hash(
+    bindings: &HashMap<String, RouteBinding>,
+    query_result_state: &QueryResultState,
+) -> Result<Option<CommittedCapeTransition>, tide::Error> {
+    indexed_transaction(
+        query_result_state.transaction_id_by_hash.get(
+            &bindings[":hash"]
+                .value
+                .to::<CommitmentToCapeTransition>()?
+                .0,
+        ),
+        query_result_state,
+    )
+}
+
+pub async fn get_transaction_by_nullifier(
+    bindings: &HashMap<String, RouteBinding>,
+    query_result_state: &QueryResultState,
+) -> Result<Option<CommittedCapeTransition>, tide::Error> {
+    indexed_transaction(
+        query_result_state
+            .transaction_id_by_nullifier
+            .get(&bindings[":nullifier"].value.to::<Nullifier>()?),
+        query_result_state,
+    )
+}
+
+pub async fn get_transaction_by_output(
+    bindings: &HashMap<String, RouteBinding>,
+    query_result_state: &QueryResultState,
+) -> Result<Option<CommittedCapeTransition>, tide::Error> {
+    indexed_transaction(
+        query_result_state
+            .transaction_id_by_output
+            .get(&bindings[":commitment"].value.to::<RecordCommitment>()?),
+        query_result_state,
+    )
+}
+
+pub async fn get_transaction_by_uid(
+    bindings: &HashMap<String, RouteBinding>,
+    query_result_state: &QueryResultState,
+) -> Result<Option<CommittedCapeTransition>, tide::Error> {
+    Ok(query_result_state
+        .transaction_by_uid(bindings[":uid"].value.as_u64()?)
+        .cloned())
+}
+
+/// A committed block, with the Ethereum transaction which committed it.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CommittedBlock {
+    pub block_id: u64,
+    pub eth_txn_hash: H256,
+    pub gas_used: Option<u64>,
+    pub timestamp: u64,
+    /// Transactions followed by wraps, with the uids of their outputs.
+    pub transactions: Vec<CommittedCapeTransition>,
+}
+
+/// Maximum number of blocks returned by `get_latest_blocks`.
+pub const MAX_LATEST_BLOCKS: usize = 100;
+
+pub fn committed_block(
+    block_id: u64,
+    query_result_state: &QueryResultState,
+) -> Option<CommittedBlock> {
+    let info = query_result_state.block_info.get(&block_id)?;
+    Some(CommittedBlock {
+        block_id,
+        eth_txn_hash: info.eth_txn_hash,
+        gas_used: info.gas_used,
+        timestamp: info.timestamp,
+        transactions: (0..info.num_transactions)
+            .filter_map(|txn_id| {
+                query_result_state
+                    .transaction_by_id
+                    .get(&(block_id, txn_id))
+                    .cloned()
+            })
+            .collect(),
+    })
+}
+
+/// The most recently committed blocks, newest first.
+pub fn latest_blocks(count: usize, query_result_state: &QueryResultState) -> Vec<CommittedBlock> {
+    query_result_state
+        .block_info
+        .keys()
+        .rev()
+        .take(std::cmp::min(count, MAX_LATEST_BLOCKS))
+        .filter_map(|block_id| committed_block(*block_id, query_result_state))
+        .collect()
+}
+
+pub async fn get_block(
+    bindings: &HashMap<String, RouteBinding>,
+    query_result_state: &QueryResultState,
+) -> Result<Option<CommittedBlock>, tide::Error> {
+    Ok(committed_block(
+        bindings[":block_id"].value.as_u64()?,
+        query_result_state,
+    ))
+}
+
+pub async fn get_latest_blocks(
+    bindings: &HashMap<String, RouteBinding>,
+    query_result_state: &QueryResultState,
+) -> Result<Vec<CommittedBlock>, tide::Error> {
+    Ok(latest_blocks(
+        bindings[":count"].value.as_index()?,
+        query_result_state,
+    ))
+}
+
+pub async fn get_checkpoint(
+    query_result_state: &QueryResultState,
+) -> Result<Option<SignedCheckpoint>, tide::Error> {
+    Ok(query_result_state.last_checkpoint.clone())
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ViewedTransactions {
+    /// Viewable transactions, in the order they were committed.
+    pub transactions: Vec<ViewedTransaction>,
+    /// Index to pass as `:first` for the next page.
+    pub next: u64,
+}
+
+/// The most transactions `get_viewed_transactions` returns in one request.
+pub const MAX_VIEWED_TRANSACTIONS: usize = 1000;
+
+pub async fn get_viewed_transactions(
+    bindings: &HashMap<String, RouteBinding>,
+    query_result_state: &QueryResultState,
+) -> Result<ViewedTransactions, tide::Error> {
+    let key = bindings[":viewing_key"].value.to::<AuditorPubKey>()?;
+    let first = bindings[":first"].value.as_index()?;
+    let max_count = std::cmp::min(
+        bindings[":max_count"].value.as_index()?,
+        MAX_VIEWED_TRANSACTIONS,
+    );
+    match query_result_state.audit.ledgers.get(&key) {
+        Some(ledger) => {
+            let transactions: Vec<_> = ledger.iter().skip(first).take(max_count).cloned().collect();
+            Ok(ViewedTransactions {
+                next: (first + transactions.len()) as u64,
+                transactions,
+            })
+        }
+        None => Err(server_error(EQSNetError::UnknownViewingKey {
+            key: key.to_string(),
+        })),
+    }
+}
+
+/// Rejects requests which do not carry the `--viewing_token`, or all of them without one.
+fn check_viewing_token(req: &tide::Request<WebState>) -> Result<(), tide::Error> {
+    let token = req
+        .state()
+        .viewing_token
+        .as_ref()
+        .ok_or_else(|| server_error(EQSNetError::ViewingDisabled {}))?;
+    let presented = req
+        .header("Authorization")
+        .and_then(|values| values.as_str().strip_prefix("Bearer "));
+    match presented {
+        Some(presented) if tokens_match(presented.as_bytes(), token.as_bytes()) => Ok(()),
+        _ => Err(server_error(EQSNetError::Unauthorized {})),
+    }
+}
+
+/// Compares tokens in time independent of where they differ, so a token cannot be guessed
+/// byte by byte from response times.
+fn tokens_match(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Erc20History {
+    pub deposits: Vec<Erc20Transfer>,
+    pub withdrawals: Vec<Erc20Transfer>,
+    /// Amount of the token deposited minus the amount withdrawn, which is the amount of it locked
+    /// in the CAPE contract.
+    pub net_deposited: u128,
+}
+
+pub async fn get_erc20_history(
+    bindings: &HashMap<String, RouteBinding>,
+    query_result_state: &QueryResultState,
+) -> Result<Erc20History, tide::Error> {
+    let erc20_code = bindings[":erc20_code"].value.to::<Erc20Code>()?;
+    Ok(Erc20History {
+        deposits: query_result_state
+            .erc20_deposits
+            .iter()
+            .filter(|deposit| deposit.erc20_code == erc20_code)
+            .cloned()
+            .collect(),
+        withdrawals: query_result_state
+            .erc20_withdrawals
+            .iter()
+            .filter(|withdrawal| withdrawal.erc20_code == erc20_code)
+            .cloned()
+            .collect(),
+        net_deposited: query_result_state
+            .erc20_deposited
+            .get(&erc20_code)
+            .cloned()
+            .unwrap_or(0),
+    })
+}
+
+/// ERC-20 deposits from and withdrawals to an Ethereum address.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Erc20AddressHistory {
+    pub deposits: Vec<Erc20Transfer>,
+    pub withdrawals: Vec<Erc20Transfer>,
+    /// Amount of each token deposited from the address minus the amount withdrawn to it. This is
+    /// negative for a token the address received more of than it deposited.
+    pub net_deposited: Vec<(Erc20Code, i128)>,
+}
+
+pub async fn get_erc20_history_by_address(
+    bindings: &HashMap<String, RouteBinding>,
+    query_result_state: &QueryResultState,
+) -> Result<Erc20AddressHistory, tide::Error> {
+    let eth_addr = bindings[":eth_addr"].value.to::<EthereumAddr>()?;
+    let deposits: Vec<_> = query_result_state
+        .erc20_deposits
+        .iter()
+        .filter(|deposit| deposit.eth_addr == eth_addr)
+        .cloned()
+        .collect();
+    let withdrawals: Vec<_> = query_result_state
+        .erc20_withdrawals
+        .iter()
+        .filter(|withdrawal| withdrawal.eth_addr == eth_addr)
+        .cloned()
+        .collect();
+    let mut net_deposited = HashMap::<Erc20Code, i128>::new();
+    for deposit in deposits.iter() {
+        *net_deposited.entry(deposit.erc20_code.clone()).or_insert(0) += deposit.amount as i128;
+    }
+    for withdrawal in withdrawals.iter() {
+        *net_deposited
+            .entry(withdrawal.erc20_code.clone())
+            .or_insert(0) -= withdrawal.amount as i128;
+    }
+    Ok(Erc20AddressHistory {
+        deposits,
+        withdrawals,
+        net_deposited: net_deposited.into_iter().collect(),
+    })
+}
+
+pub async fn get_erc20_locked(
+    query_result_state: &QueryResultState,
+) -> Result<Vec<(Erc20Code, u128)>, tide::Error> {
+    Ok(query_result_state
+        .erc20_deposited
+        .iter()
+        .map(|(erc20_code, amount)| (erc20_code.clone(), *amount))
+        .collect())
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SponsoredAsset {
+    pub asset_def: AssetDefinition,
+    pub erc20_code: Erc20Code,
+    pub sponsor: EthereumAddr,
+}
+
+fn sponsored_assets<'a>(
+    query_result_state: &'a QueryResultState,
+) -> impl Iterator<Item = SponsoredAsset> + 'a {
+    query_result_state
+        .erc20_registrar
+        .iter()
+        .map(|(asset_def, (erc20_code, sponsor))| SponsoredAsset {
+            asset_def: asset_def.clone(),
+            erc20_code: erc20_code.clone(),
+            sponsor: sponsor.clone(),
+        })
+}
+
+pub async fn get_wrapped_erc20_code(
+    bindings: &HashMap<String, RouteBinding>,
+    query_result_state: &QueryResultState,
+) -> Result<Option<Erc20Code>, tide::Error> {
+    Ok(query_result_state
+        .erc20_registrar
+        .get(&bindings[":asset_def"].value.to::<AssetDefinition>()?)
+        .map(|(erc20_code, _sponsor)| erc20_code.clone()))
+}
+
+pub async fn get_sponsored_assets(
+    query_result_state: &QueryResultState,
+) -> Result<Vec<SponsoredAsset>, tide::Error> {
+    Ok(sponsored_assets(query_result_state).collect())
+}
+
+pub async fn get_assets_by_erc20_code(
+    bindings: &HashMap<String, RouteBinding>,
+    query_result_state: &QueryResultState,
+) -> Result<Vec<SponsoredAsset>, tide::Error> {
+    let erc20_code = bindings[":erc20_code"].value.to::<Erc20Code>()?;
+    Ok(sponsored_assets(query_result_state)
+        .filter(|asset| asset.erc20_code == erc20_code)
+        .collect())
+}
+
 pub async fn dispatch_url(
-    req: tide::Request<WebState>,
-    route_pattern: &str,
+    mut req: tide::Request<WebState>,
+    key: ApiRouteKey,
     bindings: &HashMap<String, RouteBinding>,
 ) -> Result<tide::Response, tide::Error> {
-    let segments = route_pattern.split_once('/').unwrap_or((route_pattern, ""));
-    let key = ApiRouteKey::from_str(segments.0).expect("Unknown route");
-    let query_state_guard = req.state().query_result_state.read().await;
+    let query_result_state = contract_state(&req)?;
+    if matches!(key, ApiRouteKey::get_viewed_transactions) {
+        check_viewing_token(&req)?;
+    }
+    // Read request bodies before locking the state, so a slow client cannot hold up event
+    // processing.
+    let nullifiers = match key {
+        ApiRouteKey::check_nullifiers => nullifiers_body(&mut req).await?,
+        _ => Vec::new(),
+    };
+    let query_state_guard = query_result_state.read().await;
     let query_state = &*query_state_guard;
+    if let Some(msg) = &query_state.integrity_failure {
+        // Serving a state which is known to be wrong would give wallets bad Merkle paths.
+        return Err(server_error(EQSNetError::IntegrityFailure {
+            msg: msg.clone(),
+        }));
+    }
     match key {
-        ApiRouteKey::get_cap_state => response(&req, get_cap_state(query_state).await?),
+        ApiRouteKey::get_cap_state => {
+            if bindings.contains_key(":block_id") {
+                response(&req, get_cap_state_at_block(bindings, query_state).await?)
+            } else {
+                response(&req, get_cap_state(query_state).await?)
+            }
+        }
         ApiRouteKey::get_all_nullifiers => response(&req, get_all_nullifiers(query_state).await?),
+        ApiRouteKey::get_nullifiers => response(&req, get_nullifiers(bindings, query_state).await?),
+        ApiRouteKey::get_nullifier_filter => {
+            response(&req, get_nullifier_filter(query_state).await?)
+        }
         ApiRouteKey::check_nullifier => {
             response(&req, check_nullifier(bindings, query_state).await?)
         }
+        ApiRouteKey::check_nullifiers => {
+            response(&req, check_nullifiers(&nullifiers, query_state).await?)
+        }
         ApiRouteKey::get_events_since => {
             response(&req, get_events_since(bindings, query_state).await?)
         }
+        ApiRouteKey::get_filtered_events => {
+            response(&req, get_filtered_events(bindings, query_state).await?)
+        }
         ApiRouteKey::get_transaction => {
             response(&req, get_transaction(bindings, query_state).await?)
         }
         ApiRouteKey::get_transaction_by_hash => {
             response(&req, get_transaction_by_hash(bindings, query_state).await?)
         }
+        ApiRouteKey::get_transaction_by_nullifier => response(
+            &req,
+            get_transaction_by_nullifier(bindings, query_state).await?,
+        ),
+        ApiRouteKey::get_transaction_by_output => response(
+            &req,
+            get_transaction_by_output(bindings, query_state).await?,
+        ),
+        ApiRouteKey::get_transaction_by_uid => {
+            response(&req, get_transaction_by_uid(bindings, query_state).await?)
+        }
+        ApiRouteKey::get_block => response(&req, get_block(bindings, query_state).await?),
+        ApiRouteKey::get_latest_blocks => {
+            response(&req, get_latest_blocks(bindings, query_state).await?)
+        }
+        ApiRouteKey::get_checkpoint => response(&req, get_checkpoint(query_state).await?),
+        ApiRouteKey::get_viewed_transactions => {
+            response(&req, get_viewed_transactions(bindings, query_state).await?)
+        }
+        ApiRouteKey::get_erc20_history => {
+            response(&req, get_erc20_history(bindings, query_state).await?)
+        }
+        ApiRouteKey::get_erc20_history_by_address => response(
+            &req,
+            get_erc20_history_by_address(bindings, query_state).await?,
+        ),
+        ApiRouteKey::get_erc20_locked => response(&req, get_erc20_locked(query_state).await?),
+        ApiRouteKey::get_wrapped_erc20_code => {
+            response(&req, get_wrapped_erc20_code(bindings, query_state).await?)
+        }
+        ApiRouteKey::get_sponsored_assets => {
+            response(&req, get_sponsored_assets(query_state).await?)
+        }
+        ApiRouteKey::get_assets_by_erc20_code => {
+            response(&req, get_assets_by_erc20_code(bindings, query_state).await?)
+        }
+        ApiRouteKey::sync_status => response(&req, sync_status(query_state).await?),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::configuration::EQSOptions;
+    use jf_cap::keys::{AuditorKeyPair, UserKeyPair};
+    use jf_cap::structs::{FreezeFlag, RecordOpening};
+    use rand_chacha::rand_core::SeedableRng;
+    use rand_chacha::ChaChaRng;
+    use structopt::StructOpt;
+    use tagged_base64::TaggedBase64;
+
+    fn filter(event_kind: EventKindFilter, txn_kind: TxnKindFilter) -> EventFilter {
+        EventFilter {
+            event_kind,
+            txn_kind,
+            from_block: 0,
+            to_block: u64::MAX,
+        }
+    }
+
+    fn memos(transaction: Option<(u64, u64, CapeTransactionKind)>) -> LedgerEvent<CapeLedger> {
+        LedgerEvent::Memos {
+            outputs: vec![],
+            transaction,
+        }
+    }
+
+    fn wrap_commit(block_id: u64) -> LedgerEvent<CapeLedger> {
+        let mut rng = ChaChaRng::from_seed([0; 32]);
+        let ro = RecordOpening::new(
+            &mut rng,
+            10,
+            AssetDefinition::native(),
+            UserKeyPair::generate(&mut rng).pub_key(),
+            FreezeFlag::Unfrozen,
+        );
+        LedgerEvent::Commit {
+            block: cap_rust_sandbox::ledger::CapeBlock::new(vec![CapeTransition::Wrap {
+                ro: Box::new(ro),
+                erc20_code: Erc20Code(EthereumAddr([1; 20])),
+                src_addr: EthereumAddr([2; 20]),
+            }]),
+            block_id,
+            state_comm: block_id + 1,
+        }
+    }
+
+    fn integer(parameter: &str, value: u64) -> (String, RouteBinding) {
+        (
+            parameter.to_string(),
+            RouteBinding {
+                parameter: parameter.to_string(),
+                ptype: UrlSegmentType::Integer,
+                value: UrlSegmentValue::Integer(value as u128),
+            },
+        )
+    }
+
+    fn identifier(parameter: &str, value: impl ToString) -> (String, RouteBinding) {
+        (
+            parameter.to_string(),
+            RouteBinding {
+                parameter: parameter.to_string(),
+                ptype: UrlSegmentType::TaggedBase64,
+                value: UrlSegmentValue::Identifier(
+                    TaggedBase64::parse(&value.to_string()).unwrap(),
+                ),
+            },
+        )
+    }
+
+    fn literal(parameter: &str, value: &str) -> (String, RouteBinding) {
+        (
+            parameter.to_string(),
+            RouteBinding {
+                parameter: parameter.to_string(),
+                ptype: UrlSegmentType::Literal,
+                value: UrlSegmentValue::Literal(value.to_string()),
+            },
+        )
+    }
+
+    #[test]
+    fn test_txn_kind_filter() {
+        let kinds = [
+            CapeTransactionKind::Wrap,
+            CapeTransactionKind::Burn,
+            CapeTransactionKind::mint(),
+            CapeTransactionKind::freeze(),
+            CapeTransactionKind::unfreeze(),
+            CapeTransactionKind::send(),
+        ];
+        for kind in kinds.iter() {
+            assert!(TxnKindFilter::All.matches(kind));
+        }
+        let expected = [
+            (
+                TxnKindFilter::Wrap,
+                vec![true, false, false, false, false, false],
+            ),
+            (
+                TxnKindFilter::Burn,
+                vec![false, true, false, false, false, false],
+            ),
+            (
+                TxnKindFilter::Mint,
+                vec![false, false, true, false, false, false],
+            ),
+            (
+                TxnKindFilter::Freeze,
+                vec![false, false, false, true, true, false],
+            ),
+            (
+                TxnKindFilter::Transfer,
+                vec![false, false, false, false, false, true],
+            ),
+        ];
+        for (filter, matches) in expected {
+            for (kind, expected) in kinds.iter().zip(matches) {
+                assert_eq!(filter.matches(kind), expected, "{:?} {:?}", filter, kind);
+            }
+        }
+        assert_eq!(TxnKindFilter::from_str("freeze"), Ok(TxnKindFilter::Freeze));
+        assert!(TxnKindFilter::from_str("unfreeze").is_err());
+    }
+
+    #[test]
+    fn test_event_filter() {
+        let commit = wrap_commit(5);
+        let wrap_memos = memos(Some((5, 0, CapeTransactionKind::Wrap)));
+        let faucet_memos = memos(None);
+
+        let all = filter(EventKindFilter::All, TxnKindFilter::All);
+        assert!(all.matches(&commit));
+        assert!(all.matches(&wrap_memos));
+        assert!(all.matches(&faucet_memos));
+
+        // Event kinds.
+        let commits = filter(EventKindFilter::Commit, TxnKindFilter::All);
+        assert!(commits.matches(&commit));
+        assert!(!commits.matches(&wrap_memos));
+        let only_memos = filter(EventKindFilter::Memos, TxnKindFilter::All);
+        assert!(!only_memos.matches(&commit));
+        assert!(only_memos.matches(&wrap_memos));
+
+        // A commit matches if any of its transactions does.
+        let wraps = filter(EventKindFilter::All, TxnKindFilter::Wrap);
+        assert!(wraps.matches(&commit));
+        assert!(wraps.matches(&wrap_memos));
+        let burns = filter(EventKindFilter::All, TxnKindFilter::Burn);
+        assert!(!burns.matches(&commit));
+        assert!(!burns.matches(&wrap_memos));
+
+        // Faucet memos have no transaction, so they only match an unrestricted filter.
+        assert!(!wraps.matches(&faucet_memos));
+
+        // Block ranges are inclusive.
+        for (from_block, to_block, expected) in [(5, 5, true), (0, 4, false), (6, 10, false)] {
+            let range = EventFilter {
+                from_block,
+                to_block,
+                ..all.clone()
+            };
+            assert_eq!(range.matches(&commit), expected);
+            assert_eq!(range.matches(&wrap_memos), expected);
+            assert!(!range.matches(&faucet_memos));
+        }
+    }
+
+    #[async_std::test]
+    async fn test_filtered_events_scan_limit() {
+        let mut state = QueryResultState::new(EQSOptions::from_iter(["eqs"]).verifier_keys());
+        for _ in 0..MAX_EVENTS_SCANNED + 5 {
+            state.events.push(memos(None));
+        }
+        state
+            .events
+            .push(memos(Some((1, 0, CapeTransactionKind::Wrap))));
+        let num_events = state.num_events();
+
+        let bindings = |first| {
+            [
+                integer(":first", first),
+                integer(":max_count", 10),
+                literal(":event_kind", "all"),
+                literal(":txn_kind", "wrap"),
+            ]
+            .into_iter()
+            .collect::<HashMap<_, _>>()
+        };
+
+        // No event matches in the first MAX_EVENTS_SCANNED, so the first page is empty.
+        let page = get_filtered_events(&bindings(0), &state).await.unwrap();
+        assert!(page.events.is_empty());
+        assert_eq!(page.next, MAX_EVENTS_SCANNED as u64);
+
+        // Scanning resumes where the first page left off.
+        let page = get_filtered_events(&bindings(page.next), &state)
+            .await
+            .unwrap();
+        assert_eq!(page.events.len(), 1);
+        assert_eq!(page.events[0].0, num_events - 1);
+        assert_eq!(page.next, num_events);
+    }
+
+    #[async_std::test]
+    async fn test_viewed_transactions_pages() {
+        let mut state = QueryResultState::new(EQSOptions::from_iter(["eqs"]).verifier_keys());
+        let key = AuditorKeyPair::generate(&mut ChaChaRng::from_seed([0; 32])).pub_key();
+        let viewed = |txn_id| ViewedTransaction {
+            block_id: 0,
+            txn_id,
+            kind: CapeTransactionKind::Wrap,
+            inputs: vec![],
+            outputs: vec![],
+        };
+        let num_viewed = MAX_VIEWED_TRANSACTIONS as u64 + 5;
+        state
+            .audit
+            .ledgers
+            .insert(key.clone(), (0..num_viewed).map(viewed).collect::<Vec<_>>());
+
+        let bindings = |first, max_count| {
+            [
+                identifier(":viewing_key", &key),
+                integer(":first", first),
+                integer(":max_count", max_count),
+            ]
+            .into_iter()
+            .collect::<HashMap<_, _>>()
+        };
+
+        let page = get_viewed_transactions(&bindings(2, 3), &state)
+            .await
+            .unwrap();
+        let txn_ids: Vec<_> = page.transactions.iter().map(|txn| txn.txn_id).collect();
+        assert_eq!(txn_ids, vec![2, 3, 4]);
+        assert_eq!(page.next, 5);
+
+        // Pages are capped, however many transactions are asked for.
+        let page = get_viewed_transactions(&bindings(0, num_viewed), &state)
+            .await
+            .unwrap();
+        assert_eq!(page.transactions.len(), MAX_VIEWED_TRANSACTIONS);
+        let page = get_viewed_transactions(&bindings(page.next, num_viewed), &state)
+            .await
+            .unwrap();
+        assert_eq!(page.transactions.len(), 5);
+        assert_eq!(page.next, num_viewed);
+
+        // Past the end of the ledger, pages are empty.
+        let page = get_viewed_transactions(&bindings(num_viewed + 1, 10), &state)
+            .await
+            .unwrap();
+        assert!(page.transactions.is_empty());
+    }
+
+    #[async_std::test]
+    async fn test_nullifier_pages() {
+        let mut rng = ChaChaRng::from_seed([0; 32]);
+        let mut state = QueryResultState::new(EQSOptions::from_iter(["eqs"]).verifier_keys());
+        let total = MAX_NULLIFIER_PAGE + 5;
+        for _ in 0..total {
+            state.insert_nullifier(Nullifier::random_for_test(&mut rng));
+        }
+        let bindings = |first, max_count| {
+            [integer(":first", first), integer(":max_count", max_count)]
+                .into_iter()
+                .collect::<HashMap<_, _>>()
+        };
+
+        let page = get_nullifiers(&bindings(2, 3), &state).await.unwrap();
+        assert_eq!(page.nullifiers, state.nullifier_list[2..5]);
+        assert_eq!(page.next, 5);
+        assert_eq!(page.total, total as u64);
+
+        // Pages are capped, however many nullifiers are asked for.
+        let page = get_nullifiers(&bindings(0, u64::MAX), &state)
+            .await
+            .unwrap();
+        assert_eq!(page.nullifiers.len(), MAX_NULLIFIER_PAGE);
+        let page = get_nullifiers(&bindings(page.next, u64::MAX), &state)
+            .await
+            .unwrap();
+        assert_eq!(page.nullifiers.len(), 5);
+        assert_eq!(page.next, total as u64);
+    }
+
+    #[async_std::test]
+    async fn test_erc20_history_by_address() {
+        let mut state = QueryResultState::new(EQSOptions::from_iter(["eqs"]).verifier_keys());
+        let eth_addr = EthereumAddr([2; 20]);
+        let other_addr = EthereumAddr([3; 20]);
+        let token = |byte| Erc20Code(EthereumAddr([byte; 20]));
+        let transfer = |erc20_code, eth_addr: &EthereumAddr, amount| Erc20Transfer {
+            erc20_code,
+            eth_addr: eth_addr.clone(),
+            amount,
+            block_id: 1,
+            txn_id: 0,
+        };
+        state.erc20_deposits = vec![
+            transfer(token(1), &eth_addr, 100),
+            transfer(token(1), &other_addr, 500),
+            transfer(token(4), &eth_addr, 10),
+        ];
+        state.erc20_withdrawals = vec![
+            transfer(token(1), &eth_addr, 30),
+            transfer(token(4), &eth_addr, 50),
+        ];
+
+        let bindings = [identifier(":eth_addr", &eth_addr)]
+            .into_iter()
+            .collect::<HashMap<_, _>>();
+        let history = get_erc20_history_by_address(&bindings, &state)
+            .await
+            .unwrap();
+        assert_eq!(history.deposits.len(), 2);
+        assert_eq!(history.withdrawals.len(), 2);
+        // Tokens are netted separately, and an address may withdraw more than it deposited.
+        let net_deposited: HashMap<_, _> = history.net_deposited.into_iter().collect();
+        assert_eq!(net_deposited.len(), 2);
+        assert_eq!(net_deposited[&token(1)], 70);
+        assert_eq!(net_deposited[&token(4)], -40);
+    }
+
+    #[test]
+    fn test_tokens_match() {
+        assert!(tokens_match(b"secret", b"secret"));
+        assert!(!tokens_match(b"secret", b"secreT"));
+        assert!(!tokens_match(b"secret", b"secret2"));
+        assert!(!tokens_match(b"", b"secret"));
     }
 }
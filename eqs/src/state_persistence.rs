@@ -5,18 +5,22 @@
 // This program is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
 // You should have received a copy of the GNU General Public License along with this program. If not, see <https://www.gnu.org/licenses/>.
 
+use crate::metrics::PersistenceStats;
 use crate::query_result_state::QueryResultState;
 use atomic_store::{
     load_store::BincodeLoadStore, AppendLog, AtomicStore, AtomicStoreLoader, PersistenceError,
 };
 
+use async_std::sync::Arc;
 use std::path::{Path, PathBuf};
+use std::time::Instant;
 
 // hook up with atomic_store
 
 pub struct StatePersistence {
     atomic_store: AtomicStore,
     state_snapshot: AppendLog<BincodeLoadStore<QueryResultState>>,
+    stats: Arc<PersistenceStats>,
 }
 
 impl StatePersistence {
@@ -31,6 +35,7 @@ impl StatePersistence {
         Ok(StatePersistence {
             atomic_store,
             state_snapshot,
+            stats: Default::default(),
         })
     }
 
@@ -44,16 +49,31 @@ impl StatePersistence {
         Ok(StatePersistence {
             atomic_store,
             state_snapshot,
+            stats: Default::default(),
         })
     }
 
     pub fn store_latest_state(&mut self, state: &QueryResultState) {
+        let start = Instant::now();
         self.state_snapshot.store_resource(state).unwrap();
         self.state_snapshot.commit_version().unwrap();
         self.atomic_store.commit_version().unwrap();
+        self.stats.record(start.elapsed());
+    }
+
+    /// How long storing snapshots has taken so far.
+    pub fn stats(&self) -> Arc<PersistenceStats> {
+        self.stats.clone()
     }
 
     pub fn load_latest_state(&self) -> Result<QueryResultState, PersistenceError> {
         self.state_snapshot.load_latest()
     }
+
+    /// Every stored snapshot of the state, oldest first. Snapshots which fail to load are skipped.
+    pub fn snapshots(&self) -> impl Iterator<Item = QueryResultState> + '_ {
+        self.state_snapshot
+            .iter()
+            .filter_map(|snapshot| snapshot.ok())
+    }
 }
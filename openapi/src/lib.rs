@@ -0,0 +1,357 @@
+// Copyright (c) 2022 Espresso Systems (espressosys.com)
+// This file is part of the Configurable Asset Privacy for Ethereum (CAPE) library.
+
+// This program is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+// You should have received a copy of the GNU General Public License along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! OpenAPI 3 documents for the web APIs described by an `api.toml` file.
+//!
+//! Paths, parameters and descriptions come from `api.toml`. Each route names its response type
+//! with a `RESPONSE` key, and the type of its request body, if any, with a `REQUEST` key; both must
+//! be schemas in the map given to [openapi_document]. Routes which are not implemented yet are
+//! marked with `UNIMPLEMENTED = true` instead of a `RESPONSE`, and are left out of the document.
+//!
+//! Each service builds its schemas with the helpers in this crate, describing the JSON
+//! serialization of the types its endpoints take and return.
+
+use serde_json::{json, Map, Value};
+
+/// Whether a route of `api.toml` is marked as not implemented yet.
+pub fn is_unimplemented(entry: &toml::Value) -> bool {
+    entry
+        .get("UNIMPLEMENTED")
+        .and_then(|unimplemented| unimplemented.as_bool())
+        .unwrap_or(false)
+}
+
+/// The names of the schemas used by a route: its `RESPONSE` and its `REQUEST`, if any.
+pub fn route_schemas(entry: &toml::Value) -> Vec<&str> {
+    ["RESPONSE", "REQUEST"]
+        .iter()
+        .filter_map(|key| entry.get(*key).and_then(|name| name.as_str()))
+        .collect()
+}
+
+/// Check that every implemented route of `api` has a `RESPONSE`, and that every `RESPONSE` and
+/// `REQUEST` refers to one of `schemas`.
+///
+/// Returns a description of each problem found.
+pub fn check_schemas(api: &toml::Value, schemas: &Map<String, Value>) -> Result<(), Vec<String>> {
+    let mut errors = Vec::new();
+    if let Some(api_map) = api["route"].as_table() {
+        for (key, entry) in api_map {
+            if is_unimplemented(entry) {
+                continue;
+            }
+            if !entry
+                .get("RESPONSE")
+                .map(|response| response.is_str())
+                .unwrap_or(false)
+            {
+                errors.push(format!("[route.{}] has no RESPONSE", key));
+            }
+            for name in route_schemas(entry) {
+                if !schemas.contains_key(name) {
+                    errors.push(format!(
+                        "[route.{}] refers to {}, which has no schema",
+                        key, name
+                    ));
+                }
+            }
+        }
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+/// Builds the OpenAPI document for the routes in `api`, with the request and response types in
+/// `schemas`.
+///
+/// `schemas` must include an `Error` schema, which describes the body of error responses.
+pub fn openapi_document(api: &toml::Value, schemas: Map<String, Value>) -> Value {
+    if let Err(errors) = check_schemas(api, &schemas) {
+        panic!(
+            "api.toml is inconsistent with its schemas: {}",
+            errors.join("; ")
+        );
+    }
+    let mut paths = Map::new();
+    if let Some(api_map) = api["route"].as_table() {
+        for (key, entry) in api_map {
+            if is_unimplemented(entry) {
+                continue;
+            }
+            let method = entry
+                .get("METHOD")
+                .and_then(|method| method.as_str())
+                .unwrap_or("GET")
+                .to_lowercase();
+            for path in entry["PATH"]
+                .as_array()
+                .expect("PATH must be an array in api.toml")
+            {
+                let path = path
+                    .as_str()
+                    .expect("PATH must be an array of strings in api.toml");
+                let item = paths.entry(openapi_path(path)).or_insert_with(|| json!({}));
+                item[&method] = operation(key, path, entry);
+            }
+        }
+    }
+    let mut info = json!({
+        "title": api["meta"]["TITLE"].as_str().expect("TITLE must be a string in api.toml"),
+        "version": api["meta"]["FORMAT_VERSION"]
+            .as_str()
+            .expect("FORMAT_VERSION must be a string in api.toml"),
+    });
+    if let Some(description) = api["meta"].get("DESCRIPTION") {
+        info["description"] = json!(description
+            .as_str()
+            .expect("DESCRIPTION must be a string in api.toml")
+            .trim());
+    }
+    json!({
+        "openapi": "3.0.3",
+        "info": info,
+        "paths": paths,
+        "components": { "schemas": schemas },
+    })
+}
+
+/// Converts a tide path pattern (`a/:b`) to an OpenAPI path template (`/a/{b}`).
+pub fn openapi_path(path: &str) -> String {
+    path.split('/')
+        .map(|segment| match segment.strip_prefix(':') {
+            Some(param) => format!("/{{{}}}", param),
+            None => format!("/{}", segment),
+        })
+        .collect()
+}
+
+fn operation(key: &str, path: &str, entry: &toml::Value) -> Value {
+    let response = entry["RESPONSE"].as_str().unwrap();
+    let nullable = entry
+        .get("NULLABLE")
+        .and_then(|nullable| nullable.as_bool())
+        .unwrap_or(false);
+    let request = entry.get("REQUEST").map(|request| {
+        request
+            .as_str()
+            .expect("REQUEST must be a string in api.toml")
+    });
+
+    let mut parameters = Vec::new();
+    let mut bad_request = String::from("Invalid route parameter or request body.");
+    for segment in path.split('/') {
+        if let Some(param) = segment.strip_prefix(':') {
+            let ptype = entry
+                .get(segment)
+                .and_then(|ptype| ptype.as_str())
+                .unwrap_or_else(|| {
+                    panic!("Parameter {} of {} has no type in api.toml", segment, path)
+                });
+            parameters.push(json!({
+                "name": param,
+                "in": "path",
+                "required": true,
+                "schema": param_schema(ptype),
+            }));
+            if let Some(error) = entry
+                .get(&format!("ERROR_{}", param))
+                .and_then(|error| error.as_str())
+            {
+                bad_request += " ";
+                bad_request += error;
+            }
+        }
+    }
+    let mut body = schema_ref(response);
+    if nullable {
+        body = json!({ "allOf": [body], "nullable": true });
+    }
+    let mut operation = json!({
+        "operationId": path.replace(':', "").replace('/', "_"),
+        "tags": [key],
+        "description": entry["DOC"].as_str().expect("DOC must be a string in api.toml").trim(),
+        "parameters": parameters,
+        "responses": {
+            "200": {
+                "description": "Success.",
+                // Responses are negotiated with the Accept header: every route also responds with
+                // bincode, which has no schema of its own.
+                "content": {
+                    "application/json": { "schema": body },
+                    "application/octet-stream": { "schema": { "type": "string", "format": "binary" } },
+                },
+            },
+            "400": {
+                "description": bad_request,
+                "content": { "application/json": { "schema": schema_ref("Error") } },
+            },
+            "500": {
+                "description": "Internal server error.",
+                "content": { "application/json": { "schema": schema_ref("Error") } },
+            },
+        },
+    });
+    if let Some(request) = request {
+        operation["requestBody"] = json!({
+            "required": true,
+            "content": { "application/json": { "schema": schema_ref(request) } },
+        });
+    }
+    operation
+}
+
+/// Schema of a URL segment of the given `UrlSegmentType`.
+fn param_schema(ptype: &str) -> Value {
+    match ptype {
+        "Boolean" => json!({ "type": "boolean" }),
+        "Hexadecimal" => json!({ "type": "string", "pattern": "^[0-9a-fA-F]+$" }),
+        "Integer" => uint(),
+        "TaggedBase64" => json!({ "type": "string", "pattern": "^[A-Z_]+~[A-Za-z0-9_-]*$" }),
+        "Base64" => json!({ "type": "string", "pattern": "^[A-Za-z0-9_-]*$" }),
+        "Literal" => json!({ "type": "string" }),
+        _ => panic!("Unknown parameter type {} in api.toml", ptype),
+    }
+}
+
+/// A reference to the schema with the given name.
+pub fn schema_ref(name: &str) -> Value {
+    json!({ "$ref": format!("#/components/schemas/{}", name) })
+}
+
+pub fn uint() -> Value {
+    json!({ "type": "integer", "minimum": 0 })
+}
+
+pub fn array(items: Value) -> Value {
+    json!({ "type": "array", "items": items })
+}
+
+/// A fixed-length JSON array, as serde serializes Rust tuples.
+pub fn tuple(items: Vec<Value>) -> Value {
+    let len = items.len();
+    json!({ "type": "array", "items": { "anyOf": items }, "minItems": len, "maxItems": len })
+}
+
+/// A TaggedBase64 string with the given tag.
+pub fn tagged(tag: &str) -> Value {
+    json!({ "type": "string", "pattern": format!("^{}~[A-Za-z0-9_-]*$", tag) })
+}
+
+/// A value serialized by a library type whose structure is not part of the API.
+pub fn opaque(description: &str) -> Value {
+    json!({ "description": description })
+}
+
+/// One variant of an externally tagged enum.
+pub fn variant(name: &str, contents: Value) -> Value {
+    json!({
+        "type": "object",
+        "properties": { (name): contents },
+        "required": [name],
+        "additionalProperties": false,
+    })
+}
+
+/// An object with the given properties, all of which are required.
+pub fn object(properties: Vec<(&str, Value)>) -> Value {
+    let required: Vec<&str> = properties.iter().map(|(name, _)| *name).collect();
+    let properties: Map<String, Value> = properties
+        .into_iter()
+        .map(|(name, schema)| (name.to_string(), schema))
+        .collect();
+    json!({ "type": "object", "properties": properties, "required": required })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const API: &str = r#"
+[meta]
+FORMAT_VERSION = "0.1.0"
+TITLE = "Test API"
+
+[route.get_thing]
+PATH = [ "get_thing/:id" ]
+":id" = "Integer"
+RESPONSE = "Thing"
+NULLABLE = true
+DOC = "Returns a thing."
+ERROR_id = "The id must be an integer."
+
+[route.put_things]
+PATH = [ "put_things" ]
+METHOD = "POST"
+REQUEST = "Things"
+RESPONSE = "Boolean"
+DOC = "Stores things."
+
+[route.later]
+PATH = [ "later" ]
+UNIMPLEMENTED = true
+DOC = "Not yet."
+"#;
+
+    fn schemas() -> Map<String, Value> {
+        let mut schemas = Map::new();
+        schemas.insert("Thing".into(), object(vec![("id", uint())]));
+        schemas.insert("Things".into(), array(schema_ref("Thing")));
+        schemas.insert("Boolean".into(), json!({ "type": "boolean" }));
+        schemas.insert("Error".into(), opaque("An error"));
+        schemas
+    }
+
+    #[test]
+    fn test_openapi_document() {
+        let api: toml::Value = toml::from_str(API).unwrap();
+        let document = openapi_document(&api, schemas());
+        let paths = document["paths"].as_object().unwrap();
+        assert_eq!(paths.len(), 2);
+        assert!(!paths.contains_key("/later"));
+
+        let get = &paths["/get_thing/{id}"]["get"];
+        assert_eq!(get["parameters"][0]["name"], "id");
+        assert_eq!(
+            get["responses"]["200"]["content"]["application/json"]["schema"]["allOf"][0]["$ref"],
+            "#/components/schemas/Thing"
+        );
+        assert!(get["responses"]["400"]["description"]
+            .as_str()
+            .unwrap()
+            .ends_with("The id must be an integer."));
+
+        let post = &paths["/put_things"]["post"];
+        assert_eq!(
+            post["requestBody"]["content"]["application/json"]["schema"]["$ref"],
+            "#/components/schemas/Things"
+        );
+    }
+
+    #[test]
+    fn test_check_schemas() {
+        let api: toml::Value = toml::from_str(API).unwrap();
+        assert_eq!(check_schemas(&api, &schemas()), Ok(()));
+
+        let mut missing = schemas();
+        missing.remove("Things");
+        assert_eq!(
+            check_schemas(&api, &missing),
+            Err(vec![String::from(
+                "[route.put_things] refers to Things, which has no schema"
+            )])
+        );
+
+        let api: toml::Value = toml::from_str(&API.replace("UNIMPLEMENTED = true", "")).unwrap();
+        assert_eq!(
+            check_schemas(&api, &schemas()),
+            Err(vec![String::from("[route.later] has no RESPONSE")])
+        );
+    }
+}
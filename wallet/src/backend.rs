@@ -9,7 +9,7 @@
 #![deny(warnings)]
 
 use crate::{mocks::MockCapeLedger, CapeWalletBackend, CapeWalletError};
-use address_book::{address_book_port, InsertPubKey};
+use address_book::{timestamp_nonce, InsertPubKey, MAX_BATCH_SIZE};
 use async_std::sync::{Arc, Mutex, MutexGuard};
 use async_trait::async_trait;
 use cap_rust_sandbox::{
@@ -61,6 +61,7 @@ fn get_provider() -> Provider<Http> {
 pub struct CapeBackend<'a, Meta: Serialize + DeserializeOwned> {
     universal_param: &'a UniversalParam,
     relayer: surf::Client,
+    address_book: surf::Client,
     contract: CAPE<EthMiddleware>,
     storage: Arc<Mutex<AtomicWalletStorage<'a, CapeLedger, Meta>>>,
     key_stream: hd::KeyTree,
@@ -72,6 +73,7 @@ impl<'a, Meta: Serialize + DeserializeOwned + Send> CapeBackend<'a, Meta> {
     pub async fn new(
         universal_param: &'a UniversalParam,
         relayer_url: Url,
+        address_book_url: Url,
         contract_address: Address,
         eth_mnemonic: Option<String>,
         mock_eqs: Arc<Mutex<MockCapeLedger<'a>>>,
@@ -82,6 +84,10 @@ impl<'a, Meta: Serialize + DeserializeOwned + Send> CapeBackend<'a, Meta> {
             .try_into()
             .unwrap();
         let relayer = relayer.with(parse_error_body::<relayer::Error>);
+        let address_book: surf::Client = surf::Config::default()
+            .set_base_url(address_book_url)
+            .try_into()
+            .unwrap();
 
         // Create an Ethereum wallet to talk to the CAPE contract.
         let provider = get_provider();
@@ -106,6 +112,7 @@ impl<'a, Meta: Serialize + DeserializeOwned + Send> CapeBackend<'a, Meta> {
         Ok(Self {
             universal_param,
             relayer,
+            address_book,
             contract,
             storage: Arc::new(Mutex::new(storage)),
             key_stream,
@@ -197,20 +204,13 @@ impl<'a, Meta: Serialize + DeserializeOwned + Send> WalletBackend<'a, CapeLedger
     }
 
     async fn get_public_key(&self, address: &UserAddress) -> Result<UserPubKey, CapeWalletError> {
-        let address_bytes = bincode::serialize(address).unwrap();
-        let mut response = surf::post(format!(
-            "http://localhost:{}/request_pubkey",
-            address_book_port()
-        ))
-        .content_type(surf::http::mime::BYTE_STREAM)
-        .body_bytes(&address_bytes)
-        .await
-        .map_err(|err| CapeWalletError::Failed {
-            msg: format!("error requesting public key: {}", err),
-        })?;
-        let bytes = response.body_bytes().await.unwrap();
-        let pub_key: UserPubKey = bincode::deserialize(&bytes).unwrap();
-        Ok(pub_key)
+        self.get_public_keys(std::slice::from_ref(address))
+            .await?
+            .pop()
+            .flatten()
+            .ok_or_else(|| CapeWalletError::Failed {
+                msg: format!("no public key is published for {}", address),
+            })
     }
 
     async fn get_nullifier_proof(
@@ -236,17 +236,14 @@ impl<'a, Meta: Serialize + DeserializeOwned + Send> WalletBackend<'a, CapeLedger
     }
 
     async fn register_user_key(&mut self, key_pair: &UserKeyPair) -> Result<(), CapeWalletError> {
-        let pub_key_bytes = bincode::serialize(&key_pair.pub_key()).unwrap();
-        let sig = key_pair.sign(&pub_key_bytes);
-        let json_request = InsertPubKey { pub_key_bytes, sig };
-        match surf::post(format!(
-            "http://localhost:{}/insert_pubkey",
-            address_book_port()
-        ))
-        .content_type(surf::http::mime::JSON)
-        .body_json(&json_request)
-        .unwrap()
-        .await
+        let json_request = InsertPubKey::new(key_pair, timestamp_nonce());
+        match self
+            .address_book
+            .post("insert_pubkey")
+            .content_type(surf::http::mime::JSON)
+            .body_json(&json_request)
+            .unwrap()
+            .await
         {
             Ok(_) => Ok(()),
             Err(err) => Err(CapeWalletError::Failed {
@@ -360,6 +357,67 @@ impl<'a, Meta: Serialize + DeserializeOwned + Send> CapeWalletBackend<'a>
             self.eth_wallet.clone(),
         )))
     }
+
+    async fn resolve_alias(&self, alias: &str) -> Result<UserAddress, CapeWalletError> {
+        let alias_bytes = bincode::serialize(alias).unwrap();
+        let mut response = self
+            .address_book
+            .post("resolve_alias")
+            .content_type(surf::http::mime::BYTE_STREAM)
+            .body_bytes(&alias_bytes)
+            .await
+            .map_err(|err| CapeWalletError::Failed {
+                msg: format!("error resolving alias: {}", err),
+            })?;
+        if response.status() == surf::StatusCode::NotFound {
+            return Err(CapeWalletError::Failed {
+                msg: format!("no address has the alias {}", alias),
+            });
+        }
+        let bytes = response.body_bytes().await.unwrap();
+        bincode::deserialize(&bytes).map_err(|err| CapeWalletError::Failed {
+            msg: format!("error parsing address: {}", err),
+        })
+    }
+
+    async fn get_public_keys(
+        &self,
+        addresses: &[UserAddress],
+    ) -> Result<Vec<Option<UserPubKey>>, CapeWalletError> {
+        let mut pub_keys = Vec::with_capacity(addresses.len());
+        for batch in addresses.chunks(MAX_BATCH_SIZE) {
+            let address_bytes = bincode::serialize(batch).unwrap();
+            let mut response = self
+                .address_book
+                .post("request_pubkeys")
+                .content_type(surf::http::mime::BYTE_STREAM)
+                .body_bytes(&address_bytes)
+                .await
+                .map_err(|err| CapeWalletError::Failed {
+                    msg: format!("error requesting public keys: {}", err),
+                })?;
+            if !response.status().is_success() {
+                return Err(CapeWalletError::Failed {
+                    msg: format!(
+                        "error requesting public keys: the address book responded {}",
+                        response.status()
+                    ),
+                });
+            }
+            let bytes = response
+                .body_bytes()
+                .await
+                .map_err(|err| CapeWalletError::Failed {
+                    msg: format!("error reading public keys: {}", err),
+                })?;
+            let batch_keys: Vec<Option<UserPubKey>> =
+                bincode::deserialize(&bytes).map_err(|err| CapeWalletError::Failed {
+                    msg: format!("error parsing public keys: {}", err),
+                })?;
+            pub_keys.extend(batch_keys);
+        }
+        Ok(pub_keys)
+    }
 }
 
 #[cfg(test)]
@@ -382,7 +440,7 @@ mod test {
     async fn test_transfer() {
         let mut rng = ChaChaRng::from_seed([1u8; 32]);
         let universal_param = universal_setup_for_test(2usize.pow(16), &mut rng).unwrap();
-        let (sender_key, relayer_url, contract_address, mock_eqs) =
+        let (sender_key, relayer_url, address_book_url, contract_address, mock_eqs) =
             create_test_network(&mut rng, &universal_param).await;
 
         // Create a sender wallet and add the key pair that owns the faucet record.
@@ -394,6 +452,7 @@ mod test {
         let sender_backend = CapeBackend::new(
             &universal_param,
             relayer_url.clone(),
+            address_book_url.clone(),
             contract_address,
             None,
             mock_eqs.clone(),
@@ -426,6 +485,7 @@ mod test {
         let receiver_backend = CapeBackend::new(
             &universal_param,
             relayer_url.clone(),
+            address_book_url.clone(),
             contract_address,
             None,
             mock_eqs.clone(),
@@ -484,13 +544,24 @@ mod test {
                 .await,
             0
         );
+
+        // Both keys are published, and can be looked up together.
+        let unknown = UserKeyPair::generate(&mut rng).address();
+        let pub_keys = sender
+            .get_public_keys(&[sender_key.address(), receiver_key.address(), unknown])
+            .await
+            .unwrap();
+        assert_eq!(
+            pub_keys,
+            vec![Some(sender_key.pub_key()), Some(receiver_key), None]
+        );
     }
 
     #[async_std::test]
     async fn test_anonymous_erc20_transfer() {
         let mut rng = ChaChaRng::from_seed([1u8; 32]);
         let universal_param = universal_setup_for_test(2usize.pow(16), &mut rng).unwrap();
-        let (wrapper_key, relayer_url, contract_address, mock_eqs) =
+        let (wrapper_key, relayer_url, address_book_url, contract_address, mock_eqs) =
             create_test_network(&mut rng, &universal_param).await;
 
         // Create a wallet to sponsor an asset and a different wallet to deposit (we should be able
@@ -503,6 +574,7 @@ mod test {
         let sponsor_backend = CapeBackend::new(
             &universal_param,
             relayer_url.clone(),
+            address_book_url.clone(),
             contract_address.clone(),
             None,
             mock_eqs.clone(),
@@ -522,6 +594,7 @@ mod test {
         let wrapper_backend = CapeBackend::new(
             &universal_param,
             relayer_url.clone(),
+            address_book_url.clone(),
             contract_address.clone(),
             None,
             mock_eqs.clone(),
@@ -44,7 +44,7 @@ use jf_cap::{
     MerkleTree, TransactionVerifyingKey,
 };
 use key_set::{KeySet, VerifierKeySet};
-use net::UserAddress;
+use net::{TaggedBlob, UserAddress};
 use reef::Ledger;
 use seahorse::{
     cli::*,
@@ -60,6 +60,7 @@ use std::process::exit;
 use std::str::FromStr;
 use std::sync::Arc;
 use structopt::StructOpt;
+use tagged_base64::TaggedBase64;
 
 /// Implementation of the [seahorse] [CLI] interface for CAPE.
 pub struct CapeCli;
@@ -135,6 +136,29 @@ impl<'a> CLIInput<'a, CapeCli> for Erc20Code {
     }
 }
 
+/// The recipient of a transfer: an address, or an alias registered in the address book.
+///
+/// Aliases cannot contain `~`, so anything which parses as tagged base 64 is an address.
+pub enum Recipient {
+    Address(UserAddress),
+    Alias(String),
+}
+
+impl Recipient {
+    fn parse(s: &str) -> Option<Self> {
+        match TaggedBase64::parse(s) {
+            Ok(tb64) => UserAddress::from_tagged_blob(&tb64).ok().map(Self::Address),
+            Err(_) => Some(Self::Alias(String::from(s))),
+        }
+    }
+}
+
+impl<'a> CLIInput<'a, CapeCli> for Recipient {
+    fn parse_for_wallet(_wallet: &mut Wallet<'a, CapeCli>, s: &str) -> Option<Self> {
+        Self::parse(s)
+    }
+}
+
 /// The instantiation of [seahorse::Wallet] for CAPE used by the CLI.
 type CapeWallet<'a> = seahorse::Wallet<'a, MockCapeBackend<'a, LoaderMetadata>, CapeLedger>;
 
@@ -236,6 +260,38 @@ async fn cli_burn<'a>(
     finish_transaction::<CapeCli>(io, wallet, res, wait, "burned").await;
 }
 
+/// Implementation of the `send` command for the CAPE wallet CLI.
+///
+/// This is the generic `transfer` command, except that the recipient may be an alias.
+#[allow(clippy::too_many_arguments)]
+async fn cli_send<'a>(
+    io: &mut SharedIO,
+    wallet: &mut CapeWallet<'_>,
+    asset: ListItem<AssetCode>,
+    from: UserAddress,
+    to: Recipient,
+    amount: u64,
+    fee: u64,
+    wait: Option<bool>,
+) {
+    let to = match to {
+        Recipient::Address(address) => address.0,
+        Recipient::Alias(alias) => match wallet.resolve_alias(&alias).await {
+            Ok(address) => address,
+            Err(err) => {
+                cli_writeln!(io, "{}\nAssets were not transferred.", err);
+                return;
+            }
+        },
+    };
+    let res = wallet
+        .transfer(Some(&from.0), &asset.item, &[(to, amount)], fee)
+        .await;
+    cli_writeln!(io, "{}", asset.item);
+
+    finish_transaction::<CapeCli>(io, wallet, res, wait, "transferred").await;
+}
+
 /// The collection of CLI commands which are specific to CAPE.
 ///
 /// These commands are not part of the generic [seahorse::cli], but they are added to the CAPE CLI
@@ -287,6 +343,21 @@ fn cape_specific_cli_commands<'a>() -> Vec<Command<'a, CapeCli>> {
                 cli_burn(io, wallet, asset, from, to, amount, fee, wait).await;
             }
         ),
+        command!(
+            send,
+            "transfer some owned assets to an address or to an alias from the address book",
+            CapeCli,
+            |io,
+             wallet,
+             asset: ListItem<AssetCode>,
+             from: UserAddress,
+             to: Recipient,
+             amount: u64,
+             fee: u64;
+             wait: Option<bool>| {
+                cli_send(io, wallet, asset, from, to, amount, fee, wait).await;
+            }
+        ),
     ]
 }
 
@@ -445,6 +516,21 @@ mod tests {
                         cli_burn(io, wallet, asset, from, to, amount, fee, wait).await;
                     }
                 ),
+                command!(
+                    send,
+                    "transfer some owned assets to an address or to an alias from the address book",
+                    Self,
+                    |io,
+                     wallet,
+                     asset: ListItem<AssetCode>,
+                     from: UserAddress,
+                     to: Recipient,
+                     amount: u64,
+                     fee: u64;
+                     wait: Option<bool>| {
+                        cli_send(io, wallet, asset, from, to, amount, fee, wait).await;
+                    }
+                ),
             ]
         }
     }
@@ -464,6 +550,11 @@ mod tests {
             Self::from_str(s).ok()
         }
     }
+    impl<'a> CLIInput<'a, MockCapeCli> for Recipient {
+        fn parse_for_wallet(_wallet: &mut Wallet<'a, MockCapeCli>, s: &str) -> Option<Self> {
+            Self::parse(s)
+        }
+    }
 
     pub struct MockCapeArgs<'a> {
         io: SharedIO,
@@ -777,4 +868,54 @@ mod tests {
         writeln!(receiver_input, "balance {}", wrapped_asset).unwrap();
         match_output(&mut receiver_output, &[format!("{} {}", receiver_addr, 0)]);
     }
+
+    #[cfg(feature = "slow-tests")]
+    #[async_std::test]
+    async fn test_cli_send_to_alias() {
+        let mut t = CapeTest::default();
+        let (ledger, key_streams) = create_cape_network(&mut t, &[2000, 2000]).await;
+
+        // Create wallets for the sender and the receiver, and get their addresses.
+        let (mut sender_input, mut sender_output) =
+            create_cape_wallet(ledger.clone(), key_streams[0].clone());
+        let (mut receiver_input, mut receiver_output) =
+            create_cape_wallet(ledger.clone(), key_streams[1].clone());
+        writeln!(sender_input, "gen_key spending scan_from=start wait=true").unwrap();
+        let sender_addr = match_output(&mut sender_output, &["(?P<addr>ADDR~.*)"]).get("addr");
+        writeln!(receiver_input, "gen_key spending scan_from=start wait=true").unwrap();
+        let receiver_addr = match_output(&mut receiver_output, &["(?P<addr>ADDR~.*)"]).get("addr");
+
+        // Bind an alias to the receiver's address.
+        ledger.lock().await.network().register_alias(
+            String::from("receiver@example.com"),
+            UserAddress::from_tagged_blob(&TaggedBase64::parse(&receiver_addr).unwrap())
+                .unwrap()
+                .0,
+        );
+
+        // Sending to an unknown alias fails.
+        writeln!(
+            sender_input,
+            "send 0 {} nobody@example.com 100 1",
+            sender_addr
+        )
+        .unwrap();
+        match_output(&mut sender_output, &["Assets were not transferred."]);
+
+        // Send to the alias.
+        writeln!(
+            sender_input,
+            "send 0 {} receiver@example.com 100 1",
+            sender_addr
+        )
+        .unwrap();
+        let txn = match_output(&mut sender_output, &["(?P<txn>TXN~.*)"]).get("txn");
+        await_transaction(
+            &txn,
+            (&mut sender_input.clone(), &mut sender_output.clone()),
+            &mut [(&mut receiver_input, &mut receiver_output)],
+        );
+        writeln!(receiver_input, "balance 0").unwrap();
+        match_output(&mut receiver_output, &[format!("{} 1100", receiver_addr)]);
+    }
 }
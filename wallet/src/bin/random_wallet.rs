@@ -105,12 +105,13 @@ async fn main() {
     };
 
     // Everyone creates own relayer and EQS, not sure it works without EQS
-    let (sender_key, relayer_url, contract_address, mock_eqs) =
+    let (sender_key, relayer_url, address_book_url, contract_address, mock_eqs) =
         create_test_network(&mut rng, &universal_param).await;
     println!("Ledger Created");
     let backend = CapeBackend::new(
         &universal_param,
         relayer_url.clone(),
+        address_book_url.clone(),
         contract_address,
         None,
         mock_eqs.clone(),
@@ -63,6 +63,7 @@ struct Args {
 struct NetworkInfo<'a> {
     sender_key: UserKeyPair,
     relayer_url: Url,
+    address_book_url: Url,
     contract_address: Address,
     mock_eqs: Arc<Mutex<MockCapeLedger<'a>>>,
 }
@@ -86,13 +87,15 @@ async fn create_backend_and_sender_wallet<'a>(
     let network = NetworkInfo {
         sender_key: nework_tuple.0,
         relayer_url: nework_tuple.1,
-        contract_address: nework_tuple.2,
-        mock_eqs: nework_tuple.3,
+        address_book_url: nework_tuple.2,
+        contract_address: nework_tuple.3,
+        mock_eqs: nework_tuple.4,
     };
 
     let backend = CapeBackend::new(
         universal_param,
         network.relayer_url.clone(),
+        network.address_book_url.clone(),
         network.contract_address,
         None,
         network.mock_eqs.clone(),
@@ -148,6 +151,7 @@ async fn create_wallet<'a>(
     let backend = CapeBackend::new(
         universal_param,
         network.relayer_url.clone(),
+        network.address_book_url.clone(),
         network.contract_address,
         None,
         network.mock_eqs.clone(),
@@ -234,6 +234,59 @@ mod tests {
         assert_ne!(mnemonic, server.get::<String>("getmnemonic").await.unwrap());
     }
 
+    #[async_std::test]
+    #[traced_test]
+    async fn test_openapi() {
+        let server = TestServer::new().await;
+
+        let document = server
+            .get::<serde_json::Value>("openapi.json")
+            .await
+            .unwrap();
+        assert_eq!(document["openapi"], "3.0.3");
+
+        // Every path of an implemented route in api.toml is documented, and every schema reference
+        // resolves.
+        let api = cape_wallet::disco::load_messages(&default_api_path());
+        for route in api["route"]
+            .as_table()
+            .unwrap()
+            .values()
+            .filter(|route| !openapi::is_unimplemented(route))
+        {
+            for path in route["PATH"].as_array().unwrap() {
+                let path = path
+                    .as_str()
+                    .unwrap()
+                    .split('/')
+                    .map(|segment| match segment.strip_prefix(':') {
+                        Some(param) => format!("/{{{}}}", param),
+                        None => format!("/{}", segment),
+                    })
+                    .collect::<String>();
+                let operation = &document["paths"][&path]["get"];
+                assert!(operation.is_object(), "{} is not documented", path);
+                let content = operation["responses"]["200"]["content"]
+                    .as_object()
+                    .unwrap();
+                let schema = &content.values().next().unwrap()["schema"];
+                // Nullable responses wrap the reference in allOf.
+                let reference = schema["$ref"]
+                    .as_str()
+                    .or_else(|| schema["allOf"][0]["$ref"].as_str())
+                    .unwrap();
+                let name = reference.strip_prefix("#/components/schemas/").unwrap();
+                assert!(document["components"]["schemas"][name].is_object());
+            }
+        }
+        assert_eq!(
+            document["paths"]["/getbalance/all"]["get"]["responses"]["200"]["content"]
+                ["application/json"]["schema"]["$ref"],
+            "#/components/schemas/BalanceInfo"
+        );
+        assert!(document["paths"]["/view/{asset}"].is_null());
+    }
+
     #[cfg(feature = "slow-tests")]
     #[async_std::test]
     #[traced_test]
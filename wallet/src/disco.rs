@@ -104,3 +104,14 @@ pub async fn compose_help(req: tide::Request<WebState>) -> Result<tide::Response
         .body(help)
         .build())
 }
+
+/// Serve the OpenAPI document describing the routes in `api.toml`.
+///
+/// Like [compose_help], this is recomputed on every request.
+pub async fn compose_openapi(req: tide::Request<WebState>) -> Result<tide::Response, tide::Error> {
+    let document = crate::openapi::openapi_document(&req.state().api);
+    Ok(tide::Response::builder(200)
+        .content_type(tide::http::mime::JSON)
+        .body(tide::Body::from_json(&document)?)
+        .build())
+}
@@ -15,10 +15,11 @@
 //! The instantiation of [seahorse] for CAPE is contained in the modules [wallet] and [backend]. As
 //! entrypoints to the wallet, we provide a CLI and a web server as separate executables, but much
 //! of the functionality of the web server is included in this crate as a library, in the modules
-//! [disco], [routes], and [web].
+//! [disco], [openapi], [routes], and [web].
 
 pub mod backend;
 pub mod disco;
+pub mod openapi;
 pub mod routes;
 pub mod ui;
 pub mod wallet;
@@ -75,6 +75,7 @@ pub struct MockCapeNetwork {
     events: MockEventSource<CapeLedger>,
     txns: HashMap<(u64, u64), CommittedTransaction>,
     address_map: HashMap<UserAddress, UserPubKey>,
+    aliases: HashMap<String, UserAddress>,
 }
 
 impl MockCapeNetwork {
@@ -92,6 +93,7 @@ impl MockCapeNetwork {
             events: MockEventSource::new(EventSource::QueryService),
             txns: Default::default(),
             address_map: Default::default(),
+            aliases: Default::default(),
         };
 
         // Broadcast receiver memos for the records which are included in the tree from the start,
@@ -269,6 +271,20 @@ impl MockCapeNetwork {
         Ok(())
     }
 
+    pub fn register_alias(&mut self, alias: String, address: UserAddress) {
+        self.aliases.insert(alias, address);
+    }
+
+    pub fn resolve_alias(&self, alias: &str) -> Result<UserAddress, CapeWalletError> {
+        Ok(self
+            .aliases
+            .get(alias)
+            .ok_or_else(|| CapeWalletError::Failed {
+                msg: format!("no address has the alias {}", alias),
+            })?
+            .clone())
+    }
+
     pub fn get_wrapped_asset(&self, asset: &AssetDefinition) -> Result<Erc20Code, CapeWalletError> {
         match self.contract.erc20_registrar.get(asset) {
             Some((erc20_code, _)) => Ok(erc20_code.clone()),
@@ -677,6 +693,22 @@ impl<'a, Meta: Serialize + DeserializeOwned + Send> CapeWalletBackend<'a>
             msg: String::from("eth_client is not implemented for MockCapeBackend"),
         })
     }
+
+    async fn resolve_alias(&self, alias: &str) -> Result<UserAddress, CapeWalletError> {
+        self.ledger.lock().await.network().resolve_alias(alias)
+    }
+
+    async fn get_public_keys(
+        &self,
+        addresses: &[UserAddress],
+    ) -> Result<Vec<Option<UserPubKey>>, CapeWalletError> {
+        let mut ledger = self.ledger.lock().await;
+        let network = ledger.network();
+        Ok(addresses
+            .iter()
+            .map(|address| network.get_public_key(address).ok())
+            .collect())
+    }
 }
 
 fn cape_to_wallet_err(err: CapeValidationError) -> WalletError<CapeLedger> {
@@ -0,0 +1,146 @@
+// Copyright (c) 2022 Espresso Systems (espressosys.com)
+// This file is part of the Configurable Asset Privacy for Ethereum (CAPE) library.
+
+// This program is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+// You should have received a copy of the GNU General Public License along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! OpenAPI 3 description of the wallet web API.
+//!
+//! The document is built from `api.toml` by the [openapi](::openapi) crate. The schemas here
+//! describe the JSON serialization of the types returned by the endpoints in [crate::routes] and
+//! [crate::ui], so they must be kept in sync with those types.
+
+use ::openapi::{array, object, opaque, schema_ref, tagged, uint, variant};
+use serde_json::{json, Map, Value};
+
+/// Builds the OpenAPI document for the routes in `api`.
+pub fn openapi_document(api: &toml::Value) -> Value {
+    ::openapi::openapi_document(api, response_schemas())
+}
+
+/// Schemas of the response types, by the names used for `RESPONSE` in api.toml.
+pub fn response_schemas() -> Map<String, Value> {
+    let mut schemas = Map::new();
+    let mut add = |name: &str, schema: Value| {
+        schemas.insert(name.to_string(), schema);
+    };
+
+    add(
+        "Null",
+        json!({ "nullable": true, "description": "Always null" }),
+    );
+    add("String", json!({ "type": "string" }));
+    add(
+        "Path",
+        json!({ "type": "string", "description": "A file system path" }),
+    );
+    add(
+        "Error",
+        opaque("A CapeAPIError, serialized as an object with the error kind as its only key."),
+    );
+
+    add("UserAddress", tagged("ADDR"));
+    add("UserAddresses", array(schema_ref("UserAddress")));
+    add("UserPubKey", tagged("USERPUBKEY"));
+    add("AuditorPubKey", tagged("AUDPUBKEY"));
+    add("FreezerPubKey", tagged("FREEZEPUBKEY"));
+    add("AssetCode", tagged("ASSET_CODE"));
+    add("AssetDefinition", tagged("ASSET_DEF"));
+    add(
+        "AssetInfo",
+        opaque("A known asset: its definition, and the mint information if this wallet created it"),
+    );
+    add(
+        "RecordInfo",
+        opaque("A record owned by this wallet, with its opening, uid and status"),
+    );
+    add("RecordInfos", array(schema_ref("RecordInfo")));
+    add(
+        "TransactionReceipt",
+        opaque("The receipt of a submitted transaction, used to track its status"),
+    );
+
+    add(
+        "PubKey",
+        json!({
+            "oneOf": [
+                variant("Sending", schema_ref("UserPubKey")),
+                variant("Viewing", schema_ref("AuditorPubKey")),
+                variant("Freezing", schema_ref("FreezerPubKey")),
+            ]
+        }),
+    );
+    let asset_balances = json!({
+        "type": "object",
+        "description": "Balances by asset code",
+        "additionalProperties": uint(),
+    });
+    add(
+        "BalanceInfo",
+        json!({
+            "oneOf": [
+                variant("Balance", uint()),
+                variant("AccountBalances", asset_balances.clone()),
+                variant("AllBalances", json!({
+                    "type": "object",
+                    "description": "Balances of each address, by asset code",
+                    "additionalProperties": asset_balances,
+                })),
+            ]
+        }),
+    );
+    add(
+        "WalletSummary",
+        object(vec![
+            ("addresses", array(schema_ref("UserAddress"))),
+            ("sending_keys", array(schema_ref("UserPubKey"))),
+            ("viewing_keys", array(schema_ref("AuditorPubKey"))),
+            ("freezing_keys", array(schema_ref("FreezerPubKey"))),
+            ("assets", array(schema_ref("AssetInfo"))),
+        ]),
+    );
+    add(
+        "Record",
+        object(vec![
+            ("address", schema_ref("UserAddress")),
+            ("asset", schema_ref("AssetCode")),
+            ("amount", uint()),
+            ("uid", uint()),
+        ]),
+    );
+    add(
+        "Account",
+        object(vec![
+            ("records", array(schema_ref("Record"))),
+            (
+                "assets",
+                json!({
+                    "type": "object",
+                    "description": "Assets of the records, by asset code",
+                    "additionalProperties": schema_ref("AssetInfo"),
+                }),
+            ),
+        ]),
+    );
+
+    schemas
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::disco::load_messages;
+    use std::path::Path;
+
+    #[test]
+    fn test_response_schemas() {
+        let api = load_messages(&Path::new(env!("CARGO_MANIFEST_DIR")).join("api/api.toml"));
+        // Every RESPONSE and REQUEST in api.toml has a schema.
+        if let Err(errors) = ::openapi::check_schemas(&api, &response_schemas()) {
+            panic!("{}", errors.join("\n"));
+        }
+        let document = openapi_document(&api);
+        assert!(document["components"]["schemas"]["Error"].is_object());
+    }
+}
@@ -9,6 +9,7 @@
 
 use crate::{
     mocks::{MockCapeBackend, MockCapeNetwork},
+    openapi::response_schemas,
     ui::*,
     wallet::{CapeWalletError, CapeWalletExt},
     web::WebState,
@@ -273,6 +274,12 @@ pub fn check_api(api: toml::Value) -> bool {
             missing_definition = true;
         }
     }
+    if let Err(errors) = ::openapi::check_schemas(&api, &response_schemas()) {
+        for error in errors {
+            println!("{}", error);
+        }
+        missing_definition = true;
+    }
     if missing_definition {
         panic!("api.toml is inconsistent with enum ApiRoutKey");
     }
@@ -831,11 +838,7 @@ pub async fn send(
     let wallet = require_wallet(wallet)?;
 
     let src = bindings.get(":sender").unwrap().value.to::<UserAddress>()?;
-    let dst = bindings
-        .get(":recipient")
-        .unwrap()
-        .value
-        .to::<UserAddress>()?;
+    let dst = recipient(wallet, &bindings.get(":recipient").unwrap().value).await?;
     let asset = bindings.get(":asset").unwrap().value.to::<AssetCode>()?;
     let amount = bindings.get(":amount").unwrap().value.as_u64()?;
     let fee = bindings.get(":fee").unwrap().value.as_u64()?;
@@ -846,6 +849,21 @@ pub async fn send(
         .map_err(wallet_error)
 }
 
+/// Parse a recipient, which is either an address or an alias registered in the address book.
+///
+/// Aliases cannot contain `~`, so anything which parses as tagged base 64 is an address.
+async fn recipient(wallet: &Wallet, value: &UrlSegmentValue) -> Result<UserAddress, tide::Error> {
+    let recipient = value.as_string()?;
+    match UrlSegmentValue::parse(UrlSegmentType::TaggedBase64, &recipient) {
+        Some(address) => address.to::<UserAddress>(),
+        None => Ok(wallet
+            .resolve_alias(&recipient)
+            .await
+            .map_err(wallet_error)?
+            .into()),
+    }
+}
+
 pub async fn get_records(wallet: &mut Option<Wallet>) -> Result<Vec<RecordInfo>, tide::Error> {
     let wallet = require_wallet(wallet)?;
     Ok(wallet.records().await.collect::<Vec<_>>())
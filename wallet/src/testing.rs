@@ -13,7 +13,9 @@ use crate::mocks::*;
 use crate::wallet::CapeWalletExt;
 use crate::CapeWallet;
 use crate::CapeWalletError;
+use address_book::configuration::AddressBookOptions;
 use address_book::init_web_server;
+use address_book::persistence::AddressBookStore;
 use address_book::wait_for_server;
 use async_std::sync::{Arc, Mutex};
 use cap_rust_sandbox::deploy::EthMiddleware;
@@ -39,8 +41,9 @@ use reef::Ledger;
 use relayer::testing::start_minimal_relayer_for_test;
 use seahorse::testing::await_transaction;
 use seahorse::txn_builder::{TransactionReceipt, TransactionStatus};
+use structopt::StructOpt;
 use surf::Url;
-use tide::log::LevelFilter;
+use tempdir::TempDir;
 
 lazy_static! {
     static ref PORT: Arc<Mutex<u64>> = {
@@ -60,11 +63,32 @@ pub async fn port() -> u64 {
 pub async fn create_test_network<'a>(
     rng: &mut ChaChaRng,
     universal_param: &'a UniversalParam,
-) -> (UserKeyPair, Url, Address, Arc<Mutex<MockCapeLedger<'a>>>) {
-    init_web_server(LevelFilter::Error)
-        .await
-        .expect("Failed to run server.");
-    wait_for_server().await;
+) -> (
+    UserKeyPair,
+    Url,
+    Url,
+    Address,
+    Arc<Mutex<MockCapeLedger<'a>>>,
+) {
+    // Start an address book. The store must outlive the server, which runs until the test
+    // process exits.
+    let store_path = TempDir::new("address_book").unwrap().into_path();
+    let address_book_port = port().await.to_string();
+    let address_book_opt = AddressBookOptions::from_iter([
+        "address_book",
+        "--port",
+        &address_book_port,
+        "--log_level",
+        "error",
+    ]);
+    init_web_server(
+        &address_book_opt,
+        AddressBookStore::new(&store_path).unwrap(),
+    )
+    .await
+    .expect("Failed to run server.");
+    let address_book_url = address_book_opt.base_url();
+    wait_for_server(&address_book_url).await;
 
     // Set up a network that includes a minimal relayer, connected to a real Ethereum
     // blockchain, as well as a mock EQS which will track the blockchain in parallel, since we
@@ -126,7 +150,13 @@ pub async fn create_test_network<'a>(
     // either.
     let mock_eqs = Arc::new(Mutex::new(mock_eqs));
 
-    (sender_key, relayer_url, contract.address(), mock_eqs)
+    (
+        sender_key,
+        relayer_url,
+        address_book_url,
+        contract.address(),
+        mock_eqs,
+    )
 }
 
 #[derive(Debug)]
@@ -11,7 +11,7 @@ use async_std::sync::Arc;
 use async_trait::async_trait;
 use cap_rust_sandbox::{deploy::EthMiddleware, ledger::*, model::*};
 use jf_cap::{
-    keys::UserAddress,
+    keys::{UserAddress, UserPubKey},
     structs::{AssetCode, AssetDefinition, AssetPolicy, FreezeFlag, RecordOpening},
 };
 use seahorse::{
@@ -61,6 +61,18 @@ pub trait CapeWalletBackend<'a>: WalletBackend<'a, CapeLedger> {
 
     /// Get the underlying Ethereum connection.
     fn eth_client(&self) -> Result<Arc<EthMiddleware>, CapeWalletError>;
+
+    /// Look up the address which an alias is bound to in the address book.
+    async fn resolve_alias(&self, alias: &str) -> Result<UserAddress, CapeWalletError>;
+
+    /// Look up the public keys of several addresses at once.
+    ///
+    /// The result is in the same order as `addresses`, with `None` for addresses which have no
+    /// public key.
+    async fn get_public_keys(
+        &self,
+        addresses: &[UserAddress],
+    ) -> Result<Vec<Option<UserPubKey>>, CapeWalletError>;
 }
 
 pub type CapeWallet<'a, Backend> = Wallet<'a, Backend, CapeLedger>;
@@ -128,6 +140,16 @@ pub trait CapeWalletExt<'a, Backend: CapeWalletBackend<'a> + Sync + 'a> {
 
     /// Get an address owned by the underlying Ethereum wallet.
     async fn eth_address(&self) -> Result<EthereumAddr, CapeWalletError>;
+
+    /// Look up the address which an alias is bound to in the address book.
+    async fn resolve_alias(&self, alias: &str) -> Result<UserAddress, CapeWalletError>;
+
+    /// Look up the public keys of several addresses, such as the receivers of a transfer or a list
+    /// of contacts, without a request per address.
+    async fn get_public_keys(
+        &self,
+        addresses: &[UserAddress],
+    ) -> Result<Vec<Option<UserPubKey>>, CapeWalletError>;
 }
 
 #[async_trait]
@@ -251,4 +273,15 @@ impl<'a, Backend: CapeWalletBackend<'a> + Sync + 'a> CapeWalletExt<'a, Backend>
     async fn eth_address(&self) -> Result<EthereumAddr, CapeWalletError> {
         Ok(self.eth_client().await?.address().into())
     }
+
+    async fn resolve_alias(&self, alias: &str) -> Result<UserAddress, CapeWalletError> {
+        self.lock().await.backend().resolve_alias(alias).await
+    }
+
+    async fn get_public_keys(
+        &self,
+        addresses: &[UserAddress],
+    ) -> Result<Vec<Option<UserPubKey>>, CapeWalletError> {
+        self.lock().await.backend().get_public_keys(addresses).await
+    }
 }
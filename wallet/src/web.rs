@@ -332,6 +332,9 @@ pub fn init_server(
     // Define the routes handled by the web server.
     web_server.at("/public").serve_dir(web_path)?;
     web_server.at("/").get(crate::disco::compose_help);
+    web_server
+        .at("/openapi.json")
+        .get(crate::disco::compose_openapi);
 
     // Add routes from a configuration file.
     if let Some(api_map) = api["route"].as_table() {